@@ -0,0 +1,139 @@
+use crate::config::Severity;
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{rename, File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    sync::{mpsc::Receiver, oneshot},
+    time::interval,
+};
+use toml::Value;
+
+/// The audit log is rotated to `<path>.1` once it grows past this size.
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A record of the actions a monitor ran.
+#[derive(Clone, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub monitor: String,
+    pub severity: Severity,
+    pub variables: HashMap<String, Value>,
+    pub actions: Vec<&'static str>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        monitor: String,
+        severity: Severity,
+        variables: HashMap<String, Value>,
+        actions: Vec<&'static str>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            timestamp,
+            monitor,
+            severity,
+            variables,
+            actions,
+        }
+    }
+}
+
+/// Appends an `AuditEntry` as a line of JSON for every action Ramon runs.
+pub struct AuditLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    entry_rx: Receiver<AuditEntry>,
+}
+
+impl AuditLog {
+    pub async fn new(path: PathBuf, entry_rx: Receiver<AuditEntry>) -> Result<Self> {
+        let (writer, size) = open(&path).await?;
+        Ok(Self {
+            path,
+            writer,
+            size,
+            entry_rx,
+        })
+    }
+
+    /// Writes entries until every sender is dropped or `shutdown_rx` fires, then flushes.
+    pub async fn start(mut self, mut shutdown_rx: oneshot::Receiver<()>) {
+        let mut flush_interval = interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => {
+                    // Write entries that were sent before shutdown.
+                    while let Ok(entry) = self.entry_rx.try_recv() {
+                        if let Err(err) = self.write(entry).await {
+                            error!("Failed to write to audit log {:?}: {err}", self.path);
+                        }
+                    }
+                    break;
+                }
+                entry = self.entry_rx.recv() => match entry {
+                    Some(entry) => {
+                        if let Err(err) = self.write(entry).await {
+                            error!("Failed to write to audit log {:?}: {err}", self.path);
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush_interval.tick() => {
+                    if let Err(err) = self.writer.flush().await {
+                        error!("Failed to flush audit log {:?}: {err}", self.path);
+                    }
+                }
+            }
+        }
+        if let Err(err) = self.writer.flush().await {
+            error!("Failed to flush audit log {:?}: {err}", self.path);
+        }
+    }
+
+    async fn write(&mut self, entry: AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.size += line.len() as u64;
+
+        if self.size > MAX_SIZE {
+            self.rotate().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        info!("Rotating audit log {:?}", self.path);
+        self.writer.flush().await?;
+        let mut rotated_path = self.path.clone().into_os_string();
+        rotated_path.push(".1");
+        rename(&self.path, rotated_path).await?;
+        (self.writer, self.size) = open(&self.path).await?;
+        Ok(())
+    }
+}
+
+async fn open(path: &PathBuf) -> Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|err| anyhow!("Failed to open audit log {path:?}: {err}"))?;
+    let size = file.metadata().await?.len();
+    Ok((BufWriter::new(file), size))
+}
@@ -0,0 +1,176 @@
+use crate::config::{AfterExec, Config, Exec, MonitorConfig, SyslogAddress};
+use std::{env, ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+/// Where `unique`, `changed`, and `fingerprint` state is kept.
+const STATE_DIR: &str = "/var/cache/ramon";
+
+/// The outcome of each check, printed as it's made.
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+}
+
+impl Report {
+    fn check(&mut self, scope: &str, what: &str, res: Result<(), String>) {
+        match res {
+            Ok(()) => {
+                self.passed += 1;
+                println!("PASS  {scope}: {what}");
+            }
+            Err(err) => {
+                self.failed += 1;
+                println!("FAIL  {scope}: {what}: {err}");
+            }
+        }
+    }
+}
+
+/// Checks that Ramon can read, write, and run everything the config refers to, printing a line
+/// for each check. Returns the number of checks that failed.
+pub fn run(config: &Config) -> usize {
+    let mut report = Report::default();
+
+    let needs_state = config.monitors.iter().any(|monitor| {
+        monitor.unique.is_some() || monitor.changed.is_some() || monitor.fingerprint.is_some()
+    });
+    if needs_state {
+        let what = format!("{STATE_DIR} is writable");
+        report.check("state", &what, writable_dir(Path::new(STATE_DIR)));
+    }
+    if let Some(path) = &config.audit_log {
+        let what = format!("audit log {path:?} is writable");
+        report.check("audit_log", &what, writable_file(path));
+    }
+    if let Some(path) = &config.control_socket {
+        let what = format!("control socket {path:?} can be created");
+        report.check("control_socket", &what, writable_file(path));
+    }
+    if let Some(SyslogAddress::Unix(path)) = config.syslog.as_ref().map(|syslog| &syslog.address) {
+        let what = format!("syslog socket {path:?} is writable");
+        report.check("syslog", &what, access(path, libc::W_OK));
+    }
+    let mut names = config.notifications.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        if let Some(dir) = &config.notifications[name].spool_dir {
+            let what = format!("spool_dir {dir:?} is writable");
+            report.check(&format!("notify.{name}"), &what, writable_dir(dir));
+        }
+    }
+
+    for monitor in &config.monitors {
+        check_monitor(&mut report, monitor);
+    }
+
+    println!("{} checks passed, {} failed.", report.passed, report.failed);
+    report.failed
+}
+
+fn check_monitor(report: &mut Report, monitor: &MonitorConfig) {
+    let scope = format!("monitor.{}", monitor.name);
+    if let Some(log) = &monitor.log {
+        let what = format!("log {:?} is readable", log.path);
+        report.check(&scope, &what, access(&log.path, libc::R_OK));
+    }
+    for pattern in &monitor.watch {
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths.filter_map(Result::ok).collect::<Vec<_>>(),
+            Err(err) => {
+                report.check(&scope, &format!("watch {pattern:?}"), Err(err.to_string()));
+                continue;
+            }
+        };
+        if paths.is_empty() {
+            let what = format!("watch {pattern:?} matches a file");
+            report.check(&scope, &what, Err("No files match".to_owned()));
+        }
+        for path in paths {
+            let what = format!("watch {path:?} is readable");
+            report.check(&scope, &what, access(&path, libc::R_OK));
+        }
+    }
+    if let Some(dir) = &monitor.watch_dir {
+        let (what, mode) = match monitor.after_exec {
+            AfterExec::Keep => ("readable", libc::R_OK | libc::X_OK),
+            // Processed files are removed from the directory.
+            _ => ("writable", libc::R_OK | libc::W_OK | libc::X_OK),
+        };
+        let what = format!("watch_dir {dir:?} is {what}");
+        report.check(&scope, &what, access(dir, mode));
+    }
+    if let AfterExec::Move(dir) = &monitor.after_exec {
+        let what = format!("move_to {dir:?} is writable");
+        report.check(&scope, &what, writable_dir(dir));
+    }
+    if monitor.service.is_some() {
+        report.check(&scope, "journalctl is installed", executable("journalctl"));
+    }
+    if monitor.service.is_some() || monitor.service_state.is_some() {
+        report.check(&scope, "systemctl is installed", executable("systemctl"));
+    }
+    if monitor.ssh.is_some() {
+        report.check(&scope, "ssh is installed", executable("ssh"));
+    }
+    if let Some(exec) = &monitor.exec {
+        let binary = match exec {
+            Exec::Shell(_) => "sh",
+            Exec::Spawn(args) => &args[0],
+        };
+        let what = format!("exec {binary:?} is executable");
+        report.check(&scope, &what, executable(binary));
+    }
+    if let Some(path) = &monitor.fifo {
+        let what = format!("fifo {path:?} is writable");
+        report.check(&scope, &what, writable_file(path));
+    }
+}
+
+/// Checks that the directory can be written to, or created if it doesn't exist yet.
+fn writable_dir(dir: &Path) -> Result<(), String> {
+    if dir.exists() {
+        return access(dir, libc::W_OK | libc::X_OK);
+    }
+    match dir.parent() {
+        Some(parent) => access(parent, libc::W_OK | libc::X_OK)
+            .map_err(|err| format!("Doesn't exist, and it can't be created in {parent:?}: {err}")),
+        None => Err("Doesn't exist".to_owned()),
+    }
+}
+
+/// Checks that the file can be written to, or created if it doesn't exist yet.
+fn writable_file(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return access(path, libc::W_OK);
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    access(parent, libc::W_OK | libc::X_OK)
+        .map_err(|err| format!("Doesn't exist, and it can't be created in {parent:?}: {err}"))
+}
+
+/// Checks that the binary is executable, looking it up in `PATH` if it isn't a path.
+fn executable(binary: &str) -> Result<(), String> {
+    if binary.contains('/') {
+        return access(Path::new(binary), libc::X_OK);
+    }
+    let path = env::var_os("PATH").unwrap_or_default();
+    let found = env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file());
+    match found {
+        Some(path) => access(&path, libc::X_OK),
+        None => Err("Not found in PATH".to_owned()),
+    }
+}
+
+/// Checks the permissions with `access(2)`, which also reports read-only filesystems.
+fn access(path: &Path, mode: libc::c_int) -> Result<(), String> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|err| err.to_string())?;
+    if unsafe { libc::access(c_path.as_ptr(), mode) } == 0 {
+        return Ok(());
+    }
+    Err(io::Error::last_os_error().to_string())
+}
@@ -0,0 +1,93 @@
+use crate::{
+    config::CircuitBreakerConfig,
+    monitor::{Event, Stats},
+};
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc::Sender, time::sleep};
+
+/// How long to wait before restarting a source that failed without tripping the breaker.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    errors: VecDeque<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            errors: VecDeque::with_capacity(config.errors),
+        }
+    }
+
+    /// Records an error. Returns true if the breaker tripped, in which case the error count is
+    /// reset.
+    pub fn record_error(&mut self) -> bool {
+        let now = Instant::now();
+        while let Some(&time) = self.errors.front() {
+            if now.duration_since(time) <= self.config.within {
+                break;
+            }
+            self.errors.pop_front();
+        }
+        self.errors.push_back(now);
+        if self.errors.len() < self.config.errors {
+            return false;
+        }
+        self.errors.clear();
+        true
+    }
+}
+
+/// Runs a source until it fails. If the monitor has a circuit breaker, the source is restarted
+/// after each failure, and paused when the breaker trips. The source isn't restarted once nothing
+/// receives its events, e.g. because the monitor stopped for `stop_on`.
+pub async fn supervise<F, Fut>(
+    name: String,
+    source: &str,
+    config: Option<CircuitBreakerConfig>,
+    stats: Arc<Stats>,
+    event_tx: Sender<Event>,
+    mut start: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut breaker = config.map(CircuitBreaker::new);
+    loop {
+        let err = match start().await {
+            Ok(()) => anyhow!("Exited early."),
+            Err(err) => err,
+        };
+        if event_tx.is_closed() {
+            return;
+        }
+        error!("[{name}] {source}: {err}");
+        stats.source_failed(source, err);
+
+        let Some(breaker) = &mut breaker else {
+            return;
+        };
+        if breaker.record_error() {
+            let pause = breaker.config.pause;
+            warn!(
+                "[{name}] Circuit breaker tripped after {} {source} errors. Pausing for {}s.",
+                breaker.config.errors,
+                pause.as_secs(),
+            );
+            stats.breaker_trips.fetch_add(1, Ordering::Relaxed);
+            sleep(pause).await;
+            info!("[{name}] Restarting {source}.");
+        } else {
+            sleep(RESTART_DELAY).await;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{config::ExecPolicy, control};
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: ramon [OPTIONS]
+       ramon test-regex --pattern <REGEX> [--input <LINE>]
+       ramon ctl [--config <PATH>] [--shard <GROUP>] <COMMAND> [ARG]
+
+Commands:
+  test-regex                 Print the captures of a pattern for the input, or each line of stdin
+  ctl                        Send a command to the running Ramon's control_socket
+
+Options:
+  --config <PATH>            Read the config from PATH instead of the one built into the binary
+  --watch-config             Restart with the new config whenever the config file changes
+  --daemonize                Run in the background
+  --pidfile <PATH>           Where to write the PID when daemonized [default: /run/ramon.pid]
+  --log-file <PATH>          Where to write logs when daemonized [default: /var/log/ramon.log]
+  --allow-exec <BINARY>      Only allow exec to run this binary (repeatable); forbids shell commands
+  --forbid-shell-exec        Forbid exec from running shell commands
+  --test-notify              Send a test notification with every notify config and exit
+  --shard <GROUP>            Only run the monitors whose group is GROUP
+  --emit-events              Print a line of JSON to stdout for every action that runs
+  --config-test-against <FILE>
+                             Print which monitors would fire for each line of the file and exit
+  -V, --version              Print version information
+  --print-config-schema      Print a JSON Schema of the accepted config keys
+  --trace-config             Print each setting with the file and line it came from and exit
+  --generate-systemd-unit    Print a systemd unit that runs Ramon with these options and exit
+  --check-permissions        Check that Ramon can access every file and binary in the config and exit
+  -h, --help                 Print this message";
+
+pub enum Command {
+    TestRegex {
+        pattern: String,
+        /// Lines are read from stdin if this isn't set.
+        input: Option<String>,
+    },
+    /// A command for the control socket, such as `pause my-monitor`.
+    Ctl { command: String },
+}
+
+pub struct Args {
+    pub command: Option<Command>,
+    /// The embedded config is used if this isn't set.
+    pub config: Option<PathBuf>,
+    pub watch_config: bool,
+    pub help: bool,
+    pub version: bool,
+    pub print_config_schema: bool,
+    pub trace_config: bool,
+    pub generate_systemd_unit: bool,
+    pub check_permissions: bool,
+    pub daemonize: bool,
+    pub pidfile: PathBuf,
+    pub log_file: PathBuf,
+    pub test_notify: bool,
+    pub emit_events: bool,
+    pub shard: Option<String>,
+    pub config_test_against: Option<PathBuf>,
+    pub exec_policy: ExecPolicy,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            command: None,
+            config: None,
+            watch_config: false,
+            help: false,
+            version: false,
+            print_config_schema: false,
+            trace_config: false,
+            generate_systemd_unit: false,
+            check_permissions: false,
+            daemonize: false,
+            pidfile: "/run/ramon.pid".into(),
+            log_file: "/var/log/ramon.log".into(),
+            test_notify: false,
+            emit_events: false,
+            shard: None,
+            config_test_against: None,
+            exec_policy: ExecPolicy::default(),
+        }
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some_and(|arg| arg == "test-regex") {
+        args.next();
+        parsed.command = Some(parse_test_regex(args)?);
+        return Ok(parsed);
+    }
+    if args.peek().is_some_and(|arg| arg == "ctl") {
+        args.next();
+        while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+            match arg.as_str() {
+                "--config" => parsed.config = Some(value(&arg, args.next())?.into()),
+                "--shard" => parsed.shard = Some(value(&arg, args.next())?),
+                _ => bail!("Unknown argument `{arg}`\n\n{USAGE}"),
+            }
+        }
+        let command = args.collect::<Vec<_>>().join(" ");
+        if command.is_empty() {
+            bail!("`ctl` requires a command\n\n{}", control::USAGE);
+        }
+        parsed.command = Some(Command::Ctl { command });
+        return Ok(parsed);
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => parsed.help = true,
+            "-V" | "--version" => parsed.version = true,
+            "--print-config-schema" => parsed.print_config_schema = true,
+            "--trace-config" => parsed.trace_config = true,
+            "--generate-systemd-unit" => parsed.generate_systemd_unit = true,
+            "--check-permissions" => parsed.check_permissions = true,
+            "--daemonize" => parsed.daemonize = true,
+            "--config" => parsed.config = Some(value(&arg, args.next())?.into()),
+            "--watch-config" => parsed.watch_config = true,
+            "--pidfile" => parsed.pidfile = value(&arg, args.next())?.into(),
+            "--log-file" => parsed.log_file = value(&arg, args.next())?.into(),
+            "--test-notify" => parsed.test_notify = true,
+            "--emit-events" => parsed.emit_events = true,
+            "--shard" => parsed.shard = Some(value(&arg, args.next())?),
+            "--config-test-against" => {
+                parsed.config_test_against = Some(value(&arg, args.next())?.into())
+            }
+            "--allow-exec" => parsed.exec_policy.allowed.push(value(&arg, args.next())?),
+            "--forbid-shell-exec" => parsed.exec_policy.forbid_shell = true,
+            _ => bail!("Unknown argument `{arg}`\n\n{USAGE}"),
+        }
+    }
+    if parsed.watch_config && parsed.config.is_none() {
+        bail!("Argument `--watch-config` requires `--config`\n\n{USAGE}");
+    }
+    Ok(parsed)
+}
+
+fn parse_test_regex(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut pattern = None;
+    let mut input = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pattern" => pattern = Some(value(&arg, args.next())?),
+            "--input" => input = Some(value(&arg, args.next())?),
+            _ => bail!("Unknown argument `{arg}`\n\n{USAGE}"),
+        }
+    }
+    match pattern {
+        Some(pattern) => Ok(Command::TestRegex { pattern, input }),
+        None => bail!("Argument `--pattern` is required\n\n{USAGE}"),
+    }
+}
+
+fn value(arg: &str, value: Option<String>) -> Result<String> {
+    match value {
+        Some(value) => Ok(value),
+        None => bail!("Argument `{arg}` requires a value\n\n{USAGE}"),
+    }
+}
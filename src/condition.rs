@@ -0,0 +1,277 @@
+use crate::config::value_to_string;
+use anyhow::{bail, Result};
+use std::{cmp::Ordering, collections::HashMap, iter::Peekable, str::Chars};
+use toml::Value;
+
+/// A parsed `if` expression, such as `ram > 90 && swap > 50`.
+pub enum Condition {
+    Compare(Operand, Operator, Operand),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+pub enum Operand {
+    /// A variable, written as `name` or `{{name}}`.
+    Variable(String),
+    /// A number or a quoted string.
+    Literal(String),
+}
+
+#[derive(Clone, Copy)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+enum Token {
+    Operand(Operand),
+    Operator(Operator),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl Condition {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let mut tokens = tokenize(expr)?.into_iter().peekable();
+        let condition = parse_or(&mut tokens)?;
+        if tokens.next().is_some() {
+            bail!("Unexpected token after the end of the expression.");
+        }
+        Ok(condition)
+    }
+
+    /// Evaluates the condition. If both sides of a comparison are finite numbers, they're compared
+    /// numerically; otherwise they're compared as strings. Comparisons with undefined variables
+    /// are false.
+    pub fn evaluate(&self, variables: &HashMap<String, Value>) -> bool {
+        match self {
+            Condition::Compare(lhs, operator, rhs) => {
+                let (Some(lhs), Some(rhs)) = (lhs.resolve(variables), rhs.resolve(variables))
+                else {
+                    return false;
+                };
+                let ordering = match (parse_number(&lhs), parse_number(&rhs)) {
+                    // Finite numbers are always comparable, and `-0` equals `0`.
+                    (Some(lhs), Some(rhs)) => lhs.partial_cmp(&rhs).unwrap(),
+                    _ => lhs.cmp(&rhs),
+                };
+                match operator {
+                    Operator::Eq => ordering == Ordering::Equal,
+                    Operator::Ne => ordering != Ordering::Equal,
+                    Operator::Lt => ordering == Ordering::Less,
+                    Operator::Le => ordering != Ordering::Greater,
+                    Operator::Gt => ordering == Ordering::Greater,
+                    Operator::Ge => ordering != Ordering::Less,
+                }
+            }
+            Condition::And(lhs, rhs) => lhs.evaluate(variables) && rhs.evaluate(variables),
+            Condition::Or(lhs, rhs) => lhs.evaluate(variables) || rhs.evaluate(variables),
+        }
+    }
+}
+
+/// Parses a finite number. `str::parse` also accepts words such as `nan` and `inf`, which are
+/// compared as strings, so that e.g. a captured `NaN` equals itself.
+fn parse_number(string: &str) -> Option<f64> {
+    string
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+impl Operand {
+    fn resolve(&self, variables: &HashMap<String, Value>) -> Option<String> {
+        match self {
+            Operand::Variable(name) => variables.get(name).cloned().map(value_to_string),
+            Operand::Literal(literal) => Some(literal.clone()),
+        }
+    }
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+fn parse_or(tokens: &mut Tokens) -> Result<Condition> {
+    let mut condition = parse_and(tokens)?;
+    while let Some(Token::Or) = tokens.peek() {
+        tokens.next();
+        condition = Condition::Or(Box::new(condition), Box::new(parse_and(tokens)?));
+    }
+    Ok(condition)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Condition> {
+    let mut condition = parse_comparison(tokens)?;
+    while let Some(Token::And) = tokens.peek() {
+        tokens.next();
+        condition = Condition::And(Box::new(condition), Box::new(parse_comparison(tokens)?));
+    }
+    Ok(condition)
+}
+
+fn parse_comparison(tokens: &mut Tokens) -> Result<Condition> {
+    match tokens.next() {
+        Some(Token::Open) => {
+            let condition = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(condition),
+                _ => bail!("Expected `)`."),
+            }
+        }
+        Some(Token::Operand(lhs)) => {
+            let operator = match tokens.next() {
+                Some(Token::Operator(operator)) => operator,
+                _ => bail!("Expected a comparison operator."),
+            };
+            let rhs = match tokens.next() {
+                Some(Token::Operand(rhs)) => rhs,
+                _ => bail!("Expected a variable or value after the comparison operator."),
+            };
+            Ok(Condition::Compare(lhs, operator, rhs))
+        }
+        _ => bail!("Expected a comparison."),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::Open
+            }
+            ')' => {
+                chars.next();
+                Token::Close
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    bail!("Expected `{c}{c}`.");
+                }
+                match c {
+                    '&' => Token::And,
+                    _ => Token::Or,
+                }
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let equals = chars.next_if_eq(&'=').is_some();
+                Token::Operator(match (c, equals) {
+                    ('=', true) => Operator::Eq,
+                    ('!', true) => Operator::Ne,
+                    ('<', false) => Operator::Lt,
+                    ('<', true) => Operator::Le,
+                    ('>', false) => Operator::Gt,
+                    ('>', true) => Operator::Ge,
+                    _ => bail!("Unknown operator `{c}`."),
+                })
+            }
+            '"' | '\'' => {
+                chars.next();
+                let literal = take_until(&mut chars, c)?;
+                Token::Operand(Operand::Literal(literal))
+            }
+            '{' => {
+                chars.next();
+                if chars.next() != Some('{') {
+                    bail!("Expected `{{{{`.");
+                }
+                let name = take_until(&mut chars, '}')?;
+                if chars.next() != Some('}') {
+                    bail!("Expected `}}}}`.");
+                }
+                Token::Operand(Operand::Variable(name.trim().to_owned()))
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let mut literal = String::new();
+                if let Some(minus) = chars.next_if_eq(&'-') {
+                    literal.push(minus);
+                }
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.') {
+                    literal.push(c);
+                }
+                Token::Operand(Operand::Literal(literal))
+            }
+            _ if c.is_alphanumeric() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                Token::Operand(Operand::Variable(name))
+            }
+            _ => bail!("Unexpected character `{c}`."),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Consumes characters up to and including `end`, returning the characters before it.
+fn take_until(chars: &mut Peekable<Chars<'_>>, end: char) -> Result<String> {
+    let mut string = String::new();
+    for c in chars.by_ref() {
+        if c == end {
+            return Ok(string);
+        }
+        string.push(c);
+    }
+    bail!("Expected `{end}`.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(expr: &str, variables: &[(&str, &str)]) -> bool {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+            .collect();
+        Condition::parse(expr).unwrap().evaluate(&variables)
+    }
+
+    #[test]
+    fn numbers_compare_numerically() {
+        // Compared as strings, "9" > "10".
+        assert!(evaluate("a < b", &[("a", "9"), ("b", "10")]));
+        assert!(!evaluate("a > b", &[("a", "9"), ("b", "10")]));
+        assert!(evaluate("status >= 200", &[("status", "404")]));
+        assert!(evaluate("load > 1.5", &[("load", " 2.25 ")]));
+        assert!(evaluate("temp < -3", &[("temp", "-10")]));
+        assert!(evaluate("a == 1", &[("a", "1.0")]));
+    }
+
+    #[test]
+    fn strings_compare_lexicographically() {
+        assert!(evaluate("a > b", &[("a", "9x"), ("b", "10x")]));
+        assert!(evaluate("a == 'GET'", &[("a", "GET")]));
+        assert!(evaluate("a != 10", &[("a", "ten")]));
+    }
+
+    #[test]
+    fn non_finite_words_compare_as_strings() {
+        assert!(evaluate("a == 'NaN'", &[("a", "NaN")]));
+        assert!(evaluate("a == 'Infinity'", &[("a", "Infinity")]));
+        assert!(!evaluate("a == 'nan'", &[("a", "NaN")]));
+        assert!(evaluate("a != 1", &[("a", "inf")]));
+    }
+
+    #[test]
+    fn undefined_variables_are_false() {
+        assert!(!evaluate("missing > 1", &[]));
+        assert!(!evaluate("missing != 1", &[]));
+    }
+}
@@ -1,36 +1,503 @@
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    ops::Range,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
+use crate::{condition::Condition, file_watcher::Permissions, schema, template, users, vault};
 use anyhow::{anyhow, bail, Error, Result};
+use chrono::NaiveTime;
+use chrono_tz::Tz;
 use lettre::message::Mailbox;
+use log::warn;
 use regex::Regex;
-use tokio::time::{interval, Interval};
-use toml::{Table, Value};
+use reqwest::{Certificate, Url};
+use serde::{
+    de::{DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use syslog::Facility;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+use toml::{Spanned, Table, Value};
 
 pub struct Config {
     pub monitors: Vec<MonitorConfig>,
     pub notifications: HashMap<String, NotificationConfig>,
+    pub audit_log: Option<PathBuf>,
+    pub max_concurrent_notifications: Option<usize>,
+    /// A Unix socket that accepts commands such as `pause` and `fire`.
+    pub control_socket: Option<PathBuf>,
+    pub secrets_file: Option<PathBuf>,
+    /// The notify config that's notified when Ramon starts and stops.
+    pub notify_lifecycle: Option<String>,
+    /// The notify config that's notified when a maintenance window ends.
+    pub maintenance_summary: Option<String>,
+    pub http: HttpConfig,
+    pub env_names: EnvNames,
+    pub heartbeat: Option<HeartbeatConfig>,
+    pub syslog: Option<SyslogConfig>,
+    /// Where each setting came from, for `--trace-config`.
+    pub provenance: Vec<Provenance>,
+}
+
+/// Collects the span of each key's value in a table. toml can't report the span of a table, so
+/// tables are descended into instead, and their span is that of their first key.
+struct SpanCollector<'a> {
+    /// The parsed table, to tell which keys are tables.
+    table: &'a Table,
+    path: Vec<String>,
+    spans: &'a mut HashMap<Vec<String>, Range<usize>>,
+}
+
+impl<'de> DeserializeSeed<'de> for SpanCollector<'_> {
+    type Value = Option<Range<usize>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SpanCollector<'_> {
+    type Value = Option<Range<usize>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut first: Option<Range<usize>> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            let mut path = self.path.clone();
+            path.push(key.clone());
+            let span = match self.table.get(&key) {
+                Some(Value::Table(table)) => map.next_value_seed(SpanCollector {
+                    table,
+                    path: path.clone(),
+                    spans: &mut *self.spans,
+                })?,
+                _ => Some(map.next_value::<Spanned<IgnoredAny>>()?.span()),
+            };
+            let Some(span) = span else {
+                continue;
+            };
+            if first.as_ref().is_none_or(|first| span.start < first.start) {
+                first = Some(span.clone());
+            }
+            self.spans.insert(path, span);
+        }
+        Ok(first)
+    }
+}
+
+/// Where a setting came from.
+pub struct Provenance {
+    /// The key's path, e.g. `notify.email.to`.
+    pub path: String,
+    pub value: Value,
+    /// The secrets file the value was read from, or `None` if it's from the config.
+    pub secrets_file: Option<String>,
+    pub line: Option<usize>,
+    /// Whether the value was inherited from `notify.default`.
+    pub inherited: bool,
+    /// The `for_each` item the monitor was expanded for.
+    pub item: Option<String>,
+}
+
+impl Provenance {
+    fn new(path: String, value: Value, line: Option<usize>) -> Self {
+        Self {
+            path,
+            value,
+            secrets_file: None,
+            line,
+            inherited: false,
+            item: None,
+        }
+    }
+}
+
+/// The line each key of a config file is on, for `Provenance`.
+struct KeyLines(HashMap<Vec<String>, usize>);
+
+impl KeyLines {
+    fn new(doc: &str, table: &Table) -> Self {
+        let mut spans = HashMap::new();
+        let collector = SpanCollector {
+            table,
+            path: Vec::new(),
+            spans: &mut spans,
+        };
+        // The document was already parsed, so this only fails for values toml can't report the
+        // span of, in which case the lines are left out.
+        let _ = collector.deserialize(toml::Deserializer::new(doc));
+        let lines = spans
+            .into_iter()
+            .map(|(path, span)| (path, doc[..span.start].matches('\n').count() + 1))
+            .collect();
+        Self(lines)
+    }
+
+    fn get(&self, path: &[&str]) -> Option<usize> {
+        let path = path.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        self.0.get(&path).copied()
+    }
+}
+
+/// Pings an external uptime monitor, so that it notices when Ramon stops running.
+pub struct HeartbeatConfig {
+    pub url: Url,
+    pub interval: Duration,
+    /// How long each ping may take.
+    pub timeout: Duration,
+}
+
+/// Forwards a record of each action to syslog.
+pub struct SyslogConfig {
+    pub address: SyslogAddress,
+    pub facility: Facility,
+}
+
+/// Where syslog messages are sent.
+pub enum SyslogAddress {
+    /// The local syslog daemon's socket, e.g. `/dev/log`.
+    Local,
+    Unix(PathBuf),
+    Udp(String),
+    Tcp(String),
+}
+
+/// How variables are named in `exec`'s environment.
+#[derive(Clone)]
+pub struct EnvNames {
+    pub prefix: String,
+    /// Also sets each variable under its bare name, as Ramon did before it added the prefix.
+    pub unprefixed: bool,
+}
+
+impl EnvNames {
+    /// The names of the environment variables `exec` receives a variable as, e.g. `RAMON_IP` for
+    /// `ip`, and `ip` too if `unprefixed` is set.
+    pub fn of(&self, variable: &str) -> Vec<String> {
+        let name = variable
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect::<String>();
+        let mut names = vec![format!("{}{name}", self.prefix)];
+        if self.unprefixed {
+            names.push(variable.to_owned());
+        }
+        names
+    }
+}
+
+impl Default for EnvNames {
+    fn default() -> Self {
+        Self {
+            prefix: "RAMON_".to_owned(),
+            unprefixed: false,
+        }
+    }
+}
+
+/// Settings for the HTTP client that sends webhooks.
+#[derive(Default)]
+pub struct HttpConfig {
+    /// Used instead of the `HTTP_PROXY` and `HTTPS_PROXY` environment variables.
+    pub proxy: Option<Url>,
+    /// Trusted in addition to the built-in roots.
+    pub ca_certificates: Vec<Certificate>,
+    /// Whether TLS certificates aren't verified.
+    pub insecure: bool,
 }
 
 pub struct MonitorConfig {
     pub name: String,
 
     pub every: Option<Interval>,
-    pub log: Option<PathBuf>,
+    pub missed_ticks: MissedTickBehavior,
+    pub skip_if_running: bool,
+    pub log: Option<LogConfig>,
+    pub watch: Vec<String>,
+    /// Only fires `watch` events when the file's content matches.
+    pub match_content: Option<Regex>,
+    /// Fires when a `watch` file starts or stops matching `match_content`, rather than each time
+    /// it changes while it matches.
+    pub match_content_changes: bool,
+    /// Files bigger than this many bytes aren't read for `match_content`.
+    pub max_file_size: u64,
+    /// Fires for files bigger than `max_file_size` with `skipped` set, rather than ignoring them.
+    pub fire_when_skipped: bool,
+    /// Polls `watch` files instead of relying on inotify.
+    pub poll_interval: Option<Duration>,
+    /// Also fires `watch` events when a file's mode or owner changes.
+    pub watch_permissions: Option<WatchPermissions>,
+    /// Holds back `watch` events until the file hasn't been written for this long.
+    pub watch_debounce: Option<Duration>,
+    /// A directory where each new file fires an event once it's completely written.
+    pub watch_dir: Option<PathBuf>,
+    /// How long a `watch_dir` file's size and modification time must stay the same before it's
+    /// considered completely written.
+    pub settle_time: Duration,
     pub service: Option<String>,
+    /// Extra arguments passed to `journalctl`.
+    pub service_args: Vec<String>,
+    /// Reads the journal as JSON, and derives each line's severity from its `PRIORITY`.
+    pub severity_from_priority: bool,
+    pub ssh: Option<SshConfig>,
+    pub service_state: Option<String>,
+    /// How long `service_state` must be down before actions are run.
+    pub down_for: Duration,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Applies to `service` and `ssh`. `log` has its own copy.
+    pub line_limit: Option<LineLimit>,
+
+    pub severity: Severity,
+    pub labels: HashMap<String, String>,
+    /// Selects the monitor with `--shard`.
+    pub group: Option<String>,
+    /// The timezone `timestamp` is rendered in.
+    pub timezone: Tz,
+    pub first_match_only: bool,
 
     pub cooldown: Option<Duration>,
+    pub cooldown_mode: CooldownMode,
+    /// How long after Ramon starts actions are suppressed, so that transient conditions while the
+    /// host boots don't alert.
+    pub warmup: Option<Duration>,
     pub match_log: Option<Regex>,
     pub ignore_log: Option<Regex>,
+    pub match_log_bytes: Option<regex::bytes::Regex>,
+    pub ignore_log_bytes: Option<regex::bytes::Regex>,
+    /// Rewrites captured values before the conditions see them.
+    pub transform: Vec<Transform>,
+    /// Variables and the patterns that suppress the event if the variable matches.
+    pub ignore_if: Vec<(String, Regex)>,
     pub unique: Option<String>,
+    /// Whether `unique` values are recorded separately for each file matching `watch`.
+    pub unique_per_file: bool,
+    /// Only runs actions when this variable's value differs from the last one seen.
+    pub changed: Option<String>,
+    pub r#if: Option<Condition>,
+    /// The window and the number of events within it that must be exceeded.
+    pub rate: Option<(Duration, usize)>,
     pub threshold: Option<(usize, Duration)>,
+    pub require: Option<Require>,
+    /// Monitors that, while they're down, suppress this monitor's actions.
+    pub depends_on: Vec<String>,
+    /// How long after a dependency ran its actions it's considered down.
+    pub depends_on_within: Duration,
+    /// How long to wait before acting, in case the trigger is reset.
+    pub confirm_after: Option<Duration>,
+    /// Cancels actions waiting for `confirm_after`.
+    pub confirm_reset: Option<Regex>,
+    /// Stops the monitor once a line matches, after evaluating the line.
+    pub stop_on: Option<Regex>,
+    /// The variable to average and the number of values to average over.
+    pub average: Option<(String, usize)>,
+    pub anomaly: Option<Anomaly>,
 
     pub exec: Option<Exec>,
-    pub notify: Option<Notification>,
+    pub exec_mode: ExecMode,
+    pub exec_parse: Option<Regex>,
+    /// How long `exec` may run when its output is parsed before it's killed.
+    pub exec_timeout: Duration,
+    /// What happens to a `watch_dir` file once `exec` succeeded for it.
+    pub after_exec: AfterExec,
+    /// Whether `exec` is attached to a pseudo-terminal instead of Ramon's stdio.
+    pub exec_pty: bool,
+    pub env_newlines: EnvNewlines,
+    /// A named pipe that each event is written to as a line of JSON.
+    pub fifo: Option<PathBuf>,
+    pub notify: Vec<Notification>,
+    pub max_notifications_per_hour: Option<usize>,
+    /// Whether to send a summary of the suppressed notifications once the hourly cap clears.
+    pub summarize_suppressed: bool,
+    /// A template that identifies an incident. Notifications aren't sent again for the same
+    /// fingerprint until `fingerprint_window` has passed.
+    pub fingerprint: Option<String>,
+    pub fingerprint_window: Duration,
+    /// The notify config that's notified when the monitor's source, `exec`, or notifications fail.
+    pub on_error: Option<String>,
 }
 
-#[derive(Clone, Default)]
+/// The mode and owner `watch` files are expected to have. A permission change is only reported if
+/// it leaves the file with something else, and files that already don't match are reported when
+/// Ramon starts.
+#[derive(Clone, Copy, Default)]
+pub struct WatchPermissions {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl WatchPermissions {
+    /// Whether every change is reported, since neither a mode nor an owner is expected.
+    pub fn reports_any_change(&self) -> bool {
+        self.mode.is_none() && self.uid.is_none() && self.gid.is_none()
+    }
+
+    pub fn is_expected(&self, permissions: Permissions) -> bool {
+        self.mode.is_none_or(|mode| mode == permissions.mode)
+            && self.uid.is_none_or(|uid| uid == permissions.uid)
+            && self.gid.is_none_or(|gid| gid == permissions.gid)
+    }
+}
+
+/// Follows a remote log with `ssh <host> tail -F <path>`.
+#[derive(Clone)]
+pub struct SshConfig {
+    /// Passed to `ssh` as is, e.g. `user@host`, or a host defined in `~/.ssh/config`.
+    pub host: String,
+    pub path: String,
+    /// The identity file passed to `ssh -i`.
+    pub key: Option<PathBuf>,
+}
+
+/// Replaces every match of `regex` in a variable's value with `replace`, which can refer to the
+/// regex's capture groups as `$1` or `${name}`.
+pub struct Transform {
+    pub variable: String,
+    pub regex: Regex,
+    pub replace: String,
+}
+
+/// Requires other monitors' conditions to have been true recently.
+pub struct Require {
+    pub all: Vec<String>,
+    pub any: Vec<String>,
+    pub within: Duration,
+}
+
+/// Restarts a failed source, pausing it after `errors` failures within `within`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CircuitBreakerConfig {
+    pub errors: usize,
+    pub within: Duration,
+    pub pause: Duration,
+}
+
+#[derive(Clone)]
+pub struct LogConfig {
+    pub path: PathBuf,
+    pub binary: bool,
+    /// Sends each chunk of lines as a single event.
+    pub batch_lines: bool,
+    /// Collapses runs of identical lines into one event.
+    pub dedup_consecutive: bool,
+    /// How long to wait for a rotated log to be recreated. `None` waits forever.
+    pub reopen_timeout: Option<Duration>,
+    /// Polls the file instead of relying on inotify.
+    pub poll_interval: Option<Duration>,
+    pub replay: Option<ReplayConfig>,
+    pub line_limit: Option<LineLimit>,
+    pub line_ending: LineEnding,
+}
+
+/// What `cooldown` counts from.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum CooldownMode {
+    /// When the actions last ran, e.g. once `confirm_after` has passed. The cooldown is checked
+    /// before every other condition, so events during it don't update stateful conditions such as
+    /// `rate`, `threshold`, or `changed`, and don't count as true for `require`.
+    #[default]
+    Trailing,
+    /// When the event that opened the window ran or queued the actions. The cooldown is checked
+    /// after every other condition, so events during it still update stateful conditions.
+    Leading,
+}
+
+/// How lines in a text `log` end.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// Lines end with `\n`, and a `\r` before it is removed.
+    #[default]
+    Auto,
+    /// Lines end with `\n`, and a `\r` before it is kept.
+    Lf,
+    /// Lines end with `\r\n`, and a `\n` on its own is part of the line.
+    Crlf,
+}
+
+/// Guards against pathologically long lines from `log`, `service`, and `ssh`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineLimit {
+    /// In bytes.
+    pub max: usize,
+    pub action: LongLines,
+}
+
+/// What to do with lines longer than `max_line_length`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LongLines {
+    /// Keep the first `max_line_length` bytes, followed by a marker.
+    #[default]
+    Truncate,
+    Skip,
+}
+
+/// Replays the lines of a log that were written within `since` when Ramon starts.
+#[derive(Clone)]
+pub struct ReplayConfig {
+    pub since: Duration,
+    /// Matches the timestamp of a line, either as the `timestamp` group, or the whole match.
+    pub timestamp_regex: Regex,
+    /// A `strftime` format. Timestamps are parsed as RFC 3339 if this isn't set.
+    pub timestamp_format: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct NotificationConfig {
+    pub enabled: bool,
     pub smtp: Option<SmtpConfig>,
+    /// A URL the notification is POSTed to as JSON.
+    pub webhook: Option<Url>,
+    /// How variables are escaped when they're rendered into the title and body.
+    pub escape: Escape,
+    pub schedule: Vec<ScheduleEntry>,
+    /// The timezone `schedule` is evaluated in. Defaults to the local timezone.
+    pub timezone: Option<Tz>,
+    pub escalate: Vec<EscalationTier>,
+    /// Where notifications that failed to send are kept until they're retried.
+    pub spool_dir: Option<PathBuf>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            smtp: None,
+            webhook: None,
+            escape: Escape::default(),
+            schedule: Vec::new(),
+            timezone: None,
+            escalate: Vec::new(),
+            spool_dir: None,
+        }
+    }
+}
+
+/// Routes notifications to `target` between `from` and `to`.
+#[derive(Clone)]
+pub struct ScheduleEntry {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+    pub target: String,
+}
+
+/// Also notifies `target` once a monitor has sent more than `after` notifications with this config
+/// within `within`.
+#[derive(Clone)]
+pub struct EscalationTier {
+    pub after: usize,
+    pub within: Duration,
+    pub target: String,
 }
 
 #[derive(Clone)]
@@ -47,21 +514,206 @@ pub struct SmtpLogin {
     pub password: String,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Critical => "critical",
+        }
+    }
+
+    /// Maps a syslog priority, as stored in the journal's `PRIORITY` field, to a severity.
+    pub fn from_priority(priority: u8) -> Self {
+        match priority {
+            0..=3 => Self::Critical,
+            4 => Self::Warning,
+            _ => Self::Info,
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "info" => Ok(Self::Info),
+            "warn" | "warning" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            "critical" => Ok(Self::Critical),
+            _ => bail!("Unknown severity `{s}`. Expected info, warning, error, or critical."),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Exec {
     Shell(String),
     Spawn(Vec<String>),
 }
 
+/// Restricts the commands `exec` may run. This is set on the command line so that a less-trusted
+/// config can't loosen it.
+#[derive(Default)]
+pub struct ExecPolicy {
+    /// The binaries `exec` arrays may run. Any binary is allowed if this is empty.
+    pub allowed: Vec<String>,
+    pub forbid_shell: bool,
+}
+
+impl ExecPolicy {
+    fn check(&self, exec: &Exec) -> Result<()> {
+        match exec {
+            Exec::Shell(_) if self.forbid_shell => {
+                bail!("Key `exec` must be an array because shell commands are forbidden.")
+            }
+            // A shell can run anything, so it can't be checked against the allowlist.
+            Exec::Shell(_) if !self.allowed.is_empty() => {
+                bail!("Key `exec` must be an array because an allowlist is set.")
+            }
+            Exec::Spawn(args) if !self.allowed.is_empty() && !self.allowed.contains(&args[0]) => {
+                bail!("Binary {:?} is not in the exec allowlist.", args[0])
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How variables are escaped when they're rendered into a notification, so that captured text
+/// can't inject markup.
+#[derive(Clone, Copy, Default)]
+pub enum Escape {
+    #[default]
+    None,
+    /// Escape `&`, `<`, `>`, `"`, and `'`.
+    Html,
+    /// Escape `&`, `<`, and `>`, which Slack uses for links and mentions.
+    Slack,
+}
+
+/// When `exec` runs.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecMode {
+    /// Once each time the actions run.
+    #[default]
+    Trigger,
+    /// Once at startup, restarting it whenever it exits.
+    Daemon,
+}
+
+/// Only runs actions when a variable deviates from its recent values by more than `k` standard
+/// deviations.
+pub struct Anomaly {
+    pub variable: String,
+    /// The number of recent values the mean and standard deviation are computed over.
+    pub window: usize,
+    pub k: f64,
+}
+
+/// What happens to a `watch_dir` file once `exec` exited successfully for it.
+#[derive(Clone, Default)]
+pub enum AfterExec {
+    #[default]
+    Keep,
+    Delete,
+    /// Moves the file into the directory.
+    Move(PathBuf),
+}
+
+/// How `exec` receives variables that contain new lines.
+#[derive(Clone, Copy, Default)]
+pub enum EnvNewlines {
+    /// Replace new lines with `\n` and backslashes with `\\`.
+    #[default]
+    Escape,
+    /// Replace new lines with spaces.
+    Flatten,
+    /// Don't run `exec`.
+    Reject,
+    /// Pass the value unchanged.
+    Keep,
+}
+
 pub struct Notification {
     pub r#type: String,
     pub title: String,
     pub body: String,
+    /// The number of lines from the end of the monitor's `log` to append to the body.
+    pub tail: Option<usize>,
 }
 
-pub fn parse(doc: &str) -> Result<Config> {
+/// Monitor keys whose values are regexes, which `for_each` items are escaped in. Items are also
+/// escaped in the `regex` of each `transform`.
+const REGEX_KEYS: &[&str] = &[
+    "match_log",
+    "ignore_log",
+    "ignore_if",
+    "match_content",
+    "exec_parse",
+    "confirm_reset",
+    "stop_on",
+    "timestamp_regex",
+];
+
+pub fn parse(doc: &str, exec_policy: &ExecPolicy) -> Result<Config> {
     let mut table = doc
         .parse::<Table>()
         .map_err(|err| map_to_readable_syntax_err(doc, err))?;
+    reject_unknown_keys(&table, schema::TOP_LEVEL_KEYS)?;
+
+    let lines = KeyLines::new(doc, &table);
+    let mut provenance = table
+        .iter()
+        .filter(|(key, _)| !["notify", "monitor"].contains(&key.as_str()))
+        .map(|(key, value)| Provenance::new(key.clone(), value.clone(), lines.get(&[key])))
+        .collect::<Vec<_>>();
+
+    // The file and line of each notify key that was read from the secrets file.
+    let mut secret_keys = HashMap::new();
+    let secrets_file = match table.remove("secrets_file") {
+        None => None,
+        Some(Value::String(path)) => {
+            let (secrets, secret_lines) = load_secrets(&path)?;
+            for (name, config) in &secrets {
+                let Value::Table(config) = config else {
+                    continue;
+                };
+                for key in config.keys() {
+                    let line = secret_lines.get(&["notify", name, key]);
+                    secret_keys.insert((name.clone(), key.clone()), (path.clone(), line));
+                }
+            }
+            let notify = table
+                .entry("notify")
+                .or_insert_with(|| Value::Table(Table::new()));
+            let Value::Table(notify) = notify else {
+                bail!("Key `notify` must be a table.");
+            };
+            overlay_secrets(notify, secrets)
+                .map_err(|err| anyhow!("Secrets file {path:?}: {err}"))?;
+            Some(path.into())
+        }
+        Some(_) => bail!("Key `secrets_file` must be a string."),
+    };
+
+    // Vault is reached with the same settings as webhooks.
+    let http = match table.remove("http") {
+        None => HttpConfig::default(),
+        Some(Value::Table(http_table)) => parse_http_config(http_table)?,
+        Some(_) => bail!("Key `http` must be a table."),
+    };
 
     let notification_config = match table.remove("notify") {
         None => {
@@ -70,12 +722,53 @@ pub fn parse(doc: &str) -> Result<Config> {
             map
         }
         Some(Value::Table(mut notify)) => {
-            let default = match notify.remove("default") {
+            let mut default = match notify.remove("default") {
                 None => Table::new(),
                 Some(Value::Table(default_table)) => default_table,
                 Some(_) => bail!("Key `notify.default` must be a table."),
             };
 
+            let setting = |name: &str, key: &str, value: &Value| {
+                let mut setting = Provenance::new(
+                    format!("notify.{name}.{key}"),
+                    value.clone(),
+                    lines.get(&["notify", name, key]),
+                );
+                if let Some((path, line)) = secret_keys.get(&(name.to_owned(), key.to_owned())) {
+                    setting.secrets_file = Some(path.clone());
+                    setting.line = *line;
+                }
+                setting
+            };
+            provenance.extend(
+                default
+                    .iter()
+                    .map(|(key, value)| setting("default", key, value)),
+            );
+            for (name, config) in &notify {
+                let Value::Table(config) = config else {
+                    continue;
+                };
+                for (key, value) in config {
+                    provenance.push(setting(name, key, value));
+                }
+                // Keys the config doesn't set are inherited, as in `parse_notify_config`.
+                for (key, value) in &default {
+                    if !config.contains_key(key) {
+                        let mut setting = setting("default", key, value);
+                        setting.path = format!("notify.{name}.{key}");
+                        setting.inherited = true;
+                        provenance.push(setting);
+                    }
+                }
+            }
+
+            let configs = notify.iter_mut().filter_map(|(name, config)| match config {
+                Value::Table(config) => Some((name.as_str(), config)),
+                _ => None,
+            });
+            vault::resolve(configs.chain([("default", &mut default)]), &http)?;
+
             let mut hashmap = notify
                 .into_iter()
                 .map(|(name, config)| Ok((name, parse_notify_config(&default, config)?)))
@@ -86,202 +779,1584 @@ pub fn parse(doc: &str) -> Result<Config> {
                 parse_notify_config(&Table::new(), default.into())
                     .map_err(|err| anyhow!("Failed to parse default notification config: {err}"))?,
             );
+            for (name, config) in &hashmap {
+                for entry in &config.schedule {
+                    if !hashmap.contains_key(&entry.target) {
+                        bail!(
+                            "Notify config `{name}`: Schedule target `{}` does not exist.",
+                            entry.target
+                        );
+                    }
+                }
+                for tier in &config.escalate {
+                    if !hashmap.contains_key(&tier.target) {
+                        bail!(
+                            "Notify config `{name}`: Escalation target `{}` does not exist.",
+                            tier.target
+                        );
+                    }
+                }
+            }
             hashmap
         }
         Some(_) => bail!("Key `notify` must be a table."),
     };
 
-    // Validate and parse monitors.
-    let monitor_configs = match table.remove("monitor") {
-        None => bail!("No monitors found!"),
-        Some(Value::Table(monitors)) => {
-            let mut monitor_configs = Vec::with_capacity(monitors.len());
-            for (name, monitor) in monitors {
-                let monitor_table = match monitor {
-                    Value::Table(monitor) => monitor,
-                    _ => bail!("Key `monitor.{name}` must be a table."),
-                };
-                monitor_configs.push(
-                    parse_monitor_config(name.clone(), monitor_table)
-                        .map_err(|err| anyhow!("Monitor `{name}`: {err}"))?,
-                );
-            }
-            monitor_configs
-        }
-        Some(_) => bail!("Key `monitor` must be a table."),
+    let audit_log = match table.remove("audit_log") {
+        None => None,
+        Some(Value::String(audit_log)) => Some(audit_log.into()),
+        Some(_) => bail!("Key `audit_log` must be a string."),
     };
 
-    assert_table_is_empty(table)?;
-
-    Ok(Config {
-        monitors: monitor_configs,
-        notifications: notification_config,
-    })
-}
+    let max_concurrent_notifications = match table.remove("max_concurrent_notifications") {
+        None => None,
+        Some(Value::Integer(max)) if max > 0 => Some(max as usize),
+        Some(_) => bail!("Key `max_concurrent_notifications` must be a positive integer."),
+    };
 
-/// Turns a `toml::de::Error` into a human-readable error message.
-fn map_to_readable_syntax_err(doc: &str, err: toml::de::Error) -> Error {
-    let mut message = err.message().to_owned();
-    // Print lines where error occurred.
-    if let Some(err_range) = err.span() {
-        let mut line_start_byte;
-        let mut line_end_byte = 0;
-        message += "\n";
-        for (i, line) in doc.lines().enumerate() {
-            line_start_byte = line_end_byte;
-            // Account for new line.
-            line_end_byte = line_start_byte + line.len() + 1;
-            // Only print the last line.
-            if line_end_byte < err_range.end {
-                continue;
-            }
-            message += &format!("\n{}:\t{line}", i + 1);
-            message += &format!(
-                "\n\t{}{}",
-                " ".repeat(err_range.start - line_start_byte),
-                "^".repeat(err_range.len())
-            );
-            break;
+    let env_prefix = match table.remove("env_prefix") {
+        None => EnvNames::default().prefix,
+        Some(Value::String(prefix))
+            if !prefix.is_empty()
+                && !prefix.starts_with(|c: char| c.is_ascii_digit())
+                && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            prefix
         }
-    }
-    anyhow!("{message}")
-}
-
-fn parse_notify_config(default: &Table, config: Value) -> Result<NotificationConfig> {
-    let mut config_table = match config {
-        Value::Table(config_table) => config_table,
-        _ => bail!("Key must be a table."),
+        Some(Value::String(_)) => bail!(
+            "Key `env_prefix` must only contain letters, digits, and `_`, and not start with a digit."
+        ),
+        Some(_) => bail!("Key `env_prefix` must be a string."),
     };
 
-    for (k, v) in default {
-        config_table.entry(k).or_insert(v.to_owned());
+    let env_unprefixed = match table.remove("env_unprefixed") {
+        None => false,
+        Some(Value::Boolean(unprefixed)) => unprefixed,
+        Some(_) => bail!("Key `env_unprefixed` must be a boolean."),
+    };
+    if env_unprefixed {
+        warn!("`env_unprefixed` is set, so variables named like `PATH` replace the environment of `exec`.");
     }
+    let env_names = EnvNames {
+        prefix: env_prefix,
+        unprefixed: env_unprefixed,
+    };
 
-    let smtp = match config_table.remove("from") {
+    let heartbeat_url = match table.remove("heartbeat_url") {
         None => None,
-        Some(Value::String(from_str)) => {
-            let from = from_str
-                .parse()
-                .map_err(|err| anyhow!("Failed to parse `from`: {err}"))?;
-
-            let to = match config_table.remove("to") {
-                None => bail!("Key `to` must be set if `from` is set."),
-                Some(Value::String(to_str)) => to_str
-                    .parse()
-                    .map_err(|err| anyhow!("Failed to parse `to`: {err}"))?,
-                Some(_) => bail!("Key `to` must be a string."),
-            };
-
-            let login = match config_table.remove("smtp_host") {
-                None => None,
-                Some(Value::String(host)) => {
-                    let username = match config_table.remove("username") {
-                        None => {
-                            bail!("Key `username` must be set if `smtp_host` is set.")
-                        }
-                        Some(Value::String(username)) => username,
-                        Some(_) => bail!("Key `username` must be a string."),
-                    };
-
-                    let password = match config_table.remove("password") {
-                        None => {
-                            bail!("Key `password` must be set if `smtp_host` is set.")
-                        }
-                        Some(Value::String(password)) => password,
-                        Some(_) => bail!("Key `password` must be a string."),
-                    };
-
-                    Some(SmtpLogin {
-                        host,
-                        username,
-                        password,
-                    })
-                }
-                Some(_) => bail!("Key `smtp_host` must be a string."),
-            };
-
-            Some(SmtpConfig { from, to, login })
+        Some(Value::String(url)) => {
+            let url = Url::parse(&url)
+                .map_err(|err| anyhow!("Failed to parse `heartbeat_url`: {err}"))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                bail!("Key `heartbeat_url` must be an http:// or https:// URL.");
+            }
+            Some(url)
         }
-        Some(_) => bail!("Key `from` must be a string."),
+        Some(_) => bail!("Key `heartbeat_url` must be a string."),
     };
 
-    let aggregate = match config_table.remove("aggregate") {
-        None => None,
-        Some(Value::String(aggregate)) => Some(
-            duration_str::parse(aggregate)
-                .map_err(|err| anyhow!("Failed to parse `aggregate`: {err}"))?,
-        ),
-        Some(_) => bail!("Key `aggregate` must be a string."),
+    let heartbeat_interval = match table.remove("heartbeat_interval") {
+        None => Duration::from_secs(60),
+        Some(_) if heartbeat_url.is_none() => {
+            bail!("Key `heartbeat_interval` requires `heartbeat_url`.")
+        }
+        Some(Value::String(interval)) => match parse_duration("heartbeat_interval", &interval)? {
+            interval if interval.is_zero() => bail!("Key `heartbeat_interval` must not be zero."),
+            interval => interval,
+        },
+        Some(_) => bail!("Key `heartbeat_interval` must be a string."),
     };
 
-    assert_table_is_empty(config_table)?;
+    let heartbeat_timeout = match table.remove("heartbeat_timeout") {
+        None => Duration::from_secs(10),
+        Some(_) if heartbeat_url.is_none() => {
+            bail!("Key `heartbeat_timeout` requires `heartbeat_url`.")
+        }
+        Some(Value::String(timeout)) => parse_duration("heartbeat_timeout", &timeout)?,
+        Some(_) => bail!("Key `heartbeat_timeout` must be a string."),
+    };
 
-    Ok(NotificationConfig { smtp })
-}
+    let heartbeat = heartbeat_url.map(|url| HeartbeatConfig {
+        url,
+        interval: heartbeat_interval,
+        timeout: heartbeat_timeout,
+    });
 
-fn parse_monitor_config(name: String, mut monitor_table: Table) -> Result<MonitorConfig> {
-    let every = match monitor_table.remove("every") {
+    let syslog = match table.remove("syslog") {
         None => None,
-        Some(Value::String(every)) => Some(interval(
-            duration_str::parse(every).map_err(|err| anyhow!("Key `every`:\n{err}"))?,
-        )),
-        Some(_) => bail!("Key `every` must be a string."),
+        Some(Value::Table(syslog_table)) => Some(parse_syslog_config(syslog_table)?),
+        Some(_) => bail!("Key `syslog` must be a table."),
     };
 
-    let log = match monitor_table.remove("log") {
+    let control_socket = match table.remove("control_socket") {
         None => None,
-        Some(Value::String(log)) => Some(log.into()),
-        Some(_) => bail!("Key `log` must be a string."),
+        Some(Value::String(path)) => Some(path.into()),
+        Some(_) => bail!("Key `control_socket` must be a string."),
     };
 
-    let service = match monitor_table.remove("service") {
+    let notify_lifecycle = match table.remove("notify_lifecycle") {
         None => None,
-        Some(Value::String(service)) => Some(service),
-        Some(_) => bail!("Key `service` must be a string."),
+        Some(Value::String(name)) if notification_config.contains_key(&name) => Some(name),
+        Some(Value::String(name)) => {
+            bail!("Key `notify_lifecycle`: Notify config `{name}` does not exist.")
+        }
+        Some(_) => bail!("Key `notify_lifecycle` must be a string."),
     };
 
-    let cooldown = match monitor_table.remove("cooldown") {
+    let maintenance_summary = match table.remove("maintenance_summary") {
         None => None,
-        Some(Value::String(cooldown)) => {
-            Some(duration_str::parse(cooldown).map_err(|err| anyhow!("Invalid cooldown:\n{err}"))?)
+        Some(Value::String(_)) if control_socket.is_none() => {
+            bail!("Key `maintenance_summary` requires `control_socket` to be set.")
         }
-        Some(_) => bail!("Key `cooldown` must be a string."),
+        Some(Value::String(name)) if notification_config.contains_key(&name) => Some(name),
+        Some(Value::String(name)) => {
+            bail!("Key `maintenance_summary`: Notify config `{name}` does not exist.")
+        }
+        Some(_) => bail!("Key `maintenance_summary` must be a string."),
     };
 
-    let match_log = match monitor_table.remove("match_log") {
+    // The default for monitors that don't set their own `warmup`.
+    let warmup = match table.remove("warmup") {
         None => None,
-        Some(Value::String(log_regex_str)) => Some(
-            Regex::new(&log_regex_str)
-                .map_err(|err| anyhow!("Failed to parse match_log: {err}"))?,
-        ),
-        Some(_) => bail!("Key `match_log` must be a string."),
+        Some(Value::String(warmup)) => Some(parse_duration("warmup", &warmup)?),
+        Some(_) => bail!("Key `warmup` must be a string."),
     };
 
-    let ignore_log = match monitor_table.remove("ignore_log") {
+    // The default for monitors that don't set their own `on_error`.
+    let on_error = match table.remove("on_error") {
         None => None,
-        Some(Value::String(ignore_log_regex_str)) => Some(
-            Regex::new(&ignore_log_regex_str)
-                .map_err(|err| anyhow!("Failed to parse ignore_log: {err}"))?,
-        ),
-        Some(_) => bail!("Key `ignore_log` must be a string."),
+        Some(Value::String(name)) if notification_config.contains_key(&name) => Some(name),
+        Some(Value::String(name)) => {
+            bail!("Key `on_error`: Notify config `{name}` does not exist.")
+        }
+        Some(_) => bail!("Key `on_error` must be a string."),
     };
 
-    let unique = match monitor_table.remove("unique") {
-        None => None,
-        Some(Value::String(unique)) => Some(unique),
-        Some(_) => bail!("Key `unique` must be a string."),
+    // Commands that monitors can refer to as `exec = "@name"`.
+    let commands = match table.remove("commands") {
+        None => HashMap::new(),
+        Some(Value::Table(commands)) => commands
+            .into_iter()
+            .map(|(name, command)| {
+                let exec = match command {
+                    // `[commands.name]` sections.
+                    Value::Table(mut command) => {
+                        let exec = match command.remove("exec") {
+                            None => bail!("Key `commands.{name}.exec` must be set."),
+                            Some(exec) => parse_exec(&format!("commands.{name}.exec"), exec)?,
+                        };
+                        assert_table_is_empty(command, &["exec"])?;
+                        exec
+                    }
+                    command => parse_exec(&format!("commands.{name}"), command)?,
+                };
+                if matches!(&exec, Exec::Shell(command) if command.starts_with('@')) {
+                    bail!("Command `{name}` must not refer to another command.");
+                }
+                Ok((name, exec))
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `commands` must be a table."),
     };
 
-    let threshold = match monitor_table.remove("threshold") {
-        None => None,
-        Some(Value::String(threshold)) => {
-            let split = threshold.split("/").collect::<Vec<&str>>();
+    // Validate and parse monitors.
+    let monitor_configs = match table.remove("monitor") {
+        None => bail!("No monitors found!"),
+        Some(Value::Table(monitors)) => {
+            let monitors = expand_for_each(monitors, &commands, &lines, &mut provenance)?;
+            let mut monitor_configs = Vec::with_capacity(monitors.len());
+            for (name, monitor_table) in monitors {
+                let mut monitor_config =
+                    parse_monitor_config(name.clone(), monitor_table, &commands)
+                        .map_err(|err| anyhow!("Monitor `{name}`: {err}"))?;
+                if let Some(target) = &monitor_config.on_error {
+                    if !notification_config.contains_key(target) {
+                        bail!("Monitor `{name}`: Key `on_error`: Notify config `{target}` does not exist.");
+                    }
+                }
+                monitor_config.warmup = monitor_config.warmup.or(warmup);
+                monitor_config.on_error = monitor_config.on_error.or_else(|| on_error.clone());
+                if let Some(exec) = &monitor_config.exec {
+                    exec_policy
+                        .check(exec)
+                        .map_err(|err| anyhow!("Monitor `{name}`: {err}"))?;
+                }
+                monitor_configs.push(monitor_config);
+            }
+            for monitor_config in &monitor_configs {
+                check_require(monitor_config, &monitor_configs)?;
+                check_env_names(monitor_config, &env_names)?;
+                warn_undefined_placeholders(monitor_config);
+            }
+            monitor_configs
+        }
+        Some(_) => bail!("Key `monitor` must be a table."),
+    };
+
+    assert_table_is_empty(table, &schema::names(schema::TOP_LEVEL_KEYS))?;
+
+    Ok(Config {
+        monitors: monitor_configs,
+        notifications: notification_config,
+        audit_log,
+        max_concurrent_notifications,
+        control_socket,
+        secrets_file,
+        notify_lifecycle,
+        maintenance_summary,
+        http,
+        env_names,
+        heartbeat,
+        syslog,
+        provenance,
+    })
+}
+
+/// Expands each monitor with `for_each` into a monitor for each item, named `<name>.<item>`, with
+/// `{{each}}` replaced by the item in every string. Items are escaped in keys that are regexes.
+fn expand_for_each(
+    monitors: Table,
+    commands: &HashMap<String, Exec>,
+    lines: &KeyLines,
+    provenance: &mut Vec<Provenance>,
+) -> Result<Vec<(String, Table)>> {
+    let mut expanded = Vec::with_capacity(monitors.len());
+    for (name, monitor) in monitors {
+        let mut monitor_table = match monitor {
+            Value::Table(monitor) => monitor,
+            _ => bail!("Key `monitor.{name}` must be a table."),
+        };
+        let items = match monitor_table.remove("for_each") {
+            None => {
+                provenance.extend(monitor_table.iter().map(|(key, value)| {
+                    Provenance::new(
+                        format!("monitor.{name}.{key}"),
+                        value.clone(),
+                        lines.get(&["monitor", &name, key]),
+                    )
+                }));
+                expanded.push((name, monitor_table));
+                continue;
+            }
+            Some(Value::Array(items)) if !items.is_empty() => items,
+            Some(_) => {
+                bail!("Monitor `{name}`: Key `for_each` must be a non-empty array of strings.")
+            }
+        };
+        for item in items {
+            let Value::String(item) = item else {
+                bail!("Monitor `{name}`: Key `for_each` must be a non-empty array of strings.");
+            };
+            let table = expand_item(&monitor_table, &item, commands);
+            provenance.extend(table.iter().map(|(key, value)| Provenance {
+                item: Some(item.clone()),
+                ..Provenance::new(
+                    format!("monitor.{name}.{item}.{key}"),
+                    value.clone(),
+                    lines.get(&["monitor", &name, key]),
+                )
+            }));
+            expanded.push((format!("{name}.{item}"), table));
+        }
+    }
+
+    let mut names = HashSet::new();
+    for (name, _) in &expanded {
+        if !names.insert(name) {
+            bail!("Monitor `{name}` is defined more than once.");
+        }
+    }
+    Ok(expanded)
+}
+
+/// Returns a copy of a `for_each` monitor for the item. A command that `exec` refers to is copied
+/// into the monitor first, so that `{{each}}` is replaced in the command too.
+fn expand_item(monitor_table: &Table, item: &str, commands: &HashMap<String, Exec>) -> Table {
+    monitor_table
+        .iter()
+        .map(|(key, value)| {
+            let command = match (key.as_str(), value) {
+                ("exec", Value::String(exec)) => exec
+                    .strip_prefix('@')
+                    .and_then(|command| commands.get(command)),
+                _ => None,
+            };
+            let value = match command {
+                Some(Exec::Shell(command)) => Value::String(command.clone()),
+                Some(Exec::Spawn(args)) => {
+                    Value::Array(args.iter().cloned().map(Value::String).collect())
+                }
+                // Unknown commands are reported when the monitor is parsed.
+                None => value.clone(),
+            };
+            let value = match (key.as_str(), value) {
+                ("transform", Value::Table(transforms)) => expand_transform(transforms, item),
+                (key, value) => match REGEX_KEYS.contains(&key) {
+                    true => replace_each(value, &regex::escape(item)),
+                    false => replace_each(value, item),
+                },
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+/// Replaces `{{each}}` in a `transform` table. Only the `regex` of each variable is a regex, so
+/// the item isn't escaped in its `replace`.
+fn expand_transform(transforms: Table, item: &str) -> Value {
+    Value::Table(
+        transforms
+            .into_iter()
+            .map(|(variable, transform)| {
+                let Value::Table(transform) = transform else {
+                    // Rejected when the monitor is parsed.
+                    return (variable, replace_each(transform, item));
+                };
+                let transform = transform
+                    .into_iter()
+                    .map(|(key, value)| match key.as_str() {
+                        "regex" => (key, replace_each(value, &regex::escape(item))),
+                        _ => (key, replace_each(value, item)),
+                    })
+                    .collect();
+                (variable, Value::Table(transform))
+            })
+            .collect(),
+    )
+}
+
+/// Replaces `{{each}}` in every string in the value.
+fn replace_each(value: Value, item: &str) -> Value {
+    match value {
+        Value::String(string) => Value::String(string.replace("{{each}}", item)),
+        Value::Array(array) => Value::Array(
+            array
+                .into_iter()
+                .map(|value| replace_each(value, item))
+                .collect(),
+        ),
+        Value::Table(table) => Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, replace_each(value, item)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn parse_exec(key: &str, value: Value) -> Result<Exec> {
+    match value {
+        Value::String(exec_str) => Ok(Exec::Shell(exec_str)),
+        Value::Array(args) => match args.is_empty() {
+            true => bail!("Key `{key}` must not be empty."),
+            false => Ok(Exec::Spawn(args.into_iter().map(value_to_string).collect())),
+        },
+        _ => bail!("Key `{key}` must be a string or an array of strings."),
+    }
+}
+
+fn parse_syslog_config(mut table: Table) -> Result<SyslogConfig> {
+    let address = match table.remove("address") {
+        None => SyslogAddress::Local,
+        Some(Value::String(address)) => {
+            if let Some(server) = address.strip_prefix("udp://") {
+                SyslogAddress::Udp(server.to_owned())
+            } else if let Some(server) = address.strip_prefix("tcp://") {
+                SyslogAddress::Tcp(server.to_owned())
+            } else if address.starts_with('/') {
+                SyslogAddress::Unix(address.into())
+            } else {
+                bail!("Key `syslog.address` must be a socket path, or a udp:// or tcp:// address.");
+            }
+        }
+        Some(_) => bail!("Key `syslog.address` must be a string."),
+    };
+    let facility = match table.remove("facility") {
+        None => Facility::LOG_DAEMON,
+        Some(Value::String(facility)) => Facility::from_str(&facility)
+            .map_err(|()| anyhow!("Unknown syslog facility `{facility}`."))?,
+        Some(_) => bail!("Key `syslog.facility` must be a string."),
+    };
+    assert_table_is_empty(table, &["address", "facility"])?;
+    Ok(SyslogConfig { address, facility })
+}
+
+/// Checks that `require` and `depends_on` only refer to other monitors that exist.
+fn check_require(monitor: &MonitorConfig, monitors: &[MonitorConfig]) -> Result<()> {
+    let required = monitor
+        .require
+        .iter()
+        .flat_map(|require| require.all.iter().chain(&require.any))
+        .map(|name| ("require", "Required", name));
+    let dependencies = monitor
+        .depends_on
+        .iter()
+        .map(|name| ("depends_on", "Dependency", name));
+    for (key, kind, name) in required.chain(dependencies) {
+        if *name == monitor.name {
+            bail!(
+                "Monitor `{}`: Key `{key}` must not refer to itself.",
+                monitor.name
+            );
+        }
+        if !monitors.iter().any(|other| other.name == *name) {
+            bail!(
+                "Monitor `{}`: {kind} monitor `{name}` does not exist.",
+                monitor.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rejects captures that `exec` would receive as the same environment variable, such as `ip` and
+/// `IP`, since one would silently replace the other.
+fn check_env_names(monitor: &MonitorConfig, env_names: &EnvNames) -> Result<()> {
+    if monitor.exec.is_none() {
+        return Ok(());
+    }
+    let captures = monitor
+        .match_log
+        .iter()
+        .chain(&monitor.match_content)
+        .chain(&monitor.exec_parse)
+        .flat_map(|regex| capture_variables(regex.capture_names()))
+        .chain(
+            monitor
+                .match_log_bytes
+                .iter()
+                .flat_map(|regex| capture_variables(regex.capture_names())),
+        )
+        .map(|(_, name)| name)
+        .collect::<BTreeSet<_>>();
+    let mut variables = HashMap::new();
+    for capture in &captures {
+        let env_name = env_names.of(capture).swap_remove(0);
+        if let Some(other) = variables.insert(env_name.clone(), capture) {
+            bail!(
+                "Monitor `{}`: Captures `{other}` and `{capture}` would both be passed to `exec` as `{env_name}`.",
+                monitor.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Warns about placeholders in `exec` and `notify` that no variable of the monitor can fill, such
+/// as a misspelled capture group. They would otherwise be sent as is.
+fn warn_undefined_placeholders(monitor: &MonitorConfig) {
+    let mut variables = vec!["timestamp".to_owned()];
+    let regex_names = monitor
+        .match_log
+        .iter()
+        .chain(&monitor.match_content)
+        .flat_map(|regex| capture_variables(regex.capture_names()))
+        .chain(
+            monitor
+                .match_log_bytes
+                .iter()
+                .flat_map(|regex| capture_variables(regex.capture_names())),
+        );
+    variables.extend(regex_names.map(|(_, name)| name));
+    variables.extend(monitor.labels.keys().map(|key| format!("label_{key}")));
+    if monitor.log.as_ref().is_some_and(|log| log.batch_lines) {
+        variables.extend(["count".to_owned(), "lines".to_owned()]);
+    }
+    if monitor
+        .log
+        .as_ref()
+        .is_some_and(|log| log.dedup_consecutive)
+    {
+        variables.push("repeat_count".to_owned());
+    }
+    if !monitor.watch.is_empty() || monitor.watch_dir.is_some() {
+        variables.extend(["file".to_owned(), "change_time".to_owned()]);
+    }
+    if monitor.match_content.is_some() {
+        variables.push("skipped".to_owned());
+    }
+    if monitor.match_content_changes {
+        variables.push("matched".to_owned());
+    }
+    if monitor.watch_permissions.is_some() {
+        variables.extend(
+            ["change", "old_mode", "new_mode", "old_owner", "new_owner"].map(str::to_owned),
+        );
+    }
+    if monitor.service.is_some() {
+        variables.extend(
+            [
+                "service",
+                "unit_active_state",
+                "unit_main_pid",
+                "unit_restarts",
+            ]
+            .map(str::to_owned),
+        );
+    }
+    if monitor.severity_from_priority {
+        variables.push("severity".to_owned());
+    }
+    if monitor.service_state.is_some() {
+        variables.extend(["service".to_owned(), "state".to_owned()]);
+    }
+    if monitor.changed.is_some() {
+        variables.extend(["previous_value".to_owned(), "delta".to_owned()]);
+    }
+    if let Some((variable, _)) = &monitor.average {
+        variables.push(format!("{variable}_avg"));
+    }
+    if let Some(anomaly) = &monitor.anomaly {
+        variables.extend(
+            ["zscore", "mean", "stddev"].map(|suffix| format!("{}_{suffix}", anomaly.variable)),
+        );
+    }
+    if monitor.rate.is_some() {
+        variables.push("rate".to_owned());
+    }
+
+    let warn_undefined = |key: &str, template: &str, variables: &[String]| {
+        for name in template::placeholders(template) {
+            if !variables.iter().any(|variable| variable == name) {
+                warn!(
+                    "Monitor `{}`: `{key}` refers to `{{{{{name}}}}}`, which is never set.",
+                    monitor.name
+                );
+            }
+        }
+    };
+    // The fingerprint is rendered before `exec` runs, and is available to it.
+    if let Some(fingerprint) = &monitor.fingerprint {
+        warn_undefined("fingerprint", fingerprint, &variables);
+        variables.push("fingerprint".to_owned());
+    }
+    // A daemon is started before any event, so only the labels are set.
+    let exec_variables = match monitor.exec_mode {
+        ExecMode::Trigger => variables.clone(),
+        ExecMode::Daemon => monitor
+            .labels
+            .keys()
+            .map(|key| format!("label_{key}"))
+            .collect(),
+    };
+    match &monitor.exec {
+        None => {}
+        Some(Exec::Shell(command)) => warn_undefined("exec", command, &exec_variables),
+        Some(Exec::Spawn(args)) => {
+            for arg in args {
+                warn_undefined("exec", arg, &exec_variables);
+            }
+        }
+    }
+
+    // `exec_parse` runs before notifications are sent.
+    variables.extend(
+        monitor
+            .exec_parse
+            .iter()
+            .flat_map(|regex| capture_variables(regex.capture_names()))
+            .map(|(_, name)| name),
+    );
+    for notification in &monitor.notify {
+        warn_undefined("notify.title", &notification.title, &variables);
+        warn_undefined("notify.body", &notification.body, &variables);
+    }
+}
+
+/// Turns a `toml::de::Error` into a human-readable error message.
+fn map_to_readable_syntax_err(doc: &str, err: toml::de::Error) -> Error {
+    let mut message = err.message().to_owned();
+    // Print lines where error occurred.
+    if let Some(err_range) = err.span() {
+        let mut line_start_byte;
+        let mut line_end_byte = 0;
+        message += "\n";
+        for (i, line) in doc.lines().enumerate() {
+            line_start_byte = line_end_byte;
+            // Account for new line.
+            line_end_byte = line_start_byte + line.len() + 1;
+            // Only print the last line.
+            if line_end_byte < err_range.end {
+                continue;
+            }
+            message += &format!("\n{}:\t{line}", i + 1);
+            message += &format!(
+                "\n\t{}{}",
+                " ".repeat(err_range.start - line_start_byte),
+                "^".repeat(err_range.len())
+            );
+            break;
+        }
+    }
+    anyhow!("{message}")
+}
+
+fn parse_notify_config(default: &Table, config: Value) -> Result<NotificationConfig> {
+    let mut config_table = match config {
+        Value::Table(config_table) => config_table,
+        _ => bail!("Key must be a table."),
+    };
+
+    for (k, v) in default {
+        config_table.entry(k).or_insert(v.to_owned());
+    }
+    reject_unknown_keys(&config_table, schema::NOTIFY_KEYS)?;
+
+    let enabled = match config_table.remove("enabled") {
+        None => true,
+        Some(Value::Boolean(enabled)) => enabled,
+        Some(_) => bail!("Key `enabled` must be a boolean."),
+    };
+
+    let from = match config_table.remove("from") {
+        None => None,
+        Some(Value::String(from_str)) => Some(parse_mailbox("from", &from_str)?),
+        Some(_) => bail!("Key `from` must be a string."),
+    };
+
+    let to = match config_table.remove("to") {
+        None if from.is_some() => bail!("Key `to` must be set if `from` is set."),
+        None => None,
+        Some(_) if from.is_none() => bail!("Key `to` requires `from`."),
+        Some(Value::String(to_str)) => Some(parse_mailbox("to", &to_str)?),
+        Some(_) => bail!("Key `to` must be a string."),
+    };
+
+    let smtp_host = match config_table.remove("smtp_host") {
+        None => None,
+        Some(_) if from.is_none() => bail!("Key `smtp_host` requires `from`."),
+        Some(Value::String(host)) => Some(host),
+        Some(_) => bail!("Key `smtp_host` must be a string."),
+    };
+
+    let username = match config_table.remove("username") {
+        None if smtp_host.is_some() => bail!("Key `username` must be set if `smtp_host` is set."),
+        None => None,
+        Some(_) if smtp_host.is_none() => bail!("Key `username` requires `smtp_host`."),
+        Some(Value::String(username)) => Some(username),
+        Some(_) => bail!("Key `username` must be a string."),
+    };
+
+    let password = match config_table.remove("password") {
+        None if smtp_host.is_some() => bail!("Key `password` must be set if `smtp_host` is set."),
+        None => None,
+        Some(_) if smtp_host.is_none() => bail!("Key `password` requires `smtp_host`."),
+        Some(Value::String(password)) => Some(password),
+        Some(_) => bail!("Key `password` must be a string."),
+    };
+
+    let login = match (smtp_host, username, password) {
+        (Some(host), Some(username), Some(password)) => Some(SmtpLogin {
+            host,
+            username,
+            password,
+        }),
+        _ => None,
+    };
+    let smtp = from
+        .zip(to)
+        .map(|(from, to)| SmtpConfig { from, to, login });
+
+    let webhook = match config_table.remove("webhook") {
+        None => None,
+        Some(Value::String(url)) => Some(
+            url.parse()
+                .map_err(|err| anyhow!("Failed to parse `webhook`: {err}"))?,
+        ),
+        Some(_) => bail!("Key `webhook` must be a string."),
+    };
+
+    let escape = match config_table.remove("escape") {
+        None => Escape::default(),
+        Some(Value::String(escape)) => match escape.as_str() {
+            "none" => Escape::None,
+            "html" => Escape::Html,
+            "slack" => Escape::Slack,
+            _ => bail!("Key `escape` must be one of none, html, or slack."),
+        },
+        Some(_) => bail!("Key `escape` must be a string."),
+    };
+
+    let _aggregate = match config_table.remove("aggregate") {
+        None => None,
+        Some(Value::String(aggregate)) => Some(parse_duration("aggregate", &aggregate)?),
+        Some(_) => bail!("Key `aggregate` must be a string."),
+    };
+
+    let schedule = match config_table.remove("schedule") {
+        None => Vec::new(),
+        Some(Value::Array(entries)) => entries
+            .into_iter()
+            .map(parse_schedule_entry)
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `schedule` must be an array of tables."),
+    };
+
+    let timezone = config_table
+        .remove("timezone")
+        .map(parse_timezone)
+        .transpose()?;
+
+    let escalate = match config_table.remove("escalate") {
+        None => Vec::new(),
+        Some(Value::Array(tiers)) => tiers
+            .into_iter()
+            .map(parse_escalation_tier)
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `escalate` must be an array of tables."),
+    };
+
+    let spool_dir = match config_table.remove("spool_dir") {
+        None => None,
+        Some(Value::String(dir)) => Some(PathBuf::from(dir)),
+        Some(_) => bail!("Key `spool_dir` must be a string."),
+    };
+
+    assert_table_is_empty(config_table, &schema::names(schema::NOTIFY_KEYS))?;
+
+    Ok(NotificationConfig {
+        enabled,
+        smtp,
+        webhook,
+        escape,
+        schedule,
+        timezone,
+        escalate,
+        spool_dir,
+    })
+}
+
+fn parse_http_config(mut http_table: Table) -> Result<HttpConfig> {
+    let proxy = match http_table.remove("proxy") {
+        None => None,
+        Some(Value::String(proxy)) => {
+            let url = Url::parse(&proxy)
+                .map_err(|err| anyhow!("Key `http.proxy` has invalid URL {proxy:?}: {err}"))?;
+            if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+                bail!("Key `http.proxy` must be an http:// or https:// URL with a host.");
+            }
+            Some(url)
+        }
+        Some(_) => bail!("Key `http.proxy` must be a string."),
+    };
+    let ca_certificates = match http_table.remove("ca_bundle") {
+        None => Vec::new(),
+        Some(Value::String(path)) => {
+            let pem = fs::read(&path)
+                .map_err(|err| anyhow!("Failed to read `http.ca_bundle` {path:?}: {err}"))?;
+            let certificates = Certificate::from_pem_bundle(&pem)
+                .map_err(|err| anyhow!("Failed to parse `http.ca_bundle` {path:?}: {err}"))?;
+            if certificates.is_empty() {
+                bail!("Key `http.ca_bundle`: {path:?} has no PEM certificates.");
+            }
+            certificates
+        }
+        Some(_) => bail!("Key `http.ca_bundle` must be a string."),
+    };
+    let insecure = match http_table.remove("insecure") {
+        None => false,
+        Some(Value::Boolean(insecure)) => insecure,
+        Some(_) => bail!("Key `http.insecure` must be a boolean."),
+    };
+    assert_table_is_empty(http_table, &["proxy", "ca_bundle", "insecure"])?;
+    Ok(HttpConfig {
+        proxy,
+        ca_certificates,
+        insecure,
+    })
+}
+
+/// Reads the `[notify.*]` tables from the secrets file, and the lines their keys are on. A missing
+/// file is an error so that notifications aren't silently sent without credentials.
+fn load_secrets(path: &str) -> Result<(Table, KeyLines)> {
+    let metadata =
+        fs::metadata(path).map_err(|err| anyhow!("Failed to read secrets file {path:?}: {err}"))?;
+    if metadata.permissions().mode() & 0o004 != 0 {
+        warn!("Secrets file {path:?} is world-readable.");
+    }
+    let doc = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Failed to read secrets file {path:?}: {err}"))?;
+    let mut secrets = doc.parse::<Table>().map_err(|err| {
+        anyhow!(
+            "Secrets file {path:?}: {}",
+            map_to_readable_syntax_err(&doc, err)
+        )
+    })?;
+    let lines = KeyLines::new(&doc, &secrets);
+    let notify = match secrets.remove("notify") {
+        None => Table::new(),
+        Some(Value::Table(notify)) => notify,
+        Some(_) => bail!("Secrets file {path:?}: Key `notify` must be a table."),
+    };
+    assert_table_is_empty(secrets, &["notify"])
+        .map_err(|err| anyhow!("Secrets file {path:?}: {err}"))?;
+    Ok((notify, lines))
+}
+
+/// Merges the secrets into the notify configs. Keys in the secrets file take precedence.
+fn overlay_secrets(notify: &mut Table, secrets: Table) -> Result<()> {
+    for (name, secret_config) in secrets {
+        let Value::Table(secret_config) = secret_config else {
+            bail!("Key `notify.{name}` must be a table.");
+        };
+        let config = notify
+            .entry(name.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        let Value::Table(config) = config else {
+            bail!("Key `notify.{name}` must be a table.");
+        };
+        config.extend(secret_config);
+    }
+    Ok(())
+}
+
+/// Rejects `journalctl` flags that would change which unit is followed, or stop it from following.
+fn check_service_arg(arg: &str) -> Result<()> {
+    const LONG_FLAGS: &[&str] = &["--unit", "--user-unit", "--follow", "--lines"];
+    const SHORT_FLAGS: &[char] = &['u', 'f', 'n'];
+
+    let overrides = match arg.strip_prefix("--") {
+        Some(_) => LONG_FLAGS
+            .iter()
+            .any(|flag| arg == *flag || arg.starts_with(&format!("{flag}="))),
+        None => short_flags(arg).any(|flag| SHORT_FLAGS.contains(&flag)),
+    };
+    if overrides {
+        bail!(
+            "Key `service_args` must not contain {arg:?}, as Ramon sets the unit and follows it."
+        );
+    }
+    Ok(())
+}
+
+/// Splits a cluster of short `journalctl` flags, such as `-rf`, into its flags. A flag that takes
+/// a value ends the cluster, since the rest of the argument is its value, e.g. `-tfoo`.
+fn short_flags(arg: &str) -> impl Iterator<Item = char> + '_ {
+    const VALUE_FLAGS: &[char] = &[
+        'b', 'c', 'D', 'F', 'g', 'i', 'M', 'n', 'o', 'p', 'S', 't', 'T', 'u', 'U',
+    ];
+
+    let flags = arg
+        .strip_prefix('-')
+        .filter(|flags| !flags.starts_with('-'))
+        .unwrap_or_default();
+    let mut done = false;
+    flags.chars().take_while(move |&flag| {
+        let take = !done;
+        done |= VALUE_FLAGS.contains(&flag);
+        take
+    })
+}
+
+/// Returns whether the `journalctl` argument sets the output format.
+fn is_output_arg(arg: &str) -> bool {
+    arg == "--output" || arg.starts_with("--output=") || short_flags(arg).any(|flag| flag == 'o')
+}
+
+/// Returns the index of each capture group and the variable it's stored in: its name, or its
+/// number if it's unnamed. The whole match isn't stored.
+pub fn capture_variables<'a>(
+    names: impl Iterator<Item = Option<&'a str>> + 'a,
+) -> impl Iterator<Item = (usize, String)> + 'a {
+    names
+        .enumerate()
+        .skip(1)
+        .map(|(i, name)| (i, name.map_or_else(|| i.to_string(), str::to_owned)))
+}
+
+/// Compiles a regex the same way for config keys and `ramon test-regex`.
+pub fn parse_regex(key: &str, pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|err| anyhow!("Failed to parse {key}: {err}"))
+}
+
+fn parse_notification(value: Value) -> Result<Notification> {
+    match value {
+        Value::String(title) => Ok(Notification {
+            r#type: "default".to_owned(),
+            title,
+            body: String::new(),
+            tail: None,
+        }),
+        Value::Table(mut notification_table) => Ok(Notification {
+            r#type: match notification_table.remove("type") {
+                None => "default".to_owned(),
+                Some(Value::String(t)) => t,
+                Some(_) => bail!("Key `type` must be a string."),
+            },
+            title: match notification_table.remove("title") {
+                None => "Ramon Notification".to_owned(),
+                Some(Value::String(title)) => title,
+                Some(_) => bail!("Key `title` must be a string."),
+            },
+            body: match notification_table.remove("body") {
+                None => String::new(),
+                Some(Value::String(body)) => body,
+                Some(_) => bail!("Key `body` must be a string."),
+            },
+            tail: match notification_table.remove("tail") {
+                None => None,
+                Some(Value::Integer(tail)) if tail > 0 => Some(tail as usize),
+                Some(_) => bail!("Key `tail` must be a positive integer."),
+            },
+        }),
+        _ => bail!("Key `notify` must be a string, a table, or an array of them."),
+    }
+}
+
+/// Parses an email address with an optional display name, e.g. `Ramon <ramon@example.com>`.
+fn parse_mailbox(key: &str, mailbox: &str) -> Result<Mailbox> {
+    mailbox.parse().map_err(|err| {
+        anyhow!(
+            "Failed to parse `{key}` {mailbox:?}: {err}. Expected `user@example.com` or \
+             `Name <user@example.com>`, with the name in double quotes if it contains \
+             punctuation, e.g. `\"Ops, Team\" <ops@example.com>`."
+        )
+    })
+}
+
+fn parse_timezone(value: Value) -> Result<Tz> {
+    match value {
+        Value::String(timezone) => timezone
+            .parse()
+            .map_err(|_| anyhow!("Key `timezone` has unknown timezone {timezone:?}.")),
+        _ => bail!("Key `timezone` must be a string."),
+    }
+}
+
+fn parse_schedule_entry(entry: Value) -> Result<ScheduleEntry> {
+    let mut entry_table = match entry {
+        Value::Table(entry_table) => entry_table,
+        _ => bail!("Key `schedule` must be an array of tables."),
+    };
+
+    let mut parse_time = |key| match entry_table.remove(key) {
+        None => bail!("Key `{key}` must be set in each schedule entry."),
+        Some(Value::String(time)) => NaiveTime::parse_from_str(&time, "%H:%M").map_err(|err| {
+            anyhow!("Key `{key}` has invalid time {time:?} (expected HH:MM): {err}")
+        }),
+        Some(_) => bail!("Key `{key}` must be a string."),
+    };
+    let from = parse_time("from")?;
+    let to = parse_time("to")?;
+
+    let target = match entry_table.remove("target") {
+        None => bail!("Key `target` must be set in each schedule entry."),
+        Some(Value::String(target)) => target,
+        Some(_) => bail!("Key `target` must be a string."),
+    };
+
+    assert_table_is_empty(entry_table, &["from", "to", "target"])?;
+
+    Ok(ScheduleEntry { from, to, target })
+}
+
+fn parse_escalation_tier(tier: Value) -> Result<EscalationTier> {
+    let mut tier_table = match tier {
+        Value::Table(tier_table) => tier_table,
+        _ => bail!("Key `escalate` must be an array of tables."),
+    };
+
+    let after = match tier_table.remove("after") {
+        None => bail!("Key `after` must be set in each escalation tier."),
+        Some(Value::Integer(after)) if after >= 0 => after as usize,
+        Some(_) => bail!("Key `after` must be a non-negative integer."),
+    };
+
+    let within = match tier_table.remove("within") {
+        None => bail!("Key `within` must be set in each escalation tier."),
+        Some(Value::String(within)) => parse_duration("within", &within)?,
+        Some(_) => bail!("Key `within` must be a string."),
+    };
+
+    let target = match tier_table.remove("target") {
+        None => bail!("Key `target` must be set in each escalation tier."),
+        Some(Value::String(target)) => target,
+        Some(_) => bail!("Key `target` must be a string."),
+    };
+
+    assert_table_is_empty(tier_table, &["after", "within", "target"])?;
+
+    Ok(EscalationTier {
+        after,
+        within,
+        target,
+    })
+}
+
+fn parse_monitor_config(
+    name: String,
+    mut monitor_table: Table,
+    commands: &HashMap<String, Exec>,
+) -> Result<MonitorConfig> {
+    reject_unknown_keys(&monitor_table, schema::MONITOR_KEYS)?;
+
+    let every = match monitor_table.remove("every") {
+        None => None,
+        Some(Value::String(every)) => Some(interval(parse_duration("every", &every)?)),
+        Some(_) => bail!("Key `every` must be a string."),
+    };
+
+    let missed_ticks = match monitor_table.remove("missed_ticks") {
+        None => MissedTickBehavior::Skip,
+        Some(_) if every.is_none() => bail!("Key `missed_ticks` requires `every`."),
+        Some(Value::String(missed_ticks)) => match missed_ticks.as_str() {
+            "skip" => MissedTickBehavior::Skip,
+            "delay" => MissedTickBehavior::Delay,
+            "burst" => MissedTickBehavior::Burst,
+            _ => bail!("Key `missed_ticks` must be one of skip, delay, or burst."),
+        },
+        Some(_) => bail!("Key `missed_ticks` must be a string."),
+    };
+
+    let skip_if_running = match monitor_table.remove("skip_if_running") {
+        None => false,
+        Some(Value::Boolean(skip_if_running)) => skip_if_running,
+        Some(_) => bail!("Key `skip_if_running` must be a boolean."),
+    };
+
+    let log_path: Option<PathBuf> = match monitor_table.remove("log") {
+        None => None,
+        Some(Value::String(log)) => Some(log.into()),
+        Some(_) => bail!("Key `log` must be a string."),
+    };
+
+    let binary = match monitor_table.remove("binary") {
+        None => false,
+        Some(Value::Boolean(binary)) => binary,
+        Some(_) => bail!("Key `binary` must be a boolean."),
+    };
+
+    let reopen_timeout = match monitor_table.remove("reopen_timeout") {
+        None => Some(Duration::from_secs(1)),
+        Some(Value::String(timeout)) if timeout == "forever" => None,
+        Some(Value::String(timeout)) => Some(parse_duration("reopen_timeout", &timeout)?),
+        Some(_) => bail!("Key `reopen_timeout` must be a string."),
+    };
+
+    let watch = match monitor_table.remove("watch") {
+        None => Vec::new(),
+        Some(Value::String(glob)) => vec![glob],
+        Some(Value::Array(globs)) => globs
+            .into_iter()
+            .map(|glob| match glob {
+                Value::String(glob) => Ok(glob),
+                _ => bail!("Key `watch` must be a string or an array of strings."),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `watch` must be a string or an array of strings."),
+    };
+
+    let watch_debounce = match monitor_table.remove("watch_debounce") {
+        None => None,
+        Some(_) if watch.is_empty() => bail!("Key `watch_debounce` requires `watch`."),
+        Some(Value::String(debounce)) => Some(parse_duration("watch_debounce", &debounce)?),
+        Some(_) => bail!("Key `watch_debounce` must be a string."),
+    };
+
+    let watch_dir = match monitor_table.remove("watch_dir") {
+        None => None,
+        Some(Value::String(dir)) => Some(PathBuf::from(dir)),
+        Some(_) => bail!("Key `watch_dir` must be a string."),
+    };
+
+    let settle_time = match monitor_table.remove("settle_time") {
+        None => Duration::from_secs(1),
+        Some(_) if watch_dir.is_none() => bail!("Key `settle_time` requires `watch_dir`."),
+        Some(Value::String(time)) => parse_duration("settle_time", &time)?,
+        Some(_) => bail!("Key `settle_time` must be a string."),
+    };
+
+    let match_content = match monitor_table.remove("match_content") {
+        None => None,
+        Some(_) if watch.is_empty() && watch_dir.is_none() => {
+            bail!("Key `match_content` requires `watch` or `watch_dir`.")
+        }
+        Some(Value::String(pattern)) => Some(parse_regex("match_content", &pattern)?),
+        Some(_) => bail!("Key `match_content` must be a string."),
+    };
+
+    let match_content_changes = match monitor_table.remove("match_content_changes") {
+        None | Some(Value::Boolean(false)) => false,
+        Some(_) if match_content.is_none() => {
+            bail!("Key `match_content_changes` requires `match_content`.")
+        }
+        // Files in `watch_dir` are new, so they have no earlier content to compare against.
+        Some(_) if watch.is_empty() => bail!("Key `match_content_changes` requires `watch`."),
+        Some(Value::Boolean(true)) => true,
+        Some(_) => bail!("Key `match_content_changes` must be a boolean."),
+    };
+
+    let max_file_size = match monitor_table.remove("max_file_size") {
+        None => 1024 * 1024,
+        Some(_) if match_content.is_none() => {
+            bail!("Key `max_file_size` requires `match_content`.")
+        }
+        Some(Value::Integer(max)) if max > 0 => max as u64,
+        Some(_) => bail!("Key `max_file_size` must be a positive integer."),
+    };
+
+    let fire_when_skipped = match monitor_table.remove("fire_when_skipped") {
+        None => false,
+        Some(_) if match_content.is_none() => {
+            bail!("Key `fire_when_skipped` requires `match_content`.")
+        }
+        Some(Value::Boolean(fire)) => fire,
+        Some(_) => bail!("Key `fire_when_skipped` must be a boolean."),
+    };
+
+    let poll_interval = match monitor_table.remove("poll_interval") {
+        None => None,
+        Some(_) if log_path.is_none() && watch.is_empty() && watch_dir.is_none() => {
+            bail!("Key `poll_interval` requires `log`, `watch`, or `watch_dir`.")
+        }
+        Some(Value::String(interval)) => Some(parse_duration("poll_interval", &interval)?),
+        Some(_) => bail!("Key `poll_interval` must be a string."),
+    };
+
+    let watch_permissions = match monitor_table.remove("watch_permissions") {
+        None | Some(Value::Boolean(false)) => None,
+        Some(_) if watch.is_empty() => bail!("Key `watch_permissions` requires `watch`."),
+        // The poll watcher only compares modification times.
+        Some(_) if poll_interval.is_some() => {
+            bail!("Key `watch_permissions` can't be used with `poll_interval`.")
+        }
+        Some(Value::Boolean(true)) => Some(WatchPermissions::default()),
+        Some(_) => bail!("Key `watch_permissions` must be a boolean."),
+    };
+
+    let expected_mode = match monitor_table.remove("expected_mode") {
+        None => None,
+        Some(_) if watch_permissions.is_none() => {
+            bail!("Key `expected_mode` requires `watch_permissions`.")
+        }
+        Some(Value::String(mode)) => match u32::from_str_radix(&mode, 8) {
+            Ok(mode) if mode <= 0o7777 => Some(mode),
+            _ => bail!("Key `expected_mode` must be an octal mode, such as \"0644\"."),
+        },
+        Some(_) => bail!("Key `expected_mode` must be a string."),
+    };
+
+    let expected_owner = match monitor_table.remove("expected_owner") {
+        None => None,
+        Some(_) if watch_permissions.is_none() => {
+            bail!("Key `expected_owner` requires `watch_permissions`.")
+        }
+        Some(Value::String(owner)) => Some(parse_owner(&owner)?),
+        Some(_) => bail!("Key `expected_owner` must be a string."),
+    };
+
+    let watch_permissions = watch_permissions.map(|_| WatchPermissions {
+        mode: expected_mode,
+        uid: expected_owner.map(|(uid, _)| uid),
+        gid: expected_owner.and_then(|(_, gid)| gid),
+    });
+
+    let service = match monitor_table.remove("service") {
+        None => None,
+        Some(Value::String(service)) => Some(service),
+        Some(_) => bail!("Key `service` must be a string."),
+    };
+
+    let ssh = match monitor_table.remove("ssh") {
+        None => None,
+        Some(Value::Table(mut ssh_table)) => {
+            let host = match ssh_table.remove("host") {
+                None => bail!("Key `ssh.host` must be set."),
+                Some(Value::String(host)) if host.starts_with('-') => {
+                    bail!("Key `ssh.host` must not start with `-`.")
+                }
+                Some(Value::String(host)) => host,
+                Some(_) => bail!("Key `ssh.host` must be a string."),
+            };
+            let path = match ssh_table.remove("path") {
+                None => bail!("Key `ssh.path` must be set."),
+                Some(Value::String(path)) => path,
+                Some(_) => bail!("Key `ssh.path` must be a string."),
+            };
+            let key = match ssh_table.remove("key") {
+                None => None,
+                Some(Value::String(key)) => Some(key.into()),
+                Some(_) => bail!("Key `ssh.key` must be a string."),
+            };
+            assert_table_is_empty(ssh_table, &["host", "path", "key"])?;
+            Some(SshConfig { host, path, key })
+        }
+        Some(_) => bail!("Key `ssh` must be a table."),
+    };
+
+    let service_args = match monitor_table.remove("service_args") {
+        None => Vec::new(),
+        Some(_) if service.is_none() => bail!("Key `service_args` requires `service`."),
+        Some(Value::Array(args)) => args
+            .into_iter()
+            .map(|arg| match arg {
+                Value::String(arg) => {
+                    check_service_arg(&arg)?;
+                    Ok(arg)
+                }
+                _ => bail!("Key `service_args` must be an array of strings."),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `service_args` must be an array of strings."),
+    };
+
+    let severity_from_priority = match monitor_table.remove("severity_from_priority") {
+        None => false,
+        Some(_) if service.is_none() => bail!("Key `severity_from_priority` requires `service`."),
+        Some(Value::Boolean(severity_from_priority)) => severity_from_priority,
+        Some(_) => bail!("Key `severity_from_priority` must be a boolean."),
+    };
+    if severity_from_priority && service_args.iter().any(|arg| is_output_arg(arg)) {
+        bail!("Key `service_args` must not set the output format with `severity_from_priority`.");
+    }
+
+    let service_state = match monitor_table.remove("service_state") {
+        None => None,
+        Some(Value::String(service)) => Some(service),
+        Some(_) => bail!("Key `service_state` must be a string."),
+    };
+
+    let down_for = match monitor_table.remove("down_for") {
+        None => Duration::ZERO,
+        Some(_) if service_state.is_none() => bail!("Key `down_for` requires `service_state`."),
+        Some(Value::String(down_for)) => parse_duration("down_for", &down_for)?,
+        Some(_) => bail!("Key `down_for` must be a string."),
+    };
+
+    let circuit_breaker = match monitor_table.remove("circuit_breaker") {
+        None => None,
+        Some(Value::Table(mut breaker_table)) => {
+            let errors = match breaker_table.remove("errors") {
+                None => 5,
+                Some(Value::Integer(errors)) if errors > 0 => errors as usize,
+                Some(_) => bail!("Key `circuit_breaker.errors` must be a positive integer."),
+            };
+            let within = match breaker_table.remove("within") {
+                None => Duration::from_secs(60),
+                Some(Value::String(within)) => parse_duration("circuit_breaker.within", &within)?,
+                Some(_) => bail!("Key `circuit_breaker.within` must be a string."),
+            };
+            let pause = match breaker_table.remove("pause") {
+                None => Duration::from_secs(300),
+                Some(Value::String(pause)) => parse_duration("circuit_breaker.pause", &pause)?,
+                Some(_) => bail!("Key `circuit_breaker.pause` must be a string."),
+            };
+            assert_table_is_empty(breaker_table, &["errors", "within", "pause"])?;
+            Some(CircuitBreakerConfig {
+                errors,
+                within,
+                pause,
+            })
+        }
+        Some(_) => bail!("Key `circuit_breaker` must be a table."),
+    };
+
+    if binary && (log_path.is_none() || service.is_some()) {
+        bail!("Key `binary` can only be used with `log`.");
+    }
+
+    let batch_lines = match monitor_table.remove("batch_lines") {
+        None => false,
+        Some(_) if log_path.is_none() || binary => {
+            bail!("Key `batch_lines` can only be used with a text `log`.")
+        }
+        Some(Value::Boolean(batch_lines)) => batch_lines,
+        Some(_) => bail!("Key `batch_lines` must be a boolean."),
+    };
+
+    let dedup_consecutive = match monitor_table.remove("dedup_consecutive") {
+        None => false,
+        Some(_) if log_path.is_none() || binary => {
+            bail!("Key `dedup_consecutive` can only be used with a text `log`.")
+        }
+        Some(_) if batch_lines => {
+            bail!("Keys `dedup_consecutive` and `batch_lines` can't be used together.")
+        }
+        Some(Value::Boolean(dedup_consecutive)) => dedup_consecutive,
+        Some(_) => bail!("Key `dedup_consecutive` must be a boolean."),
+    };
+
+    let line_ending = match monitor_table.remove("line_ending") {
+        None => LineEnding::default(),
+        Some(_) if log_path.is_none() || binary => {
+            bail!("Key `line_ending` can only be used with a text `log`.")
+        }
+        Some(Value::String(line_ending)) => match line_ending.as_str() {
+            "auto" => LineEnding::Auto,
+            "lf" => LineEnding::Lf,
+            "crlf" => LineEnding::Crlf,
+            _ => bail!("Key `line_ending` must be \"auto\", \"lf\", or \"crlf\"."),
+        },
+        Some(_) => bail!("Key `line_ending` must be a string."),
+    };
+
+    let timestamp_regex = match monitor_table.remove("timestamp_regex") {
+        None => None,
+        Some(Value::String(pattern)) => Some(parse_regex("timestamp_regex", &pattern)?),
+        Some(_) => bail!("Key `timestamp_regex` must be a string."),
+    };
+
+    let timestamp_format = match monitor_table.remove("timestamp_format") {
+        None => None,
+        Some(Value::String(format)) => Some(format),
+        Some(_) => bail!("Key `timestamp_format` must be a string."),
+    };
+
+    let replay = match monitor_table.remove("replay_since") {
+        None => {
+            if timestamp_regex.is_some() || timestamp_format.is_some() {
+                bail!("Keys `timestamp_regex` and `timestamp_format` require `replay_since`.");
+            }
+            None
+        }
+        Some(_) if log_path.is_none() || binary => {
+            bail!("Key `replay_since` can only be used with a text `log`.")
+        }
+        Some(Value::String(since)) => Some(ReplayConfig {
+            since: parse_duration("replay_since", &since)?,
+            timestamp_regex: timestamp_regex
+                .ok_or_else(|| anyhow!("Key `replay_since` requires `timestamp_regex`."))?,
+            timestamp_format,
+        }),
+        Some(_) => bail!("Key `replay_since` must be a string."),
+    };
+
+    let long_lines = match monitor_table.remove("long_lines") {
+        None => None,
+        Some(Value::String(long_lines)) => match long_lines.as_str() {
+            "truncate" => Some(LongLines::Truncate),
+            "skip" => Some(LongLines::Skip),
+            _ => bail!("Key `long_lines` must be one of truncate or skip."),
+        },
+        Some(_) => bail!("Key `long_lines` must be a string."),
+    };
+
+    let line_limit = match monitor_table.remove("max_line_length") {
+        None => {
+            if long_lines.is_some() {
+                bail!("Key `long_lines` requires `max_line_length`.");
+            }
+            None
+        }
+        Some(_) if binary || (log_path.is_none() && service.is_none() && ssh.is_none()) => {
+            bail!("Key `max_line_length` can only be used with a text `log`, `service`, or `ssh`.")
+        }
+        Some(Value::Integer(max)) if max > 0 => Some(LineLimit {
+            max: max as usize,
+            action: long_lines.unwrap_or_default(),
+        }),
+        Some(_) => bail!("Key `max_line_length` must be a positive integer."),
+    };
+
+    let log = log_path.map(|path| LogConfig {
+        path,
+        binary,
+        batch_lines,
+        dedup_consecutive,
+        reopen_timeout,
+        poll_interval,
+        replay,
+        line_limit,
+        line_ending,
+    });
+
+    let severity = match monitor_table.remove("severity") {
+        None => Severity::default(),
+        Some(Value::String(severity)) => severity.parse()?,
+        Some(_) => bail!("Key `severity` must be a string."),
+    };
+
+    let labels = match monitor_table.remove("labels") {
+        None => HashMap::new(),
+        Some(Value::Table(labels)) => labels
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => Ok((key, value)),
+                _ => bail!("Label `{key}` must be a string."),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `labels` must be a table."),
+    };
+
+    let group = match monitor_table.remove("group") {
+        None => None,
+        Some(Value::String(group)) => Some(group),
+        Some(_) => bail!("Key `group` must be a string."),
+    };
+
+    let timezone = match monitor_table.remove("timezone") {
+        None => Tz::UTC,
+        Some(timezone) => parse_timezone(timezone)?,
+    };
+
+    let first_match_only = match monitor_table.remove("first_match_only") {
+        None => false,
+        Some(Value::Boolean(first_match_only)) => first_match_only,
+        Some(_) => bail!("Key `first_match_only` must be a boolean."),
+    };
+
+    let cooldown = match monitor_table.remove("cooldown") {
+        None => None,
+        Some(Value::String(cooldown)) => Some(parse_duration("cooldown", &cooldown)?),
+        Some(_) => bail!("Key `cooldown` must be a string."),
+    };
+
+    let cooldown_mode = match monitor_table.remove("cooldown_mode") {
+        None => CooldownMode::default(),
+        Some(_) if cooldown.is_none() => bail!("Key `cooldown_mode` requires `cooldown`."),
+        Some(Value::String(mode)) => match mode.as_str() {
+            "trailing" => CooldownMode::Trailing,
+            "leading" => CooldownMode::Leading,
+            _ => bail!("Key `cooldown_mode` must be \"trailing\" or \"leading\"."),
+        },
+        Some(_) => bail!("Key `cooldown_mode` must be a string."),
+    };
+
+    let warmup = match monitor_table.remove("warmup") {
+        None => None,
+        Some(Value::String(warmup)) => Some(parse_duration("warmup", &warmup)?),
+        Some(_) => bail!("Key `warmup` must be a string."),
+    };
+
+    let (match_log, match_log_bytes) = match monitor_table.remove("match_log") {
+        None => (None, None),
+        Some(Value::String(log_regex_str)) if binary => (
+            None,
+            Some(
+                regex::bytes::Regex::new(&log_regex_str)
+                    .map_err(|err| anyhow!("Failed to parse match_log: {err}"))?,
+            ),
+        ),
+        Some(Value::String(log_regex_str)) => {
+            (Some(parse_regex("match_log", &log_regex_str)?), None)
+        }
+        Some(_) => bail!("Key `match_log` must be a string."),
+    };
+
+    let (ignore_log, ignore_log_bytes) = match monitor_table.remove("ignore_log") {
+        None => (None, None),
+        Some(Value::String(ignore_log_regex_str)) if binary => (
+            None,
+            Some(
+                regex::bytes::Regex::new(&ignore_log_regex_str)
+                    .map_err(|err| anyhow!("Failed to parse ignore_log: {err}"))?,
+            ),
+        ),
+        Some(Value::String(ignore_log_regex_str)) => (
+            Some(parse_regex("ignore_log", &ignore_log_regex_str)?),
+            None,
+        ),
+        Some(_) => bail!("Key `ignore_log` must be a string."),
+    };
+
+    let transform = match monitor_table.remove("transform") {
+        None => Vec::new(),
+        Some(Value::Table(transform_table)) => transform_table
+            .into_iter()
+            .map(|(variable, transform)| {
+                let Value::Table(mut transform) = transform else {
+                    bail!("Key `transform.{variable}` must be a table.");
+                };
+                let regex = match transform.remove("regex") {
+                    None => bail!("Key `transform.{variable}.regex` must be set."),
+                    Some(Value::String(pattern)) => {
+                        parse_regex(&format!("transform.{variable}.regex"), &pattern)?
+                    }
+                    Some(_) => bail!("Key `transform.{variable}.regex` must be a string."),
+                };
+                let replace = match transform.remove("replace") {
+                    None => bail!("Key `transform.{variable}.replace` must be set."),
+                    Some(Value::String(replace)) => replace,
+                    Some(_) => bail!("Key `transform.{variable}.replace` must be a string."),
+                };
+                assert_table_is_empty(transform, &["regex", "replace"])?;
+                Ok(Transform {
+                    variable,
+                    regex,
+                    replace,
+                })
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `transform` must be a table."),
+    };
+
+    let ignore_if = match monitor_table.remove("ignore_if") {
+        None => Vec::new(),
+        Some(Value::Table(ignore_table)) => ignore_table
+            .into_iter()
+            .map(|(variable, pattern)| match pattern {
+                Value::String(pattern) => {
+                    let regex = Regex::new(&pattern)
+                        .map_err(|err| anyhow!("Failed to parse ignore_if.{variable}: {err}"))?;
+                    Ok((variable, regex))
+                }
+                _ => bail!("Key `ignore_if.{variable}` must be a string."),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `ignore_if` must be a table."),
+    };
+
+    let unique = match monitor_table.remove("unique") {
+        None => None,
+        Some(Value::String(unique)) => Some(unique),
+        Some(_) => bail!("Key `unique` must be a string."),
+    };
+
+    let unique_per_file = match monitor_table.remove("unique_per_file") {
+        None => false,
+        Some(Value::Boolean(true)) if unique.is_none() => {
+            bail!("Key `unique_per_file` requires `unique`.")
+        }
+        Some(Value::Boolean(true)) if watch.is_empty() => {
+            bail!("Key `unique_per_file` requires `watch`.")
+        }
+        Some(Value::Boolean(per_file)) => per_file,
+        Some(_) => bail!("Key `unique_per_file` must be a boolean."),
+    };
+
+    let changed = match monitor_table.remove("changed") {
+        None => None,
+        Some(Value::String(changed)) => Some(changed),
+        Some(_) => bail!("Key `changed` must be a string."),
+    };
+
+    let r#if = match monitor_table.remove("if") {
+        None => None,
+        Some(Value::String(expr)) => {
+            Some(Condition::parse(&expr).map_err(|err| anyhow!("Failed to parse `if`: {err}"))?)
+        }
+        Some(_) => bail!("Key `if` must be a string."),
+    };
+
+    let rate = match monitor_table.remove("rate") {
+        None => None,
+        Some(Value::Table(mut rate_table)) => {
+            let window = match rate_table.remove("window") {
+                None => bail!("Key `rate.window` must be set."),
+                Some(Value::String(window)) => parse_duration("rate.window", &window)?,
+                Some(_) => bail!("Key `rate.window` must be a string."),
+            };
+            let above = match rate_table.remove("above") {
+                None => bail!("Key `rate.above` must be set."),
+                Some(Value::Integer(above)) if above >= 0 => above as usize,
+                Some(_) => bail!("Key `rate.above` must be a non-negative integer."),
+            };
+            assert_table_is_empty(rate_table, &["window", "above"])?;
+            Some((window, above))
+        }
+        Some(_) => bail!("Key `rate` must be a table."),
+    };
+
+    let threshold = match monitor_table.remove("threshold") {
+        None => None,
+        Some(Value::String(threshold)) => {
+            let split = threshold.split("/").collect::<Vec<&str>>();
             let (threshold, duration) = match split.len() {
                 1 => match &every {
                     None => bail!("Invalid format for threshold: `every` key must be set."),
                     Some(interval) => {
-                        let duration = duration_str::parse(split[0])
-                            .map_err(|err| anyhow!("Failed to parse threshold duration: {err}"))?;
+                        let duration = parse_duration("threshold", split[0])?;
                         let threshold = duration.as_millis() / interval.period().as_millis();
                         (threshold as usize, duration)
                     }
@@ -290,8 +2365,7 @@ fn parse_monitor_config(name: String, mut monitor_table: Table) -> Result<Monito
                     let threshold = split[0]
                         .parse()
                         .map_err(|err| anyhow!("Failed to parse threshold: {err}"))?;
-                    let duration = duration_str::parse(split[1])
-                        .map_err(|err| anyhow!("Failed to parse threshold duration: {err}"))?;
+                    let duration = parse_duration("threshold", split[1])?;
                     (threshold, duration)
                 }
                 _ => bail!("Invalid format for threshold."),
@@ -301,63 +2375,405 @@ fn parse_monitor_config(name: String, mut monitor_table: Table) -> Result<Monito
         Some(_) => bail!("Key `threshold` must be a string."),
     };
 
+    let require = match monitor_table.remove("require") {
+        None => None,
+        Some(Value::Table(mut require_table)) => {
+            let mut monitor_names = |key: &str| match require_table.remove(key) {
+                None => Ok(Vec::new()),
+                Some(Value::Array(names)) => names
+                    .into_iter()
+                    .map(|name| match name {
+                        Value::String(name) => Ok(name),
+                        _ => bail!("Key `require.{key}` must be an array of monitor names."),
+                    })
+                    .collect(),
+                Some(_) => bail!("Key `require.{key}` must be an array of monitor names."),
+            };
+            let all = monitor_names("all")?;
+            let any = monitor_names("any")?;
+            if all.is_empty() && any.is_empty() {
+                bail!("Key `require` must have `all` or `any`.");
+            }
+            let within = match require_table.remove("within") {
+                None => Duration::from_secs(60),
+                Some(Value::String(within)) => parse_duration("require.within", &within)?,
+                Some(_) => bail!("Key `require.within` must be a string."),
+            };
+            assert_table_is_empty(require_table, &["all", "any", "within"])?;
+            Some(Require { all, any, within })
+        }
+        Some(_) => bail!("Key `require` must be a table."),
+    };
+
+    let depends_on = match monitor_table.remove("depends_on") {
+        None => Vec::new(),
+        Some(Value::String(name)) => vec![name],
+        Some(Value::Array(names)) => names
+            .into_iter()
+            .map(|name| match name {
+                Value::String(name) => Ok(name),
+                _ => bail!("Key `depends_on` must be a monitor name or an array of them."),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!("Key `depends_on` must be a monitor name or an array of them."),
+    };
+
+    let depends_on_within = match monitor_table.remove("depends_on_within") {
+        None => Duration::from_secs(5 * 60),
+        Some(_) if depends_on.is_empty() => {
+            bail!("Key `depends_on_within` requires `depends_on`.")
+        }
+        Some(Value::String(within)) => parse_duration("depends_on_within", &within)?,
+        Some(_) => bail!("Key `depends_on_within` must be a string."),
+    };
+
+    let confirm_after = match monitor_table.remove("confirm_after") {
+        None => None,
+        Some(Value::String(confirm_after)) => {
+            Some(parse_duration("confirm_after", &confirm_after)?)
+        }
+        Some(_) => bail!("Key `confirm_after` must be a string."),
+    };
+
+    let confirm_reset = match monitor_table.remove("confirm_reset") {
+        None => None,
+        Some(_) if confirm_after.is_none() => {
+            bail!("Key `confirm_reset` requires `confirm_after`.")
+        }
+        Some(_) if binary || (log.is_none() && service.is_none() && ssh.is_none()) => {
+            bail!("Key `confirm_reset` can only be used with a text `log`, `service`, or `ssh`.")
+        }
+        Some(Value::String(pattern)) => Some(parse_regex("confirm_reset", &pattern)?),
+        Some(_) => bail!("Key `confirm_reset` must be a string."),
+    };
+
+    let stop_on = match monitor_table.remove("stop_on") {
+        None => None,
+        Some(_) if binary || (log.is_none() && service.is_none() && ssh.is_none()) => {
+            bail!("Key `stop_on` can only be used with a text `log`, `service`, or `ssh`.")
+        }
+        Some(Value::String(pattern)) => Some(parse_regex("stop_on", &pattern)?),
+        Some(_) => bail!("Key `stop_on` must be a string."),
+    };
+
+    let average = match monitor_table.remove("average") {
+        None => None,
+        Some(Value::Table(mut average_table)) => {
+            let variable = match average_table.remove("variable") {
+                None => bail!("Key `average.variable` must be set."),
+                Some(Value::String(variable)) => variable,
+                Some(_) => bail!("Key `average.variable` must be a string."),
+            };
+            let window = match average_table.remove("window") {
+                None => bail!("Key `average.window` must be set."),
+                Some(Value::Integer(window)) if window > 0 => window as usize,
+                Some(_) => bail!("Key `average.window` must be a positive integer."),
+            };
+            assert_table_is_empty(average_table, &["variable", "window"])?;
+            Some((variable, window))
+        }
+        Some(_) => bail!("Key `average` must be a table."),
+    };
+
+    let anomaly = match monitor_table.remove("anomaly") {
+        None => None,
+        Some(Value::Table(mut anomaly_table)) => {
+            let variable = match anomaly_table.remove("variable") {
+                None => bail!("Key `anomaly.variable` must be set."),
+                Some(Value::String(variable)) => variable,
+                Some(_) => bail!("Key `anomaly.variable` must be a string."),
+            };
+            let window = match anomaly_table.remove("window") {
+                None => bail!("Key `anomaly.window` must be set."),
+                // The standard deviation of a single value is meaningless.
+                Some(Value::Integer(window)) if window > 1 => window as usize,
+                Some(_) => bail!("Key `anomaly.window` must be an integer greater than 1."),
+            };
+            let k = match anomaly_table.remove("k") {
+                None => 3.0,
+                Some(Value::Integer(k)) if k > 0 => k as f64,
+                Some(Value::Float(k)) if k > 0.0 => k,
+                Some(_) => bail!("Key `anomaly.k` must be a positive number."),
+            };
+            assert_table_is_empty(anomaly_table, &["variable", "window", "k"])?;
+            Some(Anomaly {
+                variable,
+                window,
+                k,
+            })
+        }
+        Some(_) => bail!("Key `anomaly` must be a table."),
+    };
+
     let exec = match monitor_table.remove("exec") {
         None => None,
-        Some(Value::String(exec_str)) => Some(Exec::Shell(exec_str)),
-        Some(Value::Array(args)) => match args.is_empty() {
-            true => bail!("Key `exec` must not be empty."),
-            false => Some(Exec::Spawn(args.into_iter().map(value_to_string).collect())),
+        Some(Value::String(exec_str)) if exec_str.starts_with('@') => {
+            match commands.get(&exec_str[1..]) {
+                Some(exec) => Some(exec.clone()),
+                None => bail!("Key `exec`: Command `{}` does not exist.", &exec_str[1..]),
+            }
+        }
+        Some(exec) => Some(parse_exec("exec", exec)?),
+    };
+
+    let exec_mode = match monitor_table.remove("exec_mode") {
+        None => ExecMode::default(),
+        Some(_) if exec.is_none() => bail!("Key `exec_mode` requires `exec`."),
+        Some(Value::String(exec_mode)) => match exec_mode.as_str() {
+            "trigger" => ExecMode::Trigger,
+            "daemon" => ExecMode::Daemon,
+            _ => bail!("Key `exec_mode` must be \"trigger\" or \"daemon\"."),
+        },
+        Some(_) => bail!("Key `exec_mode` must be a string."),
+    };
+
+    let exec_parse = match monitor_table.remove("exec_parse") {
+        None => None,
+        Some(_) if exec.is_none() => bail!("Key `exec_parse` requires `exec`."),
+        Some(_) if exec_mode == ExecMode::Daemon => {
+            bail!("Key `exec_parse` can't be used with `exec_mode = \"daemon\"`.")
+        }
+        Some(Value::String(exec_parse_regex_str)) => {
+            Some(parse_regex("exec_parse", &exec_parse_regex_str)?)
+        }
+        Some(_) => bail!("Key `exec_parse` must be a string."),
+    };
+
+    let exec_timeout = match monitor_table.remove("exec_timeout") {
+        None => Duration::from_secs(60),
+        Some(_) if exec_parse.is_none() => bail!("Key `exec_timeout` requires `exec_parse`."),
+        Some(Value::String(timeout)) => match parse_duration("exec_timeout", &timeout)? {
+            timeout if timeout.is_zero() => bail!("Key `exec_timeout` must not be zero."),
+            timeout => timeout,
+        },
+        Some(_) => bail!("Key `exec_timeout` must be a string."),
+    };
+
+    let exec_pty = match monitor_table.remove("exec_pty") {
+        None => false,
+        Some(_) if exec.is_none() => bail!("Key `exec_pty` requires `exec`."),
+        Some(_) if exec_mode == ExecMode::Daemon => {
+            bail!("Key `exec_pty` can't be used with `exec_mode = \"daemon\"`.")
+        }
+        Some(Value::Boolean(exec_pty)) => exec_pty,
+        Some(_) => bail!("Key `exec_pty` must be a boolean."),
+    };
+
+    let move_to = match monitor_table.remove("move_to") {
+        None => None,
+        Some(Value::String(dir)) => Some(PathBuf::from(dir)),
+        Some(_) => bail!("Key `move_to` must be a string."),
+    };
+
+    let after_exec = match monitor_table.remove("after_exec") {
+        None if move_to.is_some() => bail!("Key `move_to` requires `after_exec = \"move\"`."),
+        None => AfterExec::Keep,
+        Some(_) if watch_dir.is_none() => bail!("Key `after_exec` requires `watch_dir`."),
+        Some(_) if exec.is_none() => bail!("Key `after_exec` requires `exec`."),
+        Some(_) if exec_mode == ExecMode::Daemon => {
+            bail!("Key `after_exec` can't be used with `exec_mode = \"daemon\"`.")
+        }
+        Some(Value::String(after_exec)) => match (after_exec.as_str(), move_to) {
+            ("keep", None) => AfterExec::Keep,
+            ("delete", None) => AfterExec::Delete,
+            ("move", None) => bail!("Key `after_exec = \"move\"` requires `move_to`."),
+            // The moved file would be processed again.
+            ("move", Some(dir)) if watch_dir.as_ref() == Some(&dir) => {
+                bail!("Key `move_to` must be a different directory than `watch_dir`.")
+            }
+            ("move", Some(dir)) => AfterExec::Move(dir),
+            ("keep" | "delete", Some(_)) => {
+                bail!("Key `move_to` requires `after_exec = \"move\"`.")
+            }
+            _ => bail!("Key `after_exec` must be \"keep\", \"delete\", or \"move\"."),
+        },
+        Some(_) => bail!("Key `after_exec` must be a string."),
+    };
+
+    let fifo = match monitor_table.remove("fifo") {
+        None => None,
+        Some(Value::String(path)) => Some(PathBuf::from(path)),
+        Some(_) => bail!("Key `fifo` must be a string."),
+    };
+
+    let env_newlines = match monitor_table.remove("env_newlines") {
+        None => EnvNewlines::default(),
+        Some(Value::String(env_newlines)) => match env_newlines.as_str() {
+            "escape" => EnvNewlines::Escape,
+            "flatten" => EnvNewlines::Flatten,
+            "reject" => EnvNewlines::Reject,
+            "keep" => EnvNewlines::Keep,
+            _ => bail!("Key `env_newlines` must be one of escape, flatten, reject, or keep."),
         },
-        Some(_) => bail!("Key `exec` must be a string or an array of strings."),
+        Some(_) => bail!("Key `env_newlines` must be a string."),
     };
 
     let notify = match monitor_table.remove("notify") {
+        None => Vec::new(),
+        Some(Value::Array(notifications)) => notifications
+            .into_iter()
+            .map(parse_notification)
+            .collect::<Result<_>>()?,
+        Some(notification) => vec![parse_notification(notification)?],
+    };
+    if notify
+        .iter()
+        .any(|notification| notification.tail.is_some())
+        && log.as_ref().is_none_or(|log| log.binary)
+    {
+        bail!("Key `notify.tail` can only be used with a text `log`.");
+    }
+
+    let max_notifications_per_hour = match monitor_table.remove("max_notifications_per_hour") {
         None => None,
-        Some(Value::String(title)) => Some(Notification {
-            r#type: "default".to_owned(),
-            title,
-            body: String::new(),
-        }),
-        Some(Value::Table(mut notification_table)) => Some(Notification {
-            r#type: match notification_table.remove("type") {
-                None => "default".to_owned(),
-                Some(Value::String(t)) => t,
-                Some(_) => bail!("Key `type` must be a string."),
-            },
-            title: match notification_table.remove("title") {
-                None => "Ramon Notification".to_owned(),
-                Some(Value::String(title)) => title,
-                Some(_) => bail!("Key `title` must be a string."),
-            },
-            body: match notification_table.remove("body") {
-                None => String::new(),
-                Some(Value::String(body)) => body,
-                Some(_) => bail!("Key `body` must be a string."),
-            },
-        }),
-        Some(_) => bail!("Key `notify` must be a string or a table."),
+        Some(_) if notify.is_empty() => {
+            bail!("Key `max_notifications_per_hour` requires `notify`.")
+        }
+        Some(Value::Integer(max)) if max > 0 => Some(max as usize),
+        Some(_) => bail!("Key `max_notifications_per_hour` must be a positive integer."),
+    };
+
+    let summarize_suppressed = match monitor_table.remove("summarize_suppressed") {
+        None => false,
+        Some(_) if max_notifications_per_hour.is_none() => {
+            bail!("Key `summarize_suppressed` requires `max_notifications_per_hour`.")
+        }
+        Some(Value::Boolean(summarize_suppressed)) => summarize_suppressed,
+        Some(_) => bail!("Key `summarize_suppressed` must be a boolean."),
+    };
+
+    let fingerprint = match monitor_table.remove("fingerprint") {
+        None => None,
+        Some(_) if notify.is_empty() => bail!("Key `fingerprint` requires `notify`."),
+        Some(Value::String(fingerprint)) => Some(fingerprint),
+        Some(_) => bail!("Key `fingerprint` must be a string."),
+    };
+
+    let fingerprint_window = match monitor_table.remove("fingerprint_window") {
+        None => Duration::from_secs(60 * 60),
+        Some(_) if fingerprint.is_none() => {
+            bail!("Key `fingerprint_window` requires `fingerprint`.")
+        }
+        Some(Value::String(window)) => parse_duration("fingerprint_window", &window)?,
+        Some(_) => bail!("Key `fingerprint_window` must be a string."),
+    };
+
+    let on_error = match monitor_table.remove("on_error") {
+        None => None,
+        Some(Value::String(name)) => Some(name),
+        Some(_) => bail!("Key `on_error` must be a string."),
     };
 
-    assert_table_is_empty(monitor_table)?;
+    assert_table_is_empty(monitor_table, &schema::names(schema::MONITOR_KEYS))?;
 
     Ok(MonitorConfig {
         name,
 
         log,
+        watch,
+        match_content,
+        match_content_changes,
+        max_file_size,
+        fire_when_skipped,
+        poll_interval,
+        watch_permissions,
+        watch_debounce,
+        watch_dir,
+        settle_time,
         every,
+        missed_ticks,
+        skip_if_running,
         service,
+        service_args,
+        severity_from_priority,
+        ssh,
+        service_state,
+        down_for,
+        circuit_breaker,
+        line_limit,
+
+        severity,
+        labels,
+        group,
+        timezone,
+        first_match_only,
 
         cooldown,
+        cooldown_mode,
+        warmup,
         match_log,
         ignore_log,
+        match_log_bytes,
+        ignore_log_bytes,
+        transform,
+        ignore_if,
         unique,
+        unique_per_file,
+        changed,
+        r#if,
+        rate,
         threshold,
+        require,
+        depends_on,
+        depends_on_within,
+        confirm_after,
+        confirm_reset,
+        stop_on,
+        average,
+        anomaly,
 
         exec,
+        exec_mode,
+        after_exec,
+        exec_parse,
+        exec_timeout,
+        exec_pty,
+        env_newlines,
+        fifo,
         notify,
+        max_notifications_per_hour,
+        summarize_suppressed,
+        fingerprint,
+        fingerprint_window,
+        on_error,
     })
 }
 
+/// Parses a duration such as `"5m"`, naming the key and echoing the value if it's invalid.
+pub fn parse_duration(key: &str, value: &str) -> Result<Duration> {
+    duration_str::parse(value)
+        .map_err(|err| anyhow!("Key `{key}` has invalid duration {value:?}:\n{err}"))
+}
+
+/// Parses `expected_owner`, a `user` or `user:group` where each is a name or a numeric ID.
+fn parse_owner(owner: &str) -> Result<(u32, Option<u32>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let Some(uid) = user.parse().ok().or_else(|| users::user_id(user)) else {
+        bail!("Key `expected_owner` has unknown user {user:?}.");
+    };
+    let gid = match group {
+        None => None,
+        Some(group) => match group.parse().ok().or_else(|| users::group_id(group)) {
+            Some(gid) => Some(gid),
+            None => bail!("Key `expected_owner` has unknown group {group:?}."),
+        },
+    };
+    Ok((uid, gid))
+}
+
+/// Interprets a number or numeric string as a float.
+pub fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(integer) => Some(*integer as f64),
+        Value::Float(float) => Some(*float),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
 pub fn value_to_string(value: Value) -> String {
     match value {
         Value::String(string) => string,
@@ -365,9 +2781,249 @@ pub fn value_to_string(value: Value) -> String {
     }
 }
 
-fn assert_table_is_empty(table: Table) -> Result<()> {
-    for key in table.keys() {
-        bail!("Invalid key `{key}`");
+/// Fails with every key that isn't in the schema, before any key is parsed. This keeps the schema
+/// authoritative: a key that's parsed but missing from the schema is rejected, and the schema's
+/// tests check that every key in it is parsed.
+fn reject_unknown_keys(table: &Table, keys: &[schema::Key]) -> Result<()> {
+    let valid_keys = schema::names(keys);
+    let unknown = table
+        .iter()
+        .filter(|(key, _)| !valid_keys.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    assert_table_is_empty(unknown, &valid_keys)
+}
+
+/// Fails with every key left in the table, suggesting the closest valid key for each.
+fn assert_table_is_empty(table: Table, valid_keys: &[&str]) -> Result<()> {
+    let errors = table
+        .keys()
+        .map(|key| {
+            let suggestion = valid_keys
+                .iter()
+                .map(|valid_key| (edit_distance(key, valid_key), valid_key))
+                .min()
+                .filter(|(distance, _)| *distance <= (key.len() / 3).max(1));
+            match suggestion {
+                Some((_, valid_key)) => format!("Unknown key `{key}`; did you mean `{valid_key}`?"),
+                None => format!("Unknown key `{key}`."),
+            }
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        bail!(errors.join("\n"));
     }
     Ok(())
 }
+
+/// Returns the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Runs the shell command the way `exec` does, and returns its output.
+    fn run_shell(script: &str, variables: &HashMap<String, Value>, env_names: &EnvNames) -> String {
+        let mut command = Command::new("sh");
+        command.args(["-c", &template::render_shell(script, variables)]);
+        command.env_remove("ip");
+        for (variable, value) in variables {
+            for name in env_names.of(variable) {
+                command.env(name, value.as_str().unwrap());
+            }
+        }
+        let output = command.output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn templates_are_rendered_before_the_shell_runs() {
+        let variables = HashMap::from([("ip".to_owned(), Value::from("1.2.3.4; id"))]);
+        // `{{ip}}` is substituted and quoted, so it can't inject commands. `$ip` is left to the
+        // shell, which only has the prefixed variable.
+        let script = r#"echo {{ip}}"|$ip|$RAMON_IP""#;
+        assert_eq!(
+            run_shell(script, &variables, &EnvNames::default()),
+            "1.2.3.4; id||1.2.3.4; id\n"
+        );
+        let unprefixed = EnvNames {
+            unprefixed: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_shell(script, &variables, &unprefixed),
+            "1.2.3.4; id|1.2.3.4; id|1.2.3.4; id\n"
+        );
+    }
+
+    #[test]
+    fn env_names_are_prefixed_and_uppercased() {
+        let env_names = EnvNames::default();
+        assert_eq!(env_names.of("ip"), ["RAMON_IP"]);
+        assert_eq!(env_names.of("user.name"), ["RAMON_USER_NAME"]);
+    }
+
+    // Parsing `every` creates an interval, which needs a runtime.
+    #[tokio::test]
+    async fn captures_with_the_same_env_name_are_rejected() {
+        let doc = r#"
+            [monitor.m]
+            every = "1s"
+            exec = "true"
+            exec_parse = '(?<ip>\S+) (?<IP>\S+)'
+        "#;
+        let err = parse(doc, &ExecPolicy::default()).err().unwrap();
+        assert!(err.to_string().contains("`RAMON_IP`"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn missing_on_error_targets_are_rejected() {
+        let monitor = r#"
+            [monitor.m]
+            every = "1s"
+        "#;
+        let err = parse(
+            &format!("on_error = \"pager\"\n{monitor}"),
+            &ExecPolicy::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Key `on_error`: Notify config `pager` does not exist."
+        );
+        let err = parse(
+            &format!("{monitor}on_error = \"pager\""),
+            &ExecPolicy::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Monitor `m`: Key `on_error`: Notify config `pager` does not exist."
+        );
+    }
+
+    #[tokio::test]
+    async fn each_is_replaced_in_referenced_commands() {
+        let doc = r#"
+            [commands]
+            restart = "systemctl restart {{each}}"
+            page = ["page", "{{each}} is down"]
+
+            [monitor.restart]
+            for_each = ["api"]
+            every = "1m"
+            exec = "@restart"
+
+            [monitor.page]
+            for_each = ["web"]
+            every = "1m"
+            exec = "@page"
+        "#;
+        let config = parse(doc, &ExecPolicy::default()).unwrap();
+        let exec = |name: &str| {
+            config
+                .monitors
+                .iter()
+                .find(|monitor| monitor.name == name)
+                .and_then(|monitor| monitor.exec.clone())
+                .unwrap()
+        };
+        assert!(matches!(
+            exec("restart.api"),
+            Exec::Shell(command) if command == "systemctl restart api"
+        ));
+        assert!(matches!(
+            exec("page.web"),
+            Exec::Spawn(args) if args == ["page", "web is down"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn each_is_escaped_in_transform_regexes() {
+        let doc = r#"
+            [monitor.version]
+            for_each = ["c++"]
+            every = "1m"
+            transform = { name = { regex = "^{{each}} ", replace = "{{each}}: " } }
+        "#;
+        let config = parse(doc, &ExecPolicy::default()).unwrap();
+        let transform = &config.monitors[0].transform[0];
+        assert_eq!(transform.regex.as_str(), r"^c\+\+ ");
+        assert_eq!(transform.replace, "c++: ");
+    }
+
+    #[test]
+    fn service_args_reject_flags_in_clusters() {
+        assert!(check_service_arg("-rf").is_err());
+        assert!(check_service_arg("-qu").is_err());
+        assert!(check_service_arg("--unit=ssh").is_err());
+        // The rest of the cluster is the value of `-t`.
+        assert!(check_service_arg("-tfoo").is_ok());
+        assert!(check_service_arg("-rq").is_ok());
+        assert!(check_service_arg("--no-hostname").is_ok());
+    }
+
+    #[tokio::test]
+    async fn provenance_is_recorded_while_merging() {
+        let doc = r#"
+[notify.default]
+webhook = "https://example.com/hook"
+
+[notify.email]
+timezone = "UTC"
+
+[monitor.svc]
+for_each = ["ssh"]
+every = "1h"
+"#;
+        let config = parse(doc, &ExecPolicy::default()).unwrap();
+        let find = |path: &str| {
+            config
+                .provenance
+                .iter()
+                .find(|setting| setting.path == path)
+                .unwrap()
+        };
+        let inherited = find("notify.email.webhook");
+        assert!(inherited.inherited);
+        assert_eq!(inherited.line, Some(3));
+        assert_eq!(find("notify.email.timezone").line, Some(6));
+        let expanded = find("monitor.svc.ssh.every");
+        assert_eq!(expanded.item.as_deref(), Some("ssh"));
+        assert_eq!(expanded.line, Some(10));
+    }
+
+    #[test]
+    fn output_args_are_found_in_clusters() {
+        assert!(is_output_arg("-ocat"));
+        assert!(is_output_arg("-ro"));
+        assert!(is_output_arg("--output=cat"));
+        assert!(!is_output_arg("-tother"));
+        assert!(!is_output_arg("--no-hostname"));
+    }
+
+    #[test]
+    fn priorities_up_to_err_are_critical() {
+        assert_eq!(Severity::from_priority(0), Severity::Critical);
+        assert_eq!(Severity::from_priority(3), Severity::Critical);
+        assert_eq!(Severity::from_priority(4), Severity::Warning);
+        assert_eq!(Severity::from_priority(7), Severity::Info);
+    }
+}
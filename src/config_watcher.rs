@@ -0,0 +1,138 @@
+use crate::file_watcher;
+use anyhow::{anyhow, Result};
+use log::debug;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver},
+    time::timeout,
+};
+use toml::{Table, Value};
+
+/// Editors often write a file several times when saving it, so a change is only reported once
+/// the files have been quiet for this long.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the config files for `--watch-config`.
+pub struct ConfigWatcher {
+    // The watcher stops when it's dropped.
+    _watcher: Box<dyn Watcher + Send>,
+    watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
+    paths: Vec<PathBuf>,
+}
+
+impl ConfigWatcher {
+    /// The parent directories are watched rather than the files themselves, since editors often
+    /// save by replacing the file, which would end a watch on the file.
+    pub fn new(paths: &[&Path]) -> Result<Self> {
+        let (watcher_tx, watcher_rx) = mpsc::channel(16);
+        let mut watcher = file_watcher::new_watcher(None, move |res| {
+            // The receiver is only dropped when Ramon exits.
+            let _ = watcher_tx.blocking_send(res);
+        })?;
+
+        let mut watched = Vec::with_capacity(paths.len());
+        for path in paths {
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("{path:?} is not a file."))?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir =
+                fs::canonicalize(dir).map_err(|err| anyhow!("Failed to watch {dir:?}: {err}"))?;
+            watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .map_err(|err| anyhow!("Failed to watch {dir:?}: {err}"))?;
+            watched.push(dir.join(file_name));
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            watcher_rx,
+            paths: watched,
+        })
+    }
+
+    /// Waits for a config file to change, then for the changes to settle. Returns the files that
+    /// changed.
+    pub async fn changed(&mut self) -> Result<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            let event = self.next_event().await?;
+            self.record(event, &mut changed);
+        }
+        while let Ok(event) = timeout(DEBOUNCE, self.next_event()).await {
+            self.record(event?, &mut changed);
+        }
+        Ok(changed)
+    }
+
+    async fn next_event(&mut self) -> Result<notify::Event> {
+        match self.watcher_rx.recv().await {
+            Some(res) => Ok(res?),
+            None => Err(anyhow!("No more events.")),
+        }
+    }
+
+    fn record(&self, event: notify::Event, changed: &mut BTreeSet<PathBuf>) {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            if self.paths.contains(&path) {
+                debug!("Config event: {:?} {path:?}", event.kind);
+                changed.insert(path);
+            }
+        }
+    }
+}
+
+/// Describes which top-level keys, monitors, and notify configs differ between the configs.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let (Ok(old), Ok(new)) = (old.parse::<Table>(), new.parse::<Table>()) else {
+        return Vec::new();
+    };
+    let mut changes = Vec::new();
+    for section in ["monitor", "notify"] {
+        let empty = Table::new();
+        let table = |config: &Table| match config.get(section) {
+            Some(Value::Table(table)) => table.clone(),
+            _ => empty.clone(),
+        };
+        diff_tables(
+            &table(&old),
+            &table(&new),
+            &format!("{section}."),
+            &mut changes,
+        );
+    }
+    let top_level = |config: &Table| {
+        let mut config = config.clone();
+        config.remove("monitor");
+        config.remove("notify");
+        config
+    };
+    diff_tables(&top_level(&old), &top_level(&new), "", &mut changes);
+    changes
+}
+
+fn diff_tables(old: &Table, new: &Table, prefix: &str, changes: &mut Vec<String>) {
+    let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    for key in keys {
+        match (old.get(key), new.get(key)) {
+            (None, Some(_)) => changes.push(format!("added `{prefix}{key}`")),
+            (Some(_), None) => changes.push(format!("removed `{prefix}{key}`")),
+            (Some(old), Some(new)) if old != new => {
+                changes.push(format!("changed `{prefix}{key}`"))
+            }
+            _ => {}
+        }
+    }
+}
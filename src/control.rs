@@ -0,0 +1,202 @@
+use crate::{
+    config,
+    log_watcher::LogProgress,
+    maintenance::Maintenance,
+    monitor::{self, Event, Stats},
+};
+use anyhow::{anyhow, bail, Result};
+use log::{error, info};
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::mpsc::WeakSender,
+};
+
+pub const USAGE: &str = "Commands:
+  status             Print the state of every monitor
+  pause <MONITOR>    Stop the monitor from running actions
+  resume <MONITOR>   Resume a paused monitor
+  fire <MONITOR>     Run the monitor's actions now, bypassing its conditions
+  maintenance <DURATION>
+                     Mute notifications for the duration, e.g. `2h`
+  maintenance off    End the maintenance window now";
+
+/// What the control socket needs to report on and control a monitor.
+pub struct MonitorHandle {
+    pub name: String,
+    pub stats: Arc<Stats>,
+    pub paused: Arc<AtomicBool>,
+    /// Weak so that the monitor still exits when its sources do.
+    pub event_tx: WeakSender<Event>,
+    /// Set if the monitor follows a `log`.
+    pub log_progress: Option<Arc<LogProgress>>,
+}
+
+/// Accepts line commands on the socket until Ramon exits.
+pub async fn serve(
+    path: PathBuf,
+    mut monitors: Vec<MonitorHandle>,
+    maintenance: Arc<Maintenance>,
+) -> Result<()> {
+    // A socket left behind by a previous run would make binding fail.
+    if fs::symlink_metadata(&path).is_ok() {
+        remove_socket(&path)?;
+    }
+    let listener =
+        UnixListener::bind(&path).map_err(|err| anyhow!("Failed to bind {path:?}: {err}"))?;
+    // Commands can stop alerts, so only the owner may connect.
+    fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    info!("Listening for commands on {path:?}.");
+
+    monitors.sort_by(|a, b| a.name.cmp(&b.name));
+    let monitors = Arc::new(monitors);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let monitors = monitors.clone();
+        let maintenance = maintenance.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &monitors, &maintenance).await {
+                error!("Control socket: {err}");
+            }
+        });
+    }
+}
+
+/// Removes the socket. Anything else at the path, such as a file `control_socket` was set to by
+/// mistake, is left alone.
+pub fn remove_socket(path: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.file_type().is_socket() {
+        bail!("{path:?} already exists and isn't a socket.");
+    }
+    fs::remove_file(path).map_err(|err| anyhow!("Failed to remove {path:?}: {err}"))
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    monitors: &[MonitorHandle],
+    maintenance: &Maintenance,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match run_command(&line, monitors, maintenance).await {
+            Ok(response) => response,
+            Err(err) => format!("error: {err}\n"),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn run_command(
+    line: &str,
+    monitors: &[MonitorHandle],
+    maintenance: &Maintenance,
+) -> Result<String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let find = |name: Option<&str>| {
+        let Some(name) = name else {
+            bail!("`{command}` requires a monitor name.");
+        };
+        monitors
+            .iter()
+            .find(|monitor| monitor.name == name)
+            .ok_or_else(|| anyhow!("Monitor `{name}` does not exist."))
+    };
+    match command {
+        "status" => {
+            let mut response = monitors.iter().map(status).collect::<String>();
+            if let Some(remaining) = maintenance.remaining() {
+                response += &format!("maintenance remaining={}s\n", remaining.as_secs());
+            }
+            Ok(response)
+        }
+        "pause" => {
+            let monitor = find(words.next())?;
+            monitor.paused.store(true, Ordering::Relaxed);
+            info!("[{}] Paused.", monitor.name);
+            Ok("ok\n".to_owned())
+        }
+        "resume" => {
+            let monitor = find(words.next())?;
+            monitor.paused.store(false, Ordering::Relaxed);
+            info!("[{}] Resumed.", monitor.name);
+            Ok("ok\n".to_owned())
+        }
+        "fire" => {
+            let monitor = find(words.next())?;
+            let event_tx = monitor
+                .event_tx
+                .upgrade()
+                .ok_or_else(|| anyhow!("Monitor `{}` has exited.", monitor.name))?;
+            event_tx.send(Event::Fire).await?;
+            Ok("ok\n".to_owned())
+        }
+        "maintenance" => match words.next() {
+            None => bail!("`maintenance` requires a duration or `off`."),
+            Some("off") => {
+                if !maintenance.end() {
+                    bail!("Maintenance is not active.");
+                }
+                Ok("ok\n".to_owned())
+            }
+            Some(duration) => {
+                let duration = config::parse_duration("maintenance", duration)?;
+                maintenance.start(duration);
+                info!(
+                    "Maintenance started. Notifications are muted for {}s.",
+                    duration.as_secs()
+                );
+                Ok("ok\n".to_owned())
+            }
+        },
+        _ => bail!("Unknown command `{line}`\n{USAGE}"),
+    }
+}
+
+fn status(monitor: &MonitorHandle) -> String {
+    let state = match monitor.paused.load(Ordering::Relaxed) {
+        true => "paused",
+        false => "running",
+    };
+    let mut status = format!(
+        "{} {state} events={} actions={} notifications={} source_errors={}",
+        monitor.name,
+        monitor.stats.events.load(Ordering::Relaxed),
+        monitor.stats.actions.load(Ordering::Relaxed),
+        monitor.stats.notifications.load(Ordering::Relaxed),
+        monitor.stats.source_errors.load(Ordering::Relaxed),
+    );
+    if let Some(event_tx) = monitor.event_tx.upgrade() {
+        status += &format!(" queued={}", monitor::queued_events(&event_tx));
+    }
+    if let Some(bytes_behind) = monitor.log_progress.as_ref().and_then(|p| p.bytes_behind()) {
+        status += &format!(" bytes_behind={bytes_behind}");
+    }
+    status + "\n"
+}
+
+/// Sends the command to a running Ramon and prints the response. Returns false if the command
+/// failed.
+pub async fn send(path: &Path, command: &str) -> Result<bool> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| anyhow!("Failed to connect to {path:?}: {err}"))?;
+    stream.write_all(format!("{command}\n").as_bytes()).await?;
+    stream.shutdown().await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    print!("{response}");
+    Ok(!response.starts_with("error:"))
+}
@@ -0,0 +1,173 @@
+use crate::{file_watcher::new_watcher, monitor::Event};
+use anyhow::{anyhow, bail, Result};
+use log::debug;
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashSet,
+    mem::take,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    fs,
+    sync::mpsc::{self, Receiver, Sender},
+    time::{self, Instant},
+};
+
+/// How often files whose events were suppressed are fired again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Watches a directory for new files, firing an event for each one once it's completely written.
+pub struct DirWatcher {
+    name: String,
+    dir: PathBuf,
+    settle_time: Duration,
+    /// Whether files already in the directory are processed too.
+    process_existing: bool,
+    // The watcher stops when it's dropped.
+    _watcher: Box<dyn Watcher + Send>,
+    watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
+    event_tx: Sender<Event>,
+    /// Files that are waiting for their size to stop changing.
+    settling: Arc<Mutex<HashSet<PathBuf>>>,
+    /// Files the monitor didn't process because their events were suppressed, e.g. by
+    /// `cooldown`, which are fired again every `RETRY_INTERVAL` while they're still there.
+    deferred: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl DirWatcher {
+    pub fn new(
+        name: String,
+        dir: &Path,
+        poll_interval: Option<Duration>,
+        settle_time: Duration,
+        process_existing: bool,
+        deferred: Arc<Mutex<HashSet<PathBuf>>>,
+        event_tx: Sender<Event>,
+    ) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("{dir:?} is not a directory.");
+        }
+        let (watcher_tx, watcher_rx) = mpsc::channel(1);
+        let mut watcher = new_watcher(poll_interval, move |res| {
+            // The receiver is only dropped when the monitor exits.
+            let _ = watcher_tx.blocking_send(res);
+        })?;
+        debug!("[{name}] Watching {dir:?} for new files.");
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|err| anyhow!("Failed to watch {dir:?}: {err}"))?;
+
+        Ok(Self {
+            name,
+            dir: dir.to_owned(),
+            settle_time,
+            process_existing,
+            _watcher: watcher,
+            watcher_rx,
+            event_tx,
+            settling: Arc::default(),
+            deferred,
+        })
+    }
+
+    pub async fn start(mut self) -> Result<()> {
+        if self.process_existing {
+            let mut entries = fs::read_dir(&self.dir)
+                .await
+                .map_err(|err| anyhow!("Failed to read {:?}: {err}", self.dir))?;
+            while let Some(entry) = entries.next_entry().await? {
+                self.settle(entry.path());
+            }
+        }
+
+        let mut retry = time::interval_at(Instant::now() + RETRY_INTERVAL, RETRY_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                res = self.watcher_rx.recv() => match res {
+                    Some(res) => res?,
+                    None => break,
+                },
+                _ = retry.tick() => {
+                    let deferred = take(&mut *self.deferred.lock().unwrap());
+                    for path in deferred {
+                        self.settle(path);
+                    }
+                    continue;
+                }
+            };
+            debug!("[{}] Event: {event:?}", self.name);
+            // Files are often written elsewhere and renamed into the directory once complete.
+            let path = match event.kind {
+                EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Name(
+                    RenameMode::To | RenameMode::Both | RenameMode::Any,
+                )) => event.paths.last(),
+                _ => None,
+            };
+            if let Some(path) = path {
+                self.settle(path.clone());
+            }
+        }
+        bail!("No more events.");
+    }
+
+    /// Fires an event for the file once it's completely written, unless it's hidden, isn't a
+    /// regular file, or is already settling.
+    fn settle(&self, path: PathBuf) {
+        // Hidden files are usually still being written, e.g. by rsync.
+        let hidden = path
+            .file_name()
+            .is_none_or(|name| name.as_bytes().starts_with(b"."));
+        if hidden || !self.settling.lock().unwrap().insert(path.clone()) {
+            return;
+        }
+        let name = self.name.clone();
+        let settle_time = self.settle_time;
+        let event_tx = self.event_tx.clone();
+        let settling = self.settling.clone();
+        tokio::spawn(async move {
+            let mtime = wait_until_written(&path, settle_time).await;
+            settling.lock().unwrap().remove(&path);
+            let Some(mtime) = mtime else {
+                debug!("[{name}] Ignoring {path:?}, since it's gone or isn't a regular file.");
+                return;
+            };
+            debug!("[{name}] {path:?} is completely written.");
+            // The receiver is only dropped when the monitor exits.
+            let _ = event_tx
+                .send(Event::FileChange {
+                    path,
+                    time: SystemTime::now(),
+                    mtime,
+                    permissions: None,
+                    content: true,
+                    dropped: true,
+                })
+                .await;
+        });
+    }
+}
+
+/// Waits until the file's size and modification time stay the same for `settle_time`, and
+/// returns its modification time. Returns `None` if the file was removed or isn't a regular file.
+async fn wait_until_written(path: &Path, settle_time: Duration) -> Option<Option<SystemTime>> {
+    let mut last = None;
+    loop {
+        let metadata = fs::metadata(path).await.ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let current = (metadata.len(), metadata.modified().ok());
+        if last == Some(current) {
+            return Some(current.1);
+        }
+        last = Some(current);
+        time::sleep(settle_time).await;
+    }
+}
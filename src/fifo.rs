@@ -0,0 +1,71 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    ffi::CString,
+    io::ErrorKind,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{io::AsyncWriteExt, net::unix::pipe, time::timeout};
+
+/// How long to wait for a slow reader to make room in the pipe.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Creates the FIFO if it doesn't exist.
+pub fn create(path: &Path) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is a valid, NUL-terminated string.
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        let err = std::io::Error::last_os_error();
+        bail!("Failed to create FIFO {path:?}: {err}");
+    }
+    Ok(())
+}
+
+/// The `fifo` action. The FIFO is kept open between writes so that a reader sees every event
+/// without reopening it.
+pub struct Fifo {
+    path: PathBuf,
+    sender: Option<pipe::Sender>,
+}
+
+impl Fifo {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, sender: None }
+    }
+
+    /// Writes the line to the FIFO. The FIFO is opened without blocking, so this fails instead
+    /// of hanging if no process is reading it.
+    pub async fn write(&mut self, line: &[u8]) -> Result<()> {
+        let path = &self.path;
+        let sender = match &mut self.sender {
+            Some(sender) => sender,
+            None => self.sender.insert(open(path)?),
+        };
+        let res = timeout(WRITE_TIMEOUT, sender.write_all(line))
+            .await
+            .map_err(|_| anyhow!("Timed out writing to {path:?}; the reader isn't keeping up."))
+            .and_then(|res| res.map_err(|err| anyhow!("Failed to write to {path:?}: {err}")));
+        // The reader may have gone away, so the FIFO is reopened next time.
+        if res.is_err() {
+            self.sender = None;
+        }
+        res
+    }
+}
+
+fn open(path: &Path) -> Result<pipe::Sender> {
+    create(path)?;
+    match pipe::OpenOptions::new().open_sender(path) {
+        Ok(sender) => Ok(sender),
+        // `ENXIO` means that no process has the FIFO open for reading.
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+            bail!("No process is reading {path:?}.")
+        }
+        Err(err) if err.kind() == ErrorKind::InvalidInput => bail!("{path:?} is not a FIFO."),
+        Err(err) => bail!("Failed to open {path:?}: {err}"),
+    }
+}
@@ -0,0 +1,365 @@
+use crate::{config::WatchPermissions, monitor::Event, users};
+use anyhow::{anyhow, bail, Result};
+use log::{debug, warn};
+use notify::{
+    event::{MetadataKind, ModifyKind},
+    EventHandler, EventKind, PollWatcher, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    fs,
+    sync::mpsc::{self, Receiver, Sender},
+    task,
+    time::{self, Instant},
+};
+
+pub struct FileWatcher {
+    name: String,
+    // The watcher stops when it's dropped.
+    _watcher: Box<dyn Watcher + Send>,
+    watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
+    event_tx: Sender<Event>,
+    permissions: Option<WatchPermissions>,
+    /// The last mode and owner seen for each file, if `watch_permissions` is set.
+    last_permissions: HashMap<PathBuf, Permissions>,
+    /// The last modification time seen for each file. A write usually fires several events, e.g.
+    /// one for truncating the file and one for writing it, so changes are debounced by mtime
+    /// rather than by when they were received.
+    last_mtimes: HashMap<PathBuf, SystemTime>,
+    /// Set with `watch_debounce`.
+    debounce: Option<Duration>,
+    pending: HashMap<PathBuf, PendingChange>,
+}
+
+/// A file's mode and owner.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Permissions {
+    fn of(metadata: &Metadata) -> Self {
+        Self {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+
+    /// Formats the mode in octal, e.g. `0644`.
+    pub fn mode_string(&self) -> String {
+        format!("{:04o}", self.mode)
+    }
+
+    /// Formats the owner as `user:group`, using IDs for users and groups without a name. The
+    /// names are looked up on a blocking thread, since NSS may ask e.g. an LDAP server.
+    pub async fn owner_string(&self) -> String {
+        let Self { uid, gid, .. } = *self;
+        task::spawn_blocking(move || {
+            let user = users::user_name(uid).unwrap_or_else(|| uid.to_string());
+            let group = users::group_name(gid).unwrap_or_else(|| gid.to_string());
+            format!("{user}:{group}")
+        })
+        .await
+        .unwrap_or_else(|_| format!("{uid}:{gid}"))
+    }
+}
+
+/// A content change that's held back until the file stops being written.
+struct PendingChange {
+    /// When the first of the merged changes was received.
+    time: SystemTime,
+    /// The latest modification time seen.
+    mtime: SystemTime,
+    deadline: Instant,
+    permissions: Option<PermissionsChange>,
+}
+
+/// A file's mode and owner before and after an event.
+#[derive(Clone, Copy)]
+pub struct PermissionsChange {
+    pub old: Permissions,
+    pub new: Permissions,
+}
+
+impl FileWatcher {
+    /// Watches every file matching the globs. Globs are expanded once, so files created later
+    /// are not watched.
+    pub fn new(
+        name: String,
+        globs: &[String],
+        poll_interval: Option<Duration>,
+        permissions: Option<WatchPermissions>,
+        debounce: Option<Duration>,
+        event_tx: Sender<Event>,
+    ) -> Result<Self> {
+        let (watcher_tx, watcher_rx) = mpsc::channel(1);
+        let mut watcher = new_watcher(poll_interval, move |res| {
+            // The receiver is only dropped when the monitor exits.
+            let _ = watcher_tx.blocking_send(res);
+        })?;
+
+        let mut last_permissions = HashMap::new();
+        let mut last_mtimes = HashMap::new();
+        for pattern in globs {
+            let paths = glob::glob(pattern)
+                .map_err(|err| anyhow!("Invalid glob {pattern:?}: {err}"))?
+                .collect::<Result<Vec<_>, _>>()?;
+            if paths.is_empty() {
+                bail!("Glob {pattern:?} did not match any files.");
+            }
+            for path in paths {
+                debug!("[{name}] Watching {path:?}.");
+                watcher
+                    .watch(&path, RecursiveMode::NonRecursive)
+                    .map_err(|err| anyhow!("Failed to watch {path:?}: {err}"))?;
+                let metadata = std::fs::metadata(&path)
+                    .map_err(|err| anyhow!("Failed to read metadata of {path:?}: {err}"))?;
+                if permissions.is_some() {
+                    last_permissions.insert(path.clone(), Permissions::of(&metadata));
+                }
+                if let Ok(mtime) = metadata.modified() {
+                    last_mtimes.insert(path, mtime);
+                }
+            }
+        }
+
+        Ok(Self {
+            name,
+            _watcher: watcher,
+            watcher_rx,
+            event_tx,
+            permissions,
+            last_permissions,
+            last_mtimes,
+            debounce,
+            pending: HashMap::new(),
+        })
+    }
+
+    pub async fn start(mut self) -> Result<()> {
+        // Files that already have unexpected permissions would otherwise go unnoticed until they
+        // change again.
+        if let Some(expected) = self.permissions {
+            let time = SystemTime::now();
+            for (path, &permissions) in &self.last_permissions {
+                if expected.is_expected(permissions) {
+                    continue;
+                }
+                warn!(
+                    "[{}] {path:?} has mode {} and owner {}, which aren't expected.",
+                    self.name,
+                    permissions.mode_string(),
+                    permissions.owner_string().await,
+                );
+                self.event_tx
+                    .send(Event::FileChange {
+                        path: path.clone(),
+                        time,
+                        mtime: None,
+                        permissions: Some(PermissionsChange {
+                            old: permissions,
+                            new: permissions,
+                        }),
+                        content: false,
+                        dropped: false,
+                    })
+                    .await?;
+            }
+        }
+
+        loop {
+            let deadline = self.pending.values().map(|pending| pending.deadline).min();
+            tokio::select! {
+                res = self.watcher_rx.recv() => match res {
+                    Some(res) => self.handle(res?).await?,
+                    None => break,
+                },
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.send_settled().await?;
+                }
+            }
+        }
+        bail!("No more events.");
+    }
+
+    async fn handle(&mut self, event: notify::Event) -> Result<()> {
+        debug!("[{}] Event: {event:?}", self.name);
+        let content = matches!(
+            event.kind,
+            EventKind::Modify(
+                ModifyKind::Data(_)
+                    | ModifyKind::Any
+                    // The poll watcher reports writes as modification time changes.
+                    | ModifyKind::Metadata(MetadataKind::WriteTime)
+            )
+        );
+        // inotify reports changes to the mode and owner as `IN_ATTRIB`, which doesn't say
+        // which attribute changed.
+        let attributes = self.permissions.is_some()
+            && matches!(
+                event.kind,
+                EventKind::Modify(ModifyKind::Metadata(
+                    MetadataKind::Any | MetadataKind::Permissions | MetadataKind::Ownership
+                ))
+            );
+        if !content && !attributes {
+            return Ok(());
+        }
+
+        let time = SystemTime::now();
+        for path in event.paths {
+            // The file may have been deleted since the event was fired.
+            let metadata = fs::metadata(&path).await.ok();
+            let mtime = metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok());
+            let permissions = match (&self.permissions, &metadata) {
+                (Some(_), Some(metadata)) => {
+                    let new = Permissions::of(metadata);
+                    let old = self.last_permissions.insert(path.clone(), new);
+                    Some(PermissionsChange {
+                        old: old.unwrap_or(new),
+                        new,
+                    })
+                }
+                _ => None,
+            };
+            // Changes that didn't move the mtime were already reported with an earlier event.
+            // Deleted files have no mtime, so they're always reported.
+            let content = content
+                && match mtime {
+                    Some(mtime) => self.last_mtimes.insert(path.clone(), mtime) != Some(mtime),
+                    None => true,
+                };
+            if !content {
+                // Other attributes, such as the access time, change without a write too.
+                let (Some(expected), Some(change)) = (self.permissions, &permissions) else {
+                    continue;
+                };
+                if change.old == change.new
+                    || !expected.reports_any_change() && expected.is_expected(change.new)
+                {
+                    continue;
+                }
+            }
+            if let (true, Some(debounce), Some(mtime)) = (content, self.debounce, mtime) {
+                self.debounce(path, time, mtime, debounce, permissions);
+                continue;
+            }
+            self.event_tx
+                .send(Event::FileChange {
+                    path,
+                    time,
+                    mtime,
+                    permissions,
+                    content,
+                    dropped: false,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Holds back a content change until the file's mtime is `debounce` old, merging it with the
+    /// change that's already pending for the file, if any.
+    fn debounce(
+        &mut self,
+        path: PathBuf,
+        time: SystemTime,
+        mtime: SystemTime,
+        debounce: Duration,
+        permissions: Option<PermissionsChange>,
+    ) {
+        let deadline = deadline(mtime, debounce);
+        match self.pending.get_mut(&path) {
+            Some(pending) => {
+                pending.mtime = mtime;
+                pending.deadline = deadline;
+                if let (Some(pending), Some(change)) = (&mut pending.permissions, permissions) {
+                    pending.new = change.new;
+                }
+            }
+            None => {
+                self.pending.insert(
+                    path,
+                    PendingChange {
+                        time,
+                        mtime,
+                        deadline,
+                        permissions,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Sends the pending changes whose files weren't written again within the debounce.
+    async fn send_settled(&mut self) -> Result<()> {
+        let (Some(debounce), now) = (self.debounce, Instant::now()) else {
+            return Ok(());
+        };
+        let due = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in due {
+            // A write may not have been received yet.
+            let mtime = fs::metadata(&path)
+                .await
+                .ok()
+                .and_then(|metadata| metadata.modified().ok());
+            let pending = self.pending.get_mut(&path).unwrap();
+            if let Some(mtime) = mtime.filter(|&mtime| mtime != pending.mtime) {
+                pending.mtime = mtime;
+                pending.deadline = deadline(mtime, debounce);
+                self.last_mtimes.insert(path, mtime);
+                continue;
+            }
+            let pending = self.pending.remove(&path).unwrap();
+            self.event_tx
+                .send(Event::FileChange {
+                    path,
+                    time: pending.time,
+                    mtime: mtime.or(Some(pending.mtime)),
+                    permissions: pending.permissions,
+                    content: true,
+                    dropped: false,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// When a change whose file was last written at `mtime` is no longer held back.
+fn deadline(mtime: SystemTime, debounce: Duration) -> Instant {
+    // A clock that jumped backwards makes the mtime look like it's in the future.
+    let age = mtime.elapsed().unwrap_or_default();
+    Instant::now() + debounce.saturating_sub(age)
+}
+
+/// Creates the platform's recommended watcher, or a watcher that polls at the interval. Polling
+/// works on filesystems that don't support inotify, such as NFS and CIFS.
+pub fn new_watcher(
+    poll_interval: Option<Duration>,
+    handler: impl EventHandler,
+) -> notify::Result<Box<dyn Watcher + Send>> {
+    Ok(match poll_interval {
+        None => Box::new(notify::recommended_watcher(handler)?),
+        Some(interval) => Box::new(PollWatcher::new(
+            handler,
+            notify::Config::default().with_poll_interval(interval),
+        )?),
+    })
+}
@@ -0,0 +1,45 @@
+use crate::config::HeartbeatConfig;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use tokio::time::{self, MissedTickBehavior};
+
+/// Failed pings in a row after which each failure is logged as an error, since the uptime monitor
+/// is likely to report Ramon as down by then.
+const LOUD_AFTER: u32 = 3;
+
+/// Pings `heartbeat_url` every `heartbeat_interval` until Ramon exits.
+pub async fn run(config: HeartbeatConfig, http: Client) {
+    let mut interval = time::interval(config.interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut failures = 0;
+    loop {
+        interval.tick().await;
+        match ping(&config, &http).await {
+            Ok(()) => {
+                if failures >= LOUD_AFTER {
+                    info!("Heartbeat succeeded again after {failures} failed pings.");
+                }
+                failures = 0;
+                debug!("Sent heartbeat.");
+            }
+            Err(err) => {
+                failures += 1;
+                if failures >= LOUD_AFTER {
+                    error!("Heartbeat failed {failures} times in a row, so Ramon may be reported as down: {err}");
+                } else {
+                    warn!("Heartbeat failed: {err}");
+                }
+            }
+        }
+    }
+}
+
+async fn ping(config: &HeartbeatConfig, http: &Client) -> Result<()> {
+    http.get(config.url.clone())
+        .timeout(config.timeout)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
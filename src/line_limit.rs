@@ -0,0 +1,151 @@
+use crate::config::{LineLimit, LongLines};
+use anyhow::Result;
+use log::warn;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Appended to truncated lines so that they aren't mistaken for the whole line.
+const TRUNCATED_MARKER: &str = " [truncated]";
+/// How often to log the number of lines that were too long.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Applies `max_line_length` to lines and periodically logs how many were affected.
+pub struct LineLimiter {
+    name: String,
+    limit: Option<LineLimit>,
+    affected: usize,
+    last_report: Option<Instant>,
+}
+
+impl LineLimiter {
+    pub fn new(name: String, limit: Option<LineLimit>) -> Self {
+        Self {
+            name,
+            limit,
+            affected: 0,
+            last_report: None,
+        }
+    }
+
+    /// Returns the line, truncated if it's too long, or `None` if it should be skipped.
+    pub fn apply(&mut self, line: &str) -> Option<String> {
+        match self.limit {
+            Some(limit) if line.len() > limit.max => self.over_limit(limit, line),
+            _ => Some(line.to_owned()),
+        }
+    }
+
+    /// Reads the next line that isn't skipped. Returns `Ok(None)` at the end of the reader.
+    pub async fn read_line(
+        &mut self,
+        reader: &mut (impl AsyncBufRead + Unpin),
+    ) -> Result<Option<String>> {
+        loop {
+            match self.read_limited_line(reader).await? {
+                None => return Ok(None),
+                Some(Some(line)) => return Ok(Some(line)),
+                Some(None) => continue,
+            }
+        }
+    }
+
+    /// Reads a line without buffering more than `max_line_length` bytes of it, so that a huge
+    /// line can't exhaust memory. Returns `Some(None)` if the line was skipped.
+    async fn read_limited_line(
+        &mut self,
+        reader: &mut (impl AsyncBufRead + Unpin),
+    ) -> Result<Option<Option<String>>> {
+        let Some(limit) = self.limit else {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            return Ok(Some(Some(trim_newline(line))));
+        };
+
+        let mut line = Vec::new();
+        let mut too_long = false;
+        let mut read_any = false;
+        loop {
+            let buffer = reader.fill_buf().await?;
+            if buffer.is_empty() {
+                if !read_any {
+                    return Ok(None);
+                }
+                break;
+            }
+            read_any = true;
+            let (chunk, used, done) = match buffer.iter().position(|&byte| byte == b'\n') {
+                Some(i) => (&buffer[..i], i + 1, true),
+                None => (buffer, buffer.len(), false),
+            };
+            let room = limit.max - line.len().min(limit.max);
+            if chunk.len() > room {
+                too_long = true;
+            }
+            line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            reader.consume(used);
+            if done {
+                break;
+            }
+        }
+
+        if !too_long {
+            return Ok(Some(Some(trim_newline(String::from_utf8(line)?))));
+        }
+        // The cut may have split a character.
+        if let Err(err) = std::str::from_utf8(&line) {
+            if err.error_len().is_none() {
+                line.truncate(err.valid_up_to());
+            }
+        }
+        Ok(Some(self.over_limit(limit, &String::from_utf8(line)?)))
+    }
+
+    fn over_limit(&mut self, limit: LineLimit, line: &str) -> Option<String> {
+        self.record();
+        match limit.action {
+            LongLines::Skip => None,
+            LongLines::Truncate => {
+                let mut end = limit.max.min(line.len());
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                Some(format!("{}{TRUNCATED_MARKER}", &line[..end]))
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        self.affected += 1;
+        if self
+            .last_report
+            .is_some_and(|time| time.elapsed() < REPORT_INTERVAL)
+        {
+            return;
+        }
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let action = match limit.action {
+            LongLines::Truncate => "Truncated",
+            LongLines::Skip => "Skipped",
+        };
+        warn!(
+            "[{}] {action} {} line(s) longer than {} bytes.",
+            self.name, self.affected, limit.max
+        );
+        self.affected = 0;
+        self.last_report = Some(Instant::now());
+    }
+}
+
+fn trim_newline(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
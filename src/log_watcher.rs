@@ -1,34 +1,102 @@
-use crate::monitor::Event;
+use crate::{
+    config::{LineEnding, LogConfig, ReplayConfig},
+    file_watcher,
+    line_limit::LineLimiter,
+    monitor::Event,
+};
 use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use log::{debug, error, info, warn};
 use notify::{
     event::{MetadataKind, ModifyKind, RenameMode},
     EventKind, RecursiveMode, Watcher,
 };
 use std::{
+    future::pending,
     io::SeekFrom,
-    path::PathBuf,
+    mem::take,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use tokio::{
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     io::{AsyncReadExt, AsyncSeekExt},
     sync::mpsc::{self, Receiver, Sender},
-    time::sleep,
+    time::{sleep, sleep_until},
 };
 
+/// At most this much of the end of the log is read for `notify.tail`.
+const MAX_TAIL_SIZE: u64 = 64 * 1024;
+/// How much of the log is read at once when searching backward for `replay_since`.
+const REPLAY_CHUNK_SIZE: u64 = 64 * 1024;
+/// Growth beyond this much at once is skipped rather than read.
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+const MAX_REOPEN_BACKOFF: Duration = Duration::from_secs(10);
+const WAITING_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a line is held for `dedup_consecutive` before it's sent, even if it's still being
+/// repeated.
+const DEDUP_DELAY: Duration = Duration::from_secs(1);
+
+/// How much of a log has been sent to monitors, for reporting how far behind a watcher is.
+pub struct LogProgress {
+    path: PathBuf,
+    sent: AtomicU64,
+}
+
+impl LogProgress {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns how many bytes were written to the log but not yet sent to monitors. The file is
+    /// checked directly, since the watcher can't check it while it's waiting on a monitor.
+    pub fn bytes_behind(&self) -> Option<u64> {
+        let size = std::fs::metadata(&self.path).ok()?.len();
+        Some(size.saturating_sub(self.sent.load(Ordering::Relaxed)))
+    }
+}
+
 pub struct LogWatcher {
     name: String,
     watcher: Box<dyn Watcher + Send>,
     path: PathBuf,
+    binary: bool,
+    batch_lines: bool,
+    dedup_consecutive: bool,
+    /// The line being held for `dedup_consecutive`, how many times it was repeated, and when
+    /// it was first seen.
+    repeated: Option<(String, usize, Instant)>,
+    reopen_timeout: Option<Duration>,
     file: File,
     cursor: u64,
+    /// Whether the cursor is in the middle of a line, because Ramon started or skipped ahead
+    /// while the line was being written. The rest of that line is dropped.
+    mid_line: bool,
+    line_ending: LineEnding,
+    /// Lines sent when the watcher starts.
+    replay: Vec<String>,
+    limiter: LineLimiter,
+    progress: Arc<LogProgress>,
     watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
     event_tx: Sender<Event>,
 }
 
 impl LogWatcher {
-    pub async fn new(name: String, path: PathBuf, event_tx: Sender<Event>) -> Result<Self> {
+    pub async fn new(
+        name: String,
+        config: LogConfig,
+        progress: Arc<LogProgress>,
+        event_tx: Sender<Event>,
+    ) -> Result<Self> {
+        let path = config.path;
         let mut file = OpenOptions::new()
             .read(true)
             .open(&path)
@@ -36,31 +104,118 @@ impl LogWatcher {
             .map_err(|err| anyhow!("Failed to open {path:?}: {err}"))?;
         file.seek(SeekFrom::End(0)).await?;
         let cursor = file.stream_position().await?;
+        let mid_line = is_mid_line(&mut file, cursor, config.line_ending).await?;
+        progress.sent.store(cursor, Ordering::Relaxed);
+        let replay = match &config.replay {
+            None => Vec::new(),
+            Some(replay) => {
+                let lines = read_lines_since(&mut file, cursor, replay, config.line_ending).await?;
+                info!("[{name}] Replaying {} lines from {path:?}.", lines.len());
+                file.seek(SeekFrom::Start(cursor)).await?;
+                lines
+            }
+        };
 
         let (watcher_tx, watcher_rx) = mpsc::channel(1);
-        let mut watcher = notify::recommended_watcher(move |res| {
+        let mut watcher = file_watcher::new_watcher(config.poll_interval, move |res| {
             watcher_tx.blocking_send(res).unwrap();
         })?;
         watcher.watch(&path, RecursiveMode::NonRecursive)?;
 
         Ok(Self {
+            limiter: LineLimiter::new(name.clone(), config.line_limit),
             name,
-            watcher: Box::new(watcher),
+            watcher,
             path,
+            binary: config.binary,
+            batch_lines: config.batch_lines,
+            dedup_consecutive: config.dedup_consecutive,
+            repeated: None,
+            reopen_timeout: config.reopen_timeout,
             file,
             cursor,
+            mid_line,
+            line_ending: config.line_ending,
+            replay,
+            progress,
             watcher_rx,
             event_tx,
         })
     }
 
     pub async fn start(mut self) -> Result<()> {
-        while let Some(res) = self.watcher_rx.recv().await {
-            self.process_log_event(res?).await?;
+        for line in take(&mut self.replay) {
+            let Some(line) = self.limiter.apply(&line) else {
+                continue;
+            };
+            self.send_line(line).await?;
+        }
+        loop {
+            let flush_at = self
+                .repeated
+                .as_ref()
+                .map(|(_, _, since)| *since + DEDUP_DELAY);
+            let flush = async {
+                match flush_at {
+                    Some(flush_at) => sleep_until(flush_at.into()).await,
+                    None => pending().await,
+                }
+            };
+            tokio::select! {
+                res = self.watcher_rx.recv() => match res {
+                    Some(res) => self.process_log_event(res?).await?,
+                    None => break,
+                },
+                _ = flush => self.flush_repeated().await?,
+            }
         }
         bail!("No more events.");
     }
 
+    /// Sends the line, or holds it if `dedup_consecutive` is set, so that identical lines that
+    /// follow it are counted instead of sent.
+    async fn send_line(&mut self, line: String) -> Result<()> {
+        if !self.dedup_consecutive {
+            self.event_tx
+                .send(Event::NewLogLine {
+                    source: "log",
+                    line,
+                    severity: None,
+                    repeat_count: None,
+                })
+                .await?;
+            return Ok(());
+        }
+        if let Some((last, count, _)) = &mut self.repeated {
+            if *last == line {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        self.flush_repeated().await?;
+        self.repeated = Some((line, 1, Instant::now()));
+        Ok(())
+    }
+
+    /// Sends the held line with the number of times it was repeated.
+    async fn flush_repeated(&mut self) -> Result<()> {
+        let Some((line, count, _)) = self.repeated.take() else {
+            return Ok(());
+        };
+        if count > 1 {
+            debug!("[{}] Line was repeated {count} times.", self.name);
+        }
+        self.event_tx
+            .send(Event::NewLogLine {
+                source: "log",
+                line,
+                severity: None,
+                repeat_count: Some(count),
+            })
+            .await?;
+        Ok(())
+    }
+
     async fn process_log_event(&mut self, event: notify::Event) -> Result<()> {
         debug!("[{}] Event: {event:?}", self.name);
 
@@ -68,21 +223,49 @@ impl LogWatcher {
         // TODO: Test on other platforms.
         match event.kind {
             EventKind::Modify(ModifyKind::Name(RenameMode::From))
-            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)) => {
+            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))
+            // The poll watcher reports a recreated file as a creation.
+            | EventKind::Create(_) => {
                 self.reinit_file_descriptors().await?;
             }
             _ => {}
         }
 
-        let new_size = self.file.metadata().await?.len();
-        if new_size < self.cursor {
-            warn!("[{}] File {:?} was truncated", self.name, self.path);
-            self.cursor = new_size;
-            return Ok(());
-        } else if new_size == self.cursor {
+        let mut new_size = self.file.metadata().await?.len();
+        // The poll watcher reports events for the path rather than the open file, so an event
+        // without growth may mean the file was replaced.
+        if new_size <= self.cursor {
+            if self.was_replaced().await? {
+                // The file was rotated by creating a new file rather than renaming, so no rename
+                // event was received.
+                self.reinit_file_descriptors().await?;
+                new_size = self.file.metadata().await?.len();
+            } else if new_size < self.cursor {
+                // With copytruncate, lines may be written immediately after truncation, so the
+                // file is read from the beginning.
+                warn!("[{}] File {:?} was truncated", self.name, self.path);
+                self.cursor = 0;
+                self.mid_line = false;
+            }
+        }
+        if new_size == self.cursor {
+            self.progress.sent.store(self.cursor, Ordering::Relaxed);
             return Ok(());
         }
-        self.process_chunk(new_size).await
+        self.process_chunk(new_size).await?;
+        // Only updated once the lines were sent, so that a slow monitor shows up as lag.
+        self.progress.sent.store(self.cursor, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns true if the path now refers to a different file than the one that's open.
+    // This takes `&mut self` because the watcher isn't `Sync`.
+    async fn was_replaced(&mut self) -> Result<bool> {
+        let open = self.file.metadata().await?;
+        match fs::metadata(&self.path).await {
+            Ok(current) => Ok(current.dev() != open.dev() || current.ino() != open.ino()),
+            Err(_) => Ok(true),
+        }
     }
 
     async fn reinit_file_descriptors(&mut self) -> Result<()> {
@@ -94,24 +277,43 @@ impl LogWatcher {
         // Handle log rotation.
         // FIXME: Are there any cases where new log files are not generated immediately
         // after rotation?
-        self.watcher.unwatch(&self.path).unwrap();
-        let timeout = Instant::now().checked_add(Duration::from_secs(1)).unwrap();
+        // The path may already be unwatched after some rename sequences, which is harmless since
+        // it's watched again below.
+        if let Err(err) = self.watcher.unwatch(&self.path) {
+            warn!("[{}] Failed to unwatch {:?}: {err}", self.name, self.path);
+        }
+        let start = Instant::now();
+        let mut last_log = start;
+        let mut backoff = Duration::from_millis(10);
         self.file = loop {
             match OpenOptions::new().read(true).open(&self.path).await {
                 Ok(file) => break file,
                 Err(err) => {
-                    if Instant::now() > timeout {
-                        bail!("File {:?} was moved: {err}", self.path);
-                    } else {
-                        sleep(Duration::from_millis(10)).await;
+                    if let Some(timeout) = self.reopen_timeout {
+                        if start.elapsed() > timeout {
+                            bail!("File {:?} was moved: {err}", self.path);
+                        }
+                    }
+                    if last_log.elapsed() >= WAITING_LOG_INTERVAL {
+                        last_log = Instant::now();
+                        warn!(
+                            "[{}] Still waiting for {:?} to be recreated after {}s: {err}",
+                            self.name,
+                            self.path,
+                            start.elapsed().as_secs(),
+                        );
                     }
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_REOPEN_BACKOFF);
                 }
             }
         };
         self.cursor = 0;
+        self.mid_line = false;
         self.watcher
             .watch(&self.path, RecursiveMode::NonRecursive)?;
         info!("[{}] File descriptors were reestablished.", self.name);
+        self.event_tx.send(Event::LogRotated).await?;
 
         Ok(())
     }
@@ -120,42 +322,519 @@ impl LogWatcher {
         let prefix = format!("[{}]", self.name);
         let chunk_size = new_size - self.cursor;
         info!("{prefix} Log file grew by {chunk_size} bytes.");
-        if chunk_size > 1024 * 1024 {
+        if chunk_size > MAX_CHUNK_SIZE {
             warn!("{prefix} Chunk too big. Skipping.");
             self.cursor = new_size;
+            self.mid_line =
+                !self.binary && is_mid_line(&mut self.file, new_size, self.line_ending).await?;
             return Ok(());
         }
 
-        // Ensure chunk ends with newline.
-        // SeekFrom::End is not used here because it introduces a race condition
-        // if the file grew immediately after the size was checked.
-        self.file.seek(SeekFrom::Start(new_size - 1)).await?;
-        let mut buffer = [0; 1];
-        self.file.read(&mut buffer).await?;
-        if buffer[0] != b'\n' {
-            warn!("{prefix} Log chunk does not end in newline.");
+        // Binary logs aren't split into lines.
+        if self.binary {
+            self.file.seek(SeekFrom::Start(self.cursor)).await?;
+            let mut buffer = vec![0; chunk_size as usize];
+            self.file.read_exact(&mut buffer).await?;
+            self.cursor = new_size;
+            self.event_tx.send(Event::NewLogBytes(buffer)).await?;
             return Ok(());
         }
 
+        // The file may have grown since its size was checked, and a writer may be in the middle of
+        // a line, so everything up to the current end is read, and only the complete lines are
+        // consumed. The rest is read again once the line is finished.
         self.file.seek(SeekFrom::Start(self.cursor)).await?;
-        // Don't read the final newline.
-        let mut buffer = vec![0; chunk_size as usize - 1];
-        self.file.read_exact(&mut buffer).await?;
+        let mut buffer = Vec::with_capacity(chunk_size as usize);
+        (&mut self.file)
+            .take(MAX_CHUNK_SIZE)
+            .read_to_end(&mut buffer)
+            .await?;
+        let ending = self.line_ending;
+        if self.mid_line {
+            let Some(start) = (0..buffer.len()).find(|&i| ends_line(&buffer, i, ending)) else {
+                // A `\r` at the end may be the start of the next terminator.
+                let keep = ending == LineEnding::Crlf && buffer.last() == Some(&b'\r');
+                self.cursor += (buffer.len() - usize::from(keep)) as u64;
+                return Ok(());
+            };
+            debug!("{prefix} Dropped the rest of a partial line.");
+            buffer.drain(..=start);
+            self.cursor += start as u64 + 1;
+            self.mid_line = false;
+        }
+        let Some(end) = (0..buffer.len()).rfind(|&i| ends_line(&buffer, i, ending)) else {
+            debug!("{prefix} Waiting for the rest of the line.");
+            return Ok(());
+        };
+        // Don't keep the final line ending.
+        buffer.truncate(match ending {
+            LineEnding::Crlf => end - 1,
+            LineEnding::Auto | LineEnding::Lf => end,
+        });
         let buffer_str = match String::from_utf8(buffer) {
             Ok(buffer_str) => buffer_str,
             Err(err) => {
                 error!("{prefix} Log chunk is not valid UTF-8: {err}");
-                self.cursor = new_size;
+                self.cursor += end as u64 + 1;
                 return Ok(());
             }
         };
-        self.cursor = new_size;
-        for line in buffer_str.lines() {
-            self.event_tx
-                .send(Event::NewLogLine(line.to_owned()))
-                .await?;
+        self.cursor += end as u64 + 1;
+        if self.batch_lines {
+            let lines = split_lines(&buffer_str, ending)
+                .into_iter()
+                .filter_map(|line| self.limiter.apply(line))
+                .collect();
+            self.event_tx.send(Event::LogLines(lines)).await?;
+            return Ok(());
+        }
+        for line in split_lines(&buffer_str, ending) {
+            let Some(line) = self.limiter.apply(line) else {
+                continue;
+            };
+            self.send_line(line).await?;
         }
 
         Ok(())
     }
 }
+
+/// Returns true if the bytes before the offset aren't a line ending, i.e. reading from the offset
+/// would start in the middle of a line.
+async fn is_mid_line(file: &mut File, offset: u64, ending: LineEnding) -> Result<bool> {
+    if offset == 0 {
+        return Ok(false);
+    }
+    let len = match ending {
+        LineEnding::Crlf => offset.min(2),
+        LineEnding::Auto | LineEnding::Lf => 1,
+    };
+    file.seek(SeekFrom::Start(offset - len)).await?;
+    let mut bytes = vec![0; len as usize];
+    file.read_exact(&mut bytes).await?;
+    Ok(!ends_line(&bytes, bytes.len() - 1, ending))
+}
+
+/// Whether the byte at `i` is the `\n` that ends a line.
+fn ends_line(buffer: &[u8], i: usize, ending: LineEnding) -> bool {
+    buffer[i] == b'\n' && (ending != LineEnding::Crlf || i > 0 && buffer[i - 1] == b'\r')
+}
+
+/// Splits text that ends with a complete line, without its final line ending, into lines.
+fn split_lines(text: &str, ending: LineEnding) -> Vec<&str> {
+    match ending {
+        LineEnding::Auto => text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect(),
+        LineEnding::Lf => text.split('\n').collect(),
+        LineEnding::Crlf => text.split("\r\n").collect(),
+    }
+}
+
+/// Reads backward from `end` and returns the lines whose timestamp is within `replay.since`, in
+/// order. Lines are assumed to be in chronological order, so reading stops at the first line that
+/// is too old. Lines without a timestamp are included if the line after them was.
+async fn read_lines_since(
+    file: &mut File,
+    end: u64,
+    replay: &ReplayConfig,
+    ending: LineEnding,
+) -> Result<Vec<String>> {
+    let cutoff = Utc::now() - replay.since;
+    let mut lines = Vec::new();
+    let mut pos = end;
+    // The bytes after `pos` that haven't been split into lines.
+    let mut rest = Vec::new();
+    loop {
+        while let Some(i) = (0..rest.len()).rfind(|&i| ends_line(&rest, i, ending)) {
+            let line = rest.split_off(i + 1);
+            rest.truncate(match ending {
+                LineEnding::Crlf => i - 1,
+                LineEnding::Auto | LineEnding::Lf => i,
+            });
+            if !replay_line(line, replay, cutoff, ending, &mut lines) {
+                lines.reverse();
+                return Ok(lines);
+            }
+        }
+        if pos == 0 {
+            replay_line(rest, replay, cutoff, ending, &mut lines);
+            lines.reverse();
+            return Ok(lines);
+        }
+
+        let len = REPLAY_CHUNK_SIZE.min(pos);
+        pos -= len;
+        file.seek(SeekFrom::Start(pos)).await?;
+        let mut chunk = vec![0; len as usize];
+        file.read_exact(&mut chunk).await?;
+        chunk.append(&mut rest);
+        rest = chunk;
+    }
+}
+
+/// Adds the line if it isn't older than the cutoff. Returns false once a line is too old.
+fn replay_line(
+    mut line: Vec<u8>,
+    replay: &ReplayConfig,
+    cutoff: DateTime<Utc>,
+    ending: LineEnding,
+    lines: &mut Vec<String>,
+) -> bool {
+    if ending == LineEnding::Auto && line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.is_empty() {
+        return true;
+    }
+    let line = String::from_utf8_lossy(&line).into_owned();
+    if parse_line_time(&line, replay).is_some_and(|time| time < cutoff) {
+        return false;
+    }
+    lines.push(line);
+    true
+}
+
+fn parse_line_time(line: &str, replay: &ReplayConfig) -> Option<DateTime<Utc>> {
+    let captures = replay.timestamp_regex.captures(line)?;
+    let timestamp = captures
+        .name("timestamp")
+        .or_else(|| captures.get(0))?
+        .as_str();
+    let Some(format) = &replay.timestamp_format else {
+        return DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|time| time.to_utc());
+    };
+    // Formats without an offset are in the local timezone.
+    match DateTime::parse_from_str(timestamp, format) {
+        Ok(time) => Some(time.to_utc()),
+        Err(_) => NaiveDateTime::parse_from_str(timestamp, format)
+            .ok()?
+            .and_local_timezone(Local)
+            .earliest()
+            .map(|time| time.to_utc()),
+    }
+}
+
+/// Reads up to the last `lines` lines of the log, or `MAX_TAIL_SIZE`, whichever is shorter.
+pub async fn read_tail(path: &Path, lines: usize) -> Result<String> {
+    let mut file = OpenOptions::new().read(true).open(path).await?;
+    let end = file.seek(SeekFrom::End(0)).await?;
+    let start = end.saturating_sub(MAX_TAIL_SIZE);
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut buffer = Vec::with_capacity((end - start) as usize);
+    file.take(end - start).read_to_end(&mut buffer).await?;
+    let buffer = String::from_utf8_lossy(&buffer);
+    let mut tail = buffer.lines().collect::<Vec<_>>();
+    // The first line is likely cut off.
+    if start > 0 && !tail.is_empty() {
+        tail.remove(0);
+    }
+    let skip = tail.len().saturating_sub(lines);
+    Ok(tail[skip..].join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::DataChange;
+    use regex::Regex;
+    use std::{fs::OpenOptions, io::Write};
+    use tokio::time::timeout;
+
+    async fn watch(path: &Path) -> Receiver<Event> {
+        let (watcher, event_rx) = new_watcher(path, LineEnding::Auto).await;
+        tokio::spawn(watcher.start());
+        event_rx
+    }
+
+    async fn new_watcher(path: &Path, line_ending: LineEnding) -> (LogWatcher, Receiver<Event>) {
+        let config = LogConfig {
+            path: path.to_owned(),
+            binary: false,
+            batch_lines: false,
+            dedup_consecutive: false,
+            reopen_timeout: None,
+            poll_interval: None,
+            replay: None,
+            line_limit: None,
+            line_ending,
+        };
+        let (event_tx, event_rx) = mpsc::channel(16);
+        let progress = Arc::new(LogProgress::new(path.to_owned()));
+        let watcher = LogWatcher::new("test".to_owned(), config, progress, event_tx)
+            .await
+            .unwrap();
+        (watcher, event_rx)
+    }
+
+    /// Returns the next line, or `None` if the log was rotated.
+    async fn next_line(event_rx: &mut Receiver<Event>) -> Option<String> {
+        let event = timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .expect("timed out waiting for an event")
+            .unwrap();
+        match event {
+            Event::NewLogLine { line, .. } => Some(line),
+            Event::LogRotated => None,
+            _ => panic!("unexpected event"),
+        }
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncation_rereads_the_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "one\ntwo\n");
+        let mut event_rx = watch(&path).await;
+
+        append(&path, "three\n");
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("three"));
+
+        // Like copytruncate, which keeps the file open.
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, "four\n");
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("four"));
+    }
+
+    #[tokio::test]
+    async fn rotation_reopens_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "one\n");
+        let mut event_rx = watch(&path).await;
+
+        std::fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        append(&path, "two\n");
+        assert_eq!(next_line(&mut event_rx).await, None);
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("two"));
+    }
+
+    #[tokio::test]
+    async fn deletion_waits_for_the_path_to_be_recreated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "one\n");
+        let mut event_rx = watch(&path).await;
+
+        std::fs::remove_file(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        append(&path, "two\n");
+        assert_eq!(next_line(&mut event_rx).await, None);
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("two"));
+    }
+
+    // notify drops its watch when it sees the file deleted, so the path may already be unwatched
+    // when the file is reopened, which used to panic.
+    #[tokio::test]
+    async fn reopening_an_unwatched_path_is_survived() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "one\n");
+        let (mut watcher, mut event_rx) = new_watcher(&path, LineEnding::Auto).await;
+        watcher.watcher.unwatch(&path).unwrap();
+
+        watcher.reinit_file_descriptors().await.unwrap();
+        assert_eq!(next_line(&mut event_rx).await, None);
+        tokio::spawn(watcher.start());
+        append(&path, "two\n");
+        // A reopened file is read from the start.
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("one"));
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("two"));
+    }
+
+    // A file that's replaced without a rename event on the watched path, e.g. by the poll watcher
+    // or a rotation that creates a new file, is only noticed because its inode changed.
+    #[tokio::test]
+    async fn replacement_without_a_rename_event_reopens_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "first line\n");
+        let (mut watcher, mut event_rx) = new_watcher(&path, LineEnding::Auto).await;
+        // Events are fed by hand, so inotify mustn't see the rename.
+        watcher.watcher.unwatch(&path).unwrap();
+
+        // The new file is smaller, so it doesn't look like the log grew.
+        let new = dir.path().join("app.log.new");
+        append(&new, "two\n");
+        std::fs::rename(&new, &path).unwrap();
+        watcher
+            .process_log_event(notify::Event::new(EventKind::Modify(ModifyKind::Data(
+                DataChange::Any,
+            ))))
+            .await
+            .unwrap();
+        assert_eq!(next_line(&mut event_rx).await, None);
+        assert_eq!(next_line(&mut event_rx).await.as_deref(), Some("two"));
+    }
+
+    #[tokio::test]
+    async fn repeated_rotation_is_survived() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "one\n");
+        let mut event_rx = watch(&path).await;
+
+        for i in 1..=3 {
+            std::fs::rename(&path, dir.path().join(format!("app.log.{i}"))).unwrap();
+            append(&path, "");
+        }
+        std::fs::remove_file(&path).unwrap();
+        append(&path, "two\n");
+        loop {
+            match next_line(&mut event_rx).await {
+                None => continue,
+                Some(line) => break assert_eq!(line, "two"),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_appends_are_read_as_whole_lines() {
+        const WRITERS: usize = 4;
+        const LINES: usize = 2000;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "");
+        let mut event_rx = watch(&path).await;
+
+        // Whole lines are appended with a single write, which the lock keeps from landing in the
+        // middle of a line that's written in two halves.
+        let lock = Arc::new(std::sync::Mutex::new(()));
+        let mut writers = Vec::new();
+        for writer in 0..WRITERS {
+            let (path, lock) = (path.clone(), lock.clone());
+            writers.push(std::thread::spawn(move || {
+                for i in 0..LINES {
+                    let _guard = lock.lock().unwrap();
+                    append(&path, &format!("{writer} {i} {}\n", "x".repeat(i % 100)));
+                }
+            }));
+        }
+        let (split_path, split_lock) = (path.clone(), lock.clone());
+        writers.push(std::thread::spawn(move || {
+            for i in 0..LINES / 10 {
+                let _guard = split_lock.lock().unwrap();
+                append(&split_path, &format!("{WRITERS} {i} first "));
+                std::thread::sleep(Duration::from_micros(200));
+                append(&split_path, "half\n");
+            }
+        }));
+
+        let mut next = [0; WRITERS + 1];
+        for _ in 0..WRITERS * LINES + LINES / 10 {
+            let line = next_line(&mut event_rx).await.unwrap();
+            let mut fields = line.splitn(3, ' ');
+            let writer: usize = fields.next().unwrap().parse().unwrap();
+            let i: usize = fields.next().unwrap().parse().unwrap();
+            let rest = fields.next().unwrap_or_default();
+            match writer {
+                WRITERS => assert_eq!(rest, "first half", "{line:?}"),
+                _ => assert_eq!(rest, "x".repeat(i % 100), "{line:?}"),
+            }
+            assert_eq!(i, next[writer], "{line:?}");
+            next[writer] += 1;
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn line_endings_end_lines() {
+        let buffer = b"a\nb\r\n";
+        assert!(ends_line(buffer, 1, LineEnding::Auto));
+        assert!(ends_line(buffer, 1, LineEnding::Lf));
+        assert!(!ends_line(buffer, 1, LineEnding::Crlf));
+        for ending in [LineEnding::Auto, LineEnding::Lf, LineEnding::Crlf] {
+            assert!(ends_line(buffer, 4, ending));
+            assert!(!ends_line(buffer, 3, ending));
+        }
+        // The `\r` may be in the previous chunk.
+        assert!(!ends_line(b"\n", 0, LineEnding::Crlf));
+        assert!(ends_line(b"\n", 0, LineEnding::Auto));
+    }
+
+    #[test]
+    fn lines_are_split_by_the_line_ending() {
+        let text = "one\r\ntwo\nthree\r\n";
+        assert_eq!(
+            split_lines(text, LineEnding::Auto),
+            ["one", "two", "three", ""]
+        );
+        assert_eq!(
+            split_lines(text, LineEnding::Lf),
+            ["one\r", "two", "three\r", ""]
+        );
+        assert_eq!(
+            split_lines(text, LineEnding::Crlf),
+            ["one", "two\nthree", ""]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_joins_line_endings_split_across_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        // The `\r` ends the second to last chunk, and the `\n` starts the last.
+        let long = "x".repeat(REPLAY_CHUNK_SIZE as usize - 3);
+        append(&path, &format!("one\r\n{long}\r\n"));
+        let replay = ReplayConfig {
+            since: Duration::from_secs(3600),
+            // Lines without a timestamp are all replayed.
+            timestamp_regex: Regex::new("^never$").unwrap(),
+            timestamp_format: None,
+        };
+
+        for (ending, expected) in [
+            (LineEnding::Auto, ["one".to_owned(), long.clone()]),
+            (LineEnding::Lf, ["one\r".to_owned(), format!("{long}\r")]),
+            (LineEnding::Crlf, ["one".to_owned(), long.clone()]),
+        ] {
+            let mut file = File::open(&path).await.unwrap();
+            let end = file.metadata().await.unwrap().len();
+            let lines = read_lines_since(&mut file, end, &replay, ending)
+                .await
+                .unwrap();
+            assert_eq!(lines, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn line_endings_split_across_writes_are_joined() {
+        for (ending, expected) in [
+            (LineEnding::Auto, ["one", "two"]),
+            (LineEnding::Lf, ["one\r", "two\r"]),
+            (LineEnding::Crlf, ["one", "two"]),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("app.log");
+            append(&path, "");
+            let (watcher, mut event_rx) = new_watcher(&path, ending).await;
+            tokio::spawn(watcher.start());
+
+            append(&path, "one\r");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            append(&path, "\ntwo\r\n");
+            for line in expected {
+                assert_eq!(next_line(&mut event_rx).await.as_deref(), Some(line));
+            }
+        }
+    }
+}
@@ -1,59 +1,846 @@
+mod audit_log;
+mod check_permissions;
+mod circuit_breaker;
+mod cli;
+mod condition;
 mod config;
+mod config_watcher;
+mod control;
+mod dir_watcher;
+mod fifo;
+mod file_watcher;
+mod heartbeat;
+mod line_limit;
 mod log_watcher;
+mod maintenance;
 mod monitor;
+mod notifier;
+mod on_error;
+mod schema;
+mod spool;
+mod ssh;
+mod syslog_forwarder;
+mod systemd_unit;
+mod template;
+mod trace;
+mod users;
+mod vault;
 
-use anyhow::{anyhow, Result};
-use log::error;
-use monitor::Monitor;
-use std::{process::exit, sync::Arc};
+use anyhow::{anyhow, bail, Result};
+use audit_log::AuditLog;
+use config::{Config, NotificationConfig, Severity};
+use config_watcher::ConfigWatcher;
+use daemonize::Daemonize;
+use log::{error, info, warn};
+use monitor::{Monitor, Shared, Stats};
+use notifier::Notification;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fs::{self, OpenOptions},
+    future, io,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{self, exit},
+    sync::{atomic::Ordering, Arc},
+};
+use syslog_forwarder::SyslogForwarder;
+use tokio::{
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+    sync::{mpsc, oneshot, Semaphore},
+    task::{self, JoinHandle},
+};
+use toml::Value;
+
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("RAMON_GIT_COMMIT"),
+    " ",
+    env!("RAMON_BUILD_DATE"),
+    ")"
+);
+
+fn main() {
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(2);
+        }
+    };
+
+    // These are handled before the config is loaded so that they work even if the config is
+    // broken.
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    if args.version {
+        println!("ramon {VERSION}");
+        return;
+    }
+    if args.print_config_schema {
+        println!("{:#}", schema::json_schema());
+        return;
+    }
+    if args.generate_systemd_unit {
+        match systemd_unit::generate(&args) {
+            Ok(unit) => print!("{unit}"),
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(cli::Command::TestRegex { pattern, input }) = &args.command {
+        match test_regex(pattern, input.as_deref()) {
+            Ok(true) => return,
+            Ok(false) => exit(1),
+            Err(err) => {
+                eprintln!("{err}");
+                exit(2);
+            }
+        }
+    }
+
+    if let Some(cli::Command::Ctl { command }) = &args.command {
+        match ctl(&args, command) {
+            Ok(true) => return,
+            Ok(false) => exit(1),
+            Err(err) => {
+                eprintln!("{err}");
+                exit(2);
+            }
+        }
+    }
+
+    // This must happen before the runtime starts, as forking only keeps the calling thread.
+    if args.daemonize {
+        if let Err(err) = daemonize(&args) {
+            eprintln!("{err}");
+            exit(1);
+        }
+    }
 
-#[tokio::main]
-async fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("ramon=info"))
         .init();
 
-    if let Err(err) = run().await {
+    let res = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| anyhow!("Failed to start runtime: {err}"))
+        .and_then(|runtime| {
+            if args.test_notify {
+                runtime.block_on(test_notify(&args))
+            } else if args.trace_config {
+                runtime.block_on(trace_config(&args))
+            } else if args.check_permissions {
+                runtime.block_on(check_permissions(&args))
+            } else if let Some(path) = &args.config_test_against {
+                runtime.block_on(config_test_against(&args, path))
+            } else {
+                runtime.block_on(run(&args))
+            }
+        });
+    if let Err(err) = res {
         eprintln!("{err}");
         exit(1);
     }
 }
 
-async fn run() -> Result<()> {
-    let doc = include_str!("../ramon.toml");
-    let config = config::parse(doc).map_err(|err| {
+/// Prints the captures of the pattern for the input, or for each line of stdin. Returns true if
+/// anything matched.
+fn test_regex(pattern: &str, input: Option<&str>) -> Result<bool> {
+    let regex = config::parse_regex("pattern", pattern)?;
+    let lines = match input {
+        Some(input) => vec![input.to_owned()],
+        None => io::stdin().lines().collect::<Result<_, _>>()?,
+    };
+    let mut matched = false;
+    for line in lines {
+        let Some(captures) = regex.captures(&line) else {
+            println!("No match: {line}");
+            continue;
+        };
+        matched = true;
+        println!("Match: {line}");
+        for (i, name) in regex.capture_names().enumerate() {
+            let value = captures.get(i).map(|capture| capture.as_str());
+            let label = match name {
+                Some(name) => format!("{i} ({name})"),
+                None => i.to_string(),
+            };
+            match value {
+                Some(value) => println!("  {label}: {value:?}"),
+                None => println!("  {label}: <none>"),
+            }
+        }
+    }
+    Ok(matched)
+}
+
+/// Sends the command to the running Ramon's control socket and prints the response. Returns false
+/// if the command failed.
+fn ctl(args: &cli::Args, command: &str) -> Result<bool> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| anyhow!("Failed to start runtime: {err}"))?
+        .block_on(async {
+            // Parsing the config requires a runtime.
+            let mut config = load_config(args)?;
+            select_shard(args, &mut config)?;
+            let Some(path) = config.control_socket else {
+                bail!("Key `control_socket` is not set in ramon.toml.");
+            };
+            control::send(&path, command).await
+        })
+}
+
+/// Forks into the background, redirecting output to the log file and writing the PID file.
+fn daemonize(args: &cli::Args) -> Result<()> {
+    if let Ok(pid) = fs::read_to_string(&args.pidfile) {
+        if let Ok(pid) = pid.trim().parse::<u32>() {
+            if Path::new(&format!("/proc/{pid}")).exists() {
+                bail!(
+                    "Ramon is already running with PID {pid} (according to {:?}).",
+                    args.pidfile
+                );
+            }
+        }
+    }
+
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.log_file)
+        .map_err(|err| anyhow!("Failed to open {:?}: {err}", args.log_file))?;
+    Daemonize::new()
+        .pid_file(&args.pidfile)
+        // Keep relative paths in the config working.
+        .working_directory(env::current_dir()?)
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        .start()
+        .map_err(|err| anyhow!("Failed to daemonize: {err}"))?;
+    Ok(())
+}
+
+fn load_config(args: &cli::Args) -> Result<Config> {
+    parse_config(args, &read_config(args)?)
+}
+
+fn read_config(args: &cli::Args) -> Result<String> {
+    match &args.config {
+        None => Ok(include_str!("../ramon.toml").to_owned()),
+        Some(path) => {
+            fs::read_to_string(path).map_err(|err| anyhow!("Failed to read {path:?}: {err}"))
+        }
+    }
+}
+
+fn config_name(args: &cli::Args) -> String {
+    match &args.config {
+        None => "ramon.toml".to_owned(),
+        Some(path) => path.display().to_string(),
+    }
+}
+
+fn parse_config(args: &cli::Args, doc: &str) -> Result<Config> {
+    let name = config_name(args);
+    config::parse(doc, &args.exec_policy).map_err(|err| {
         anyhow!(
-            r#"Failed to parse ramon.toml: {err}
+            r#"Failed to parse {name}: {err}
 
 Refer to https://github.com/reujab/ramon#specification-wip"#
         )
-    })?;
+    })
+}
+
+/// Sends a test notification with every enabled notify config.
+async fn test_notify(args: &cli::Args) -> Result<()> {
+    let config = load_config(args)?;
+    let notification = Notification {
+        monitor: "test".into(),
+        severity: Severity::Info,
+        source: "test",
+        title: "Test notification".into(),
+        body: format!("This is a test notification from ramon {VERSION}."),
+        variables: HashMap::new(),
+        test: true,
+    };
+    let http = notifier::http_client(&config.http)?;
+    let mut names = config.notifications.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut failed = false;
+    for name in names {
+        let notify_config = &config.notifications[name];
+        if !notify_config.enabled {
+            info!("[{name}] Skipping disabled config.");
+            continue;
+        }
+        match notifier::send(name, name, notify_config, &notification, None, &http).await {
+            Ok(true) => info!("[{name}] Sent test notification."),
+            Ok(false) => info!("[{name}] No backends are configured."),
+            Err(err) => {
+                error!("[{name}] {err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        bail!("Failed to send test notifications.");
+    }
+    Ok(())
+}
+
+/// Prints whether Ramon can access each file and binary in the config, so that permission errors
+/// are found before monitors hit them at runtime.
+async fn check_permissions(args: &cli::Args) -> Result<()> {
+    let mut config = load_config(args)?;
+    select_shard(args, &mut config)?;
+    let failed = check_permissions::run(&config);
+    if failed > 0 {
+        bail!("{failed} permission checks failed.");
+    }
+    Ok(())
+}
+
+/// Prints each setting with where it came from. The config is validated first, so that the trace
+/// matches what Ramon would run.
+async fn trace_config(args: &cli::Args) -> Result<()> {
+    let config = load_config(args)?;
+    for line in trace::trace(&config_name(args), &config.provenance) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Feeds each line of the file to the monitors that follow a log, and prints which would have
+/// run their actions. Sources aren't started, and actions aren't run.
+async fn config_test_against(args: &cli::Args, path: &Path) -> Result<()> {
+    let mut config = load_config(args)?;
+    select_shard(args, &mut config)?;
+    let lines =
+        fs::read_to_string(path).map_err(|err| anyhow!("Failed to read {path:?}: {err}"))?;
+
+    let shared = Shared {
+        notify_configs: Arc::new(config.notifications),
+        audit_tx: None,
+        syslog_tx: None,
+        notify_limit: None,
+        last_true: Arc::default(),
+        down: Arc::default(),
+        log_watchers: Arc::default(),
+        emit_events: false,
+        dry_run: true,
+        http: notifier::http_client(&config.http)?,
+        maintenance: Arc::default(),
+        env_names: Arc::new(config.env_names),
+        daemons: Arc::default(),
+    };
+    let mut monitors = Vec::new();
+    for mut monitor_config in config.monitors {
+        let source = if monitor_config.log.is_some() {
+            "log"
+        } else if monitor_config.service.is_some() {
+            "service"
+        } else if monitor_config.ssh.is_some() {
+            "ssh"
+        } else {
+            continue;
+        };
+        monitor_config.every = None;
+        monitor_config.log = None;
+        monitor_config.watch.clear();
+        monitor_config.watch_dir = None;
+        monitor_config.service = None;
+        monitor_config.ssh = None;
+        monitor_config.service_state = None;
+        let name = monitor_config.name.clone();
+        let monitor = Monitor::new(monitor_config, shared.clone())
+            .await
+            .map_err(|err| anyhow!("Monitor `{name}`: {err}"))?;
+        monitors.push((monitor, source));
+    }
+    if monitors.is_empty() {
+        bail!("No monitors have a `log`, `service`, or `ssh` source.");
+    }
+    monitors.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let mut fired = 0;
+    let mut line_count = 0;
+    for (i, line) in lines.lines().enumerate() {
+        line_count += 1;
+        for (monitor, source) in &mut monitors {
+            let Some(variables) = monitor.test_line(source, line.to_owned()).await? else {
+                continue;
+            };
+            fired += 1;
+            // Sorted so that the output can be diffed.
+            let variables = variables.into_iter().collect::<BTreeMap<_, _>>();
+            println!(
+                "Line {}: `{}` would run its actions with {}",
+                i + 1,
+                monitor.name,
+                serde_json::to_string(&variables)?,
+            );
+        }
+    }
+    println!("{fired} actions would have run for {line_count} lines.");
+    Ok(())
+}
+
+async fn run(args: &cli::Args) -> Result<()> {
+    let doc = read_config(args)?;
+    let mut config = parse_config(args, &doc)?;
+    select_shard(args, &mut config)?;
+    log_summary(&config);
+
+    let config_watcher = match &args.config {
+        Some(path) if args.watch_config => {
+            let mut paths = vec![path.as_path()];
+            paths.extend(config.secrets_file.as_deref());
+            Some(ConfigWatcher::new(&paths)?)
+        }
+        _ => None,
+    };
+    let secrets = config
+        .secrets_file
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok());
 
     let notify_config = Arc::new(config.notifications);
+    let lifecycle = config
+        .notify_lifecycle
+        .as_deref()
+        .map(|target| (target, notify_config.clone(), config.monitors.len()));
+
+    let (audit_tx, audit_log) = match config.audit_log {
+        None => (None, None),
+        Some(path) => {
+            let (audit_tx, audit_rx) = mpsc::channel(64);
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            let audit_log = AuditLog::new(path, audit_rx).await?;
+            let handle = tokio::spawn(audit_log.start(shutdown_rx));
+            (Some(audit_tx), Some((shutdown_tx, handle)))
+        }
+    };
+
+    let syslog_tx = config.syslog.map(|syslog| {
+        let (syslog_tx, syslog_rx) = mpsc::channel(64);
+        let forwarder = SyslogForwarder::new(syslog, syslog_rx);
+        tokio::task::spawn_blocking(|| forwarder.start());
+        syslog_tx
+    });
+
+    let shared = Shared {
+        notify_configs: notify_config,
+        audit_tx,
+        syslog_tx,
+        notify_limit: config
+            .max_concurrent_notifications
+            .map(|max| Arc::new(Semaphore::new(max))),
+        last_true: Arc::default(),
+        down: Arc::default(),
+        log_watchers: Arc::default(),
+        emit_events: args.emit_events,
+        dry_run: false,
+        http: notifier::http_client(&config.http)?,
+        maintenance: Arc::default(),
+        env_names: Arc::new(config.env_names),
+        daemons: Arc::default(),
+    };
 
     // Process monitors.
     let mut monitors = Vec::with_capacity(config.monitors.len());
     for monitor_config in config.monitors {
         let name = monitor_config.name.clone();
-        let monitor = Monitor::new(monitor_config, notify_config.clone())
+        let monitor = Monitor::new(monitor_config, shared.clone())
             .await
             .map_err(|err| anyhow!("Monitor `{}`: {err}", name))?;
         monitors.push(monitor);
     }
+    let stats = monitors
+        .iter()
+        .map(|monitor| (monitor.name.clone(), monitor.stats.clone()))
+        .collect::<Vec<_>>();
+    if let Some(path) = &config.control_socket {
+        let monitors = monitors.iter().map(Monitor::control_handle).collect();
+        let path = path.clone();
+        let maintenance = shared.maintenance.clone();
+        tokio::spawn(async move {
+            if let Err(err) = control::serve(path, monitors, maintenance).await {
+                error!("Control socket: {err}");
+            }
+        });
+
+        let maintenance = shared.maintenance.clone();
+        let summary = config
+            .maintenance_summary
+            .clone()
+            .map(|target| (target, shared.notify_configs.clone()));
+        let http = shared.http.clone();
+        tokio::spawn(async move {
+            loop {
+                let suppressed = maintenance.ended().await;
+                let total = suppressed.values().sum::<u64>();
+                info!("Maintenance ended. {total} notifications were suppressed.");
+                if let Some((target, notify_configs)) = &summary {
+                    notify_maintenance_summary(target, notify_configs, &http, &suppressed).await;
+                }
+            }
+        });
+    }
     let mut handles = Vec::with_capacity(monitors.len());
     for mut monitor in monitors {
         let handle = tokio::spawn(async move {
+            // A monitor only returns without an error once `stop_on` matched.
             let res = monitor.start().await;
             if let Err(err) = &res {
                 error!("[{}] {err}", monitor.name);
+                error!("[{}] Monitor exited early.", monitor.name);
             }
-            error!("[{}] Monitor exited early.", monitor.name);
             res
         });
         handles.push(handle);
     }
-    for handle in handles {
-        handle.await??;
+    if let Some((target, notify_configs, monitors)) = &lifecycle {
+        notify_lifecycle(target, notify_configs, &shared.http, "started", *monitors).await;
+    }
+    if let Some(heartbeat) = config.heartbeat {
+        tokio::spawn(heartbeat::run(heartbeat, shared.http.clone()));
+    }
+    // Configs inherit `spool_dir` from `notify.default`, so several may share a directory.
+    let spool_dirs = shared
+        .notify_configs
+        .values()
+        .filter_map(|config| config.spool_dir.clone())
+        .collect::<HashSet<_>>();
+    for dir in spool_dirs {
+        let notify_configs = shared.notify_configs.clone();
+        let limit = shared.notify_limit.clone();
+        tokio::spawn(spool::run(dir, notify_configs, limit, shared.http.clone()));
+    }
+    let monitors_exited = async {
+        for handle in handles {
+            handle.await??;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    tokio::select! {
+        res = monitors_exited => {
+            res?;
+            info!("Every monitor has stopped. Shutting down.");
+            if let Some((target, notify_configs, monitors)) = &lifecycle {
+                notify_lifecycle(target, notify_configs, &shared.http, "stopped", *monitors).await;
+            }
+            stop(&stats, &shared, config.control_socket.as_deref(), audit_log).await;
+            Ok(())
+        }
+        res = shutdown_signal() => {
+            res?;
+            info!("Shutting down.");
+            if let Some((target, notify_configs, monitors)) = &lifecycle {
+                notify_lifecycle(target, notify_configs, &shared.http, "stopped", *monitors).await;
+            }
+            stop(&stats, &shared, config.control_socket.as_deref(), audit_log).await;
+            Ok(())
+        }
+        res = config_changed(args, &doc, secrets, config_watcher) => {
+            res?;
+            info!("Restarting with the new config.");
+            stop(&stats, &shared, config.control_socket.as_deref(), audit_log).await;
+            restart()
+        }
+    }
+}
+
+/// Sends a notification that Ramon started or stopped, so that gaps in coverage are noticed.
+async fn notify_lifecycle(
+    target: &str,
+    notify_configs: &HashMap<String, NotificationConfig>,
+    http: &reqwest::Client,
+    event: &str,
+    monitors: usize,
+) {
+    let notify_config = &notify_configs[target];
+    if !notify_config.enabled {
+        return;
+    }
+    let hostname = hostname();
+    let notification = Notification {
+        monitor: "ramon".into(),
+        severity: Severity::Info,
+        source: "lifecycle",
+        title: format!("Ramon {event} on {hostname}"),
+        body: format!("Ramon {VERSION} {event} on {hostname} with {monitors} monitors."),
+        variables: HashMap::from([
+            ("event".into(), Value::String(event.into())),
+            ("hostname".into(), Value::String(hostname)),
+            ("version".into(), Value::String(VERSION.into())),
+            ("monitors".into(), Value::Integer(monitors as i64)),
+        ]),
+        test: false,
+    };
+    match notifier::send(target, target, notify_config, &notification, None, http).await {
+        Ok(true) => info!("[{target}] Sent `{event}` notification."),
+        Ok(false) => {}
+        Err(err) => error!("[{target}] Failed to send `{event}` notification: {err}"),
+    }
+}
+
+/// Sends the number of notifications each monitor suppressed during a maintenance window.
+async fn notify_maintenance_summary(
+    target: &str,
+    notify_configs: &HashMap<String, NotificationConfig>,
+    http: &reqwest::Client,
+    suppressed: &BTreeMap<String, u64>,
+) {
+    let notify_config = &notify_configs[target];
+    if !notify_config.enabled {
+        return;
+    }
+    let hostname = hostname();
+    let total = suppressed.values().sum::<u64>();
+    let mut body = format!(
+        "Maintenance on {hostname} ended. {total} notifications were suppressed during it."
+    );
+    for (monitor, count) in suppressed {
+        body += &format!("\n{monitor}: {count}");
+    }
+    let notification = Notification {
+        monitor: "ramon".into(),
+        severity: Severity::Info,
+        source: "maintenance",
+        title: format!("Maintenance on {hostname} ended"),
+        body,
+        variables: HashMap::from([
+            ("hostname".into(), Value::String(hostname)),
+            ("suppressed".into(), Value::Integer(total as i64)),
+        ]),
+        test: false,
+    };
+    match notifier::send(target, target, notify_config, &notification, None, http).await {
+        Ok(true) => info!("[{target}] Sent maintenance summary."),
+        Ok(false) => {}
+        Err(err) => error!("[{target}] Failed to send maintenance summary: {err}"),
     }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: The buffer is valid for its length, and it's truncated at the first NUL below.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "unknown".into();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
 
+/// Resolves once the config has changed and the new config is valid. An invalid config is
+/// reported and otherwise ignored, so that Ramon keeps running with the old config.
+async fn config_changed(
+    args: &cli::Args,
+    doc: &str,
+    secrets: Option<String>,
+    watcher: Option<ConfigWatcher>,
+) -> Result<()> {
+    let Some(mut watcher) = watcher else {
+        return future::pending().await;
+    };
+    loop {
+        watcher.changed().await?;
+        let new_doc = match read_config(args) {
+            Ok(new_doc) => new_doc,
+            Err(err) => {
+                error!("{err}. Keeping the current config.");
+                continue;
+            }
+        };
+        let changes = config_watcher::diff(doc, &new_doc);
+        // Parsing may block on user and group lookups and Vault, while the monitors are running.
+        let new_config = match task::block_in_place(|| parse_config(args, &new_doc)) {
+            Ok(new_config) => new_config,
+            Err(err) => {
+                error!("{err}\n\nKeeping the current config.");
+                continue;
+            }
+        };
+        let new_secrets = new_config
+            .secrets_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok());
+        if changes.is_empty() && new_secrets == secrets {
+            info!("The config was saved without changes.");
+            continue;
+        }
+        if !changes.is_empty() {
+            info!("Config changed: {}.", changes.join(", "));
+        }
+        if new_secrets != secrets {
+            info!("Secrets file changed.");
+        }
+        return Ok(());
+    }
+}
+
+/// Replaces the process with a new instance of Ramon, which loads the new config. The process is
+/// already in the background, so it isn't daemonized again.
+fn restart() -> Result<()> {
+    let args = env::args().skip(1).filter(|arg| arg != "--daemonize");
+    let err = process::Command::new(env::current_exe()?).args(args).exec();
+    bail!("Failed to restart: {err}");
+}
+
+async fn stop(
+    stats: &[(String, Arc<Stats>)],
+    shared: &Shared,
+    control_socket: Option<&Path>,
+    audit_log: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+) {
+    log_stats(stats);
+    shared.daemons.stop().await;
+    if let Some(path) = control_socket {
+        let _ = control::remove_socket(path);
+    }
+    if let Some(audit_log) = audit_log {
+        stop_audit_log(audit_log).await;
+    }
+}
+
+/// Drops the monitors that aren't in the group selected with `--shard`.
+fn select_shard(args: &cli::Args, config: &mut Config) -> Result<()> {
+    let Some(shard) = &args.shard else {
+        return Ok(());
+    };
+    config
+        .monitors
+        .retain(|monitor| monitor.group.as_ref() == Some(shard));
+    if config.monitors.is_empty() {
+        bail!("No monitors are in group `{shard}`.");
+    }
+    info!("Running the monitors in group `{shard}`.");
+
+    // Each instance gets its own socket and audit log, since the config is shared.
+    let with_shard = |path: &PathBuf| {
+        let mut path = path.clone().into_os_string();
+        path.push(format!(".{shard}"));
+        PathBuf::from(path)
+    };
+    config.control_socket = config.control_socket.as_ref().map(with_shard);
+    config.audit_log = config.audit_log.as_ref().map(with_shard);
+
+    // `require` and `depends_on` can only see monitors in the same instance.
+    for monitor in &config.monitors {
+        for name in monitor
+            .require
+            .iter()
+            .flat_map(|require| require.all.iter().chain(&require.any))
+        {
+            if !config.monitors.iter().any(|other| other.name == *name) {
+                warn!(
+                    "[{}] Required monitor `{name}` is not in group `{shard}`.",
+                    monitor.name
+                );
+            }
+        }
+        for name in &monitor.depends_on {
+            if !config.monitors.iter().any(|other| other.name == *name) {
+                warn!(
+                    "[{}] Dependency `{name}` is not in group `{shard}`, so it's never considered down.",
+                    monitor.name
+                );
+            }
+        }
+    }
     Ok(())
 }
+
+/// Logs which sources and notify configs were loaded.
+fn log_summary(config: &Config) {
+    let monitors = &config.monitors;
+    let count = |has_source: fn(&config::MonitorConfig) -> bool| {
+        monitors
+            .iter()
+            .filter(|monitor| has_source(monitor))
+            .count()
+    };
+    info!(
+        "Loaded {} monitors: {} log, {} service, {} ssh, {} service_state, {} every, {} watch.",
+        monitors.len(),
+        count(|monitor| monitor.log.is_some()),
+        count(|monitor| monitor.service.is_some()),
+        count(|monitor| monitor.ssh.is_some()),
+        count(|monitor| monitor.service_state.is_some()),
+        count(|monitor| monitor.every.is_some()),
+        count(|monitor| !monitor.watch.is_empty() || monitor.watch_dir.is_some()),
+    );
+
+    let mut names = config.notifications.keys().collect::<Vec<_>>();
+    names.sort();
+    let targets = names
+        .into_iter()
+        .map(|name| {
+            let notify_config = &config.notifications[name];
+            let mut backends = Vec::new();
+            if notify_config.smtp.is_some() {
+                backends.push("smtp");
+            }
+            if notify_config.webhook.is_some() {
+                backends.push("webhook");
+            }
+            if backends.is_empty() {
+                backends.push("no backends");
+            }
+            if !notify_config.enabled {
+                backends.push("disabled");
+            }
+            format!("{name} ({})", backends.join(", "))
+        })
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        info!("No notify configs.");
+    } else {
+        info!("Notify configs: {}.", targets.join(", "));
+    }
+}
+
+/// Resolves when Ramon receives SIGINT or SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
+
+async fn stop_audit_log((shutdown_tx, handle): (oneshot::Sender<()>, JoinHandle<()>)) {
+    let _ = shutdown_tx.send(());
+    if let Err(err) = handle.await {
+        error!("Audit log: {err}");
+    }
+}
+
+fn log_stats(stats: &[(String, Arc<Stats>)]) {
+    for (name, stats) in stats {
+        info!(
+            "[{name}] {} events, {} actions, {} notifications sent, {} notification failures, {} \
+             exec failures, {} source errors, {} circuit breaker trips",
+            stats.events.load(Ordering::Relaxed),
+            stats.actions.load(Ordering::Relaxed),
+            stats.notifications.load(Ordering::Relaxed),
+            stats.notify_failures.load(Ordering::Relaxed),
+            stats.exec_failures.load(Ordering::Relaxed),
+            stats.source_errors.load(Ordering::Relaxed),
+            stats.breaker_trips.load(Ordering::Relaxed),
+        );
+    }
+}
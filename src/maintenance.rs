@@ -0,0 +1,79 @@
+use std::{
+    collections::BTreeMap,
+    mem::take,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time::sleep_until};
+
+/// A window during which notifications are muted, set through the control socket.
+#[derive(Default)]
+pub struct Maintenance {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    until: Option<Instant>,
+    /// The number of notifications each monitor didn't send during the window.
+    suppressed: BTreeMap<String, u64>,
+}
+
+impl Maintenance {
+    /// Mutes notifications for the duration, replacing the current window if there is one.
+    pub fn start(&self, duration: Duration) {
+        self.state.lock().unwrap().until = Some(Instant::now() + duration);
+        self.changed.notify_waiters();
+    }
+
+    /// Ends the current window now. Returns false if there wasn't one.
+    pub fn end(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.until.is_none() {
+            return false;
+        }
+        state.until = Some(Instant::now());
+        drop(state);
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// Returns how much of the window is left, if notifications are muted.
+    pub fn remaining(&self) -> Option<Duration> {
+        let until = self.state.lock().unwrap().until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    /// Returns how much of the window is left, and counts a suppressed notification for the
+    /// monitor, if notifications are muted.
+    pub fn suppress(&self, monitor: &str) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let remaining = state.until?.checked_duration_since(Instant::now())?;
+        *state.suppressed.entry(monitor.to_owned()).or_default() += 1;
+        Some(remaining)
+    }
+
+    /// Resolves when a window ends, with the number of notifications each monitor suppressed.
+    pub async fn ended(&self) -> BTreeMap<String, u64> {
+        loop {
+            // Created before the state is read, so that a change in between isn't missed.
+            let changed = self.changed.notified();
+            let until = self.state.lock().unwrap().until;
+            let Some(until) = until else {
+                changed.await;
+                continue;
+            };
+            tokio::select! {
+                _ = sleep_until(until.into()) => {}
+                _ = changed => continue,
+            }
+            let mut state = self.state.lock().unwrap();
+            // The window may have been extended while sleeping.
+            if state.until.is_some_and(|until| until <= Instant::now()) {
+                state.until = None;
+                return take(&mut state.suppressed);
+            }
+        }
+    }
+}
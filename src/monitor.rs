@@ -1,54 +1,492 @@
 use crate::{
-    config::{value_to_string, Exec, MonitorConfig, Notification, NotificationConfig},
-    log_watcher::LogWatcher,
+    audit_log::AuditEntry,
+    circuit_breaker,
+    condition::Condition,
+    config::{
+        capture_variables, value_to_f64, value_to_string, AfterExec, CircuitBreakerConfig,
+        CooldownMode, EnvNames, EnvNewlines, Exec, ExecMode, LineEnding, LineLimit, LogConfig,
+        MonitorConfig, Notification, NotificationConfig, Require, ScheduleEntry, Severity,
+        Transform,
+    },
+    control::MonitorHandle,
+    dir_watcher::DirWatcher,
+    fifo::{self, Fifo},
+    file_watcher::{FileWatcher, PermissionsChange},
+    line_limit::LineLimiter,
+    log_watcher::{self, LogProgress, LogWatcher},
+    maintenance::Maintenance,
+    notifier, on_error, ssh, template,
 };
 use anyhow::{anyhow, bail, Result};
-use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, Message,
-    SmtpTransport, Transport,
-};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use chrono_tz::Tz;
 use log::{debug, error, info, warn};
-use regex::Regex;
+use regex::{Captures, Regex};
 use std::{
-    collections::{HashMap, HashSet},
-    mem::replace,
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    future::pending,
+    io::{self, Write},
+    mem::{replace, take},
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
     process::Stdio,
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    fs::{create_dir, rename, OpenOptions},
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
-    process::Command,
-    sync::mpsc::{self, Receiver},
+    fs::{self, create_dir, rename, File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    process::{Child, ChildStdout, Command},
+    sync::{
+        mpsc::{self, error::TrySendError, Receiver, Sender, WeakSender},
+        watch, Semaphore,
+    },
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
 };
 use toml::Value;
 
+const SERVICE_STATE_INTERVAL: Duration = Duration::from_secs(5);
+/// The window `max_notifications_per_hour` is counted over.
+const NOTIFY_CAP_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// How long the `service` unit's properties are reused, so that a burst of matching lines
+/// doesn't run `systemctl` for each one.
+const UNIT_PROPERTIES_TTL: Duration = Duration::from_secs(5);
+const DAEMON_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const DAEMON_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How many suffixes are tried for a `move_to` file before giving up.
+const MAX_MOVE_SUFFIX: usize = 1000;
+/// How often a log monitor logs how far behind it is, at the debug level.
+const LAG_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Handles shared by every monitor.
+#[derive(Clone)]
+pub struct Shared {
+    pub notify_configs: Arc<HashMap<String, NotificationConfig>>,
+    pub audit_tx: Option<Sender<AuditEntry>>,
+    pub syslog_tx: Option<Sender<AuditEntry>>,
+    /// Bounds the number of notifications being sent at once across all monitors.
+    pub notify_limit: Option<Arc<Semaphore>>,
+    /// When each monitor's conditions were last all true, for `require`.
+    pub last_true: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Which monitors are down, for `depends_on`.
+    pub down: Arc<Mutex<HashMap<String, Down>>>,
+    /// Whether each action is printed to stdout as JSON.
+    pub emit_events: bool,
+    /// Log watchers that are shared by monitors following the same log with the same settings.
+    pub log_watchers: Arc<Mutex<HashMap<LogWatcherKey, SharedLog>>>,
+    /// Whether actions are recorded in `Monitor::dry_run_variables` instead of run, and state
+    /// isn't read from or written to disk.
+    pub dry_run: bool,
+    /// Shared by every webhook so that connections are reused.
+    pub http: reqwest::Client,
+    /// Mutes notifications while a maintenance window set through the control socket is active.
+    pub maintenance: Arc<Maintenance>,
+    pub env_names: Arc<EnvNames>,
+    /// Stopped before Ramon exits or restarts, since restarting replaces the process without
+    /// dropping the children.
+    pub daemons: Arc<Daemons>,
+}
+
+/// The processes started with `exec_mode = "daemon"`.
+#[derive(Default)]
+pub struct Daemons(Mutex<Vec<Arc<Daemon>>>);
+
+impl Daemons {
+    /// Kills every daemon and waits for them to exit.
+    pub async fn stop(&self) {
+        let daemons = take(&mut *self.0.lock().unwrap());
+        for daemon in daemons {
+            daemon.stop().await;
+        }
+    }
+}
+
+/// A monitor's `exec_mode = "daemon"` process, and the task that restarts it.
+pub struct Daemon {
+    stop_tx: watch::Sender<bool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    /// Keeps the monitor running even if it has no other source.
+    _event_tx: Sender<Event>,
+}
+
+impl Daemon {
+    /// Kills the process and waits for it to exit. It isn't restarted afterwards.
+    async fn stop(&self) {
+        self.stop_tx.send_replace(true);
+        let handle = self.handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+    }
+}
+
+/// Why a monitor is considered down by the monitors that depend on it.
+pub struct Down {
+    /// When the monitor last ran its actions.
+    since: Instant,
+    /// Whether it stays down until its `service_state` unit is active again, rather than for
+    /// `depends_on_within`.
+    until_recovered: bool,
+}
+
+/// The settings that must match for monitors to share a log watcher.
+#[derive(PartialEq, Eq, Hash)]
+pub struct LogWatcherKey {
+    path: PathBuf,
+    binary: bool,
+    batch_lines: bool,
+    dedup_consecutive: bool,
+    reopen_timeout: Option<Duration>,
+    poll_interval: Option<Duration>,
+    line_limit: Option<LineLimit>,
+    line_ending: LineEnding,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// The monitors a shared source sends its events to.
+type Subscribers = Arc<Mutex<Vec<Sender<Event>>>>;
+
+/// A log watcher's subscribers, and how far it has read.
+type SharedLog = (Subscribers, Arc<LogProgress>);
+
 pub struct Monitor {
     pub name: String,
-    notify_config: NotificationConfig,
+    pub stats: Arc<Stats>,
+    shared: Shared,
 
     event_rx: Receiver<Event>,
+    /// For the control socket.
+    event_tx: WeakSender<Event>,
+    paused: Arc<AtomicBool>,
+    /// The variables of the last actions that would have run in a dry run.
+    dry_run_variables: Option<HashMap<String, Value>>,
     last_action_time: Option<Instant>,
+    last_action_severity: Severity,
+    first_match_fired: bool,
+    /// When the `service_state` unit was first seen down, and whether that outage was alerted.
+    down_since: Option<(Instant, bool)>,
+    down_for: Duration,
+    /// Whether the last `exec` child is still running.
+    exec_running: Arc<AtomicBool>,
+    skip_if_running: bool,
+
+    severity: Severity,
+    labels: HashMap<String, String>,
+    timezone: Tz,
+    first_match_only: bool,
 
     cooldown: Option<Duration>,
+    cooldown_mode: CooldownMode,
+    /// When the event that opened the current leading `cooldown` window ran or queued the
+    /// actions.
+    last_trigger_time: Option<Instant>,
+    /// Actions are suppressed until then.
+    warmup_until: Option<Instant>,
     log_regex: Option<Regex>,
     ignore_regex: Option<Regex>,
+    log_bytes_regex: Option<regex::bytes::Regex>,
+    ignore_bytes_regex: Option<regex::bytes::Regex>,
+    transform: Vec<Transform>,
+    ignore_if: Vec<(String, Regex)>,
+    /// Sets the `severity` variable, since it varies with the journal's `PRIORITY`.
+    severity_from_priority: bool,
+    content_regex: Option<Regex>,
+    /// Files bigger than this many bytes aren't read for `match_content`.
+    max_file_size: u64,
+    fire_when_skipped: bool,
+    /// Whether each `watch` file last matched `match_content`, if `match_content_changes` is set.
+    content_matched: Option<HashMap<PathBuf, bool>>,
     unique: Option<Unique>,
+    changed: Option<Changed>,
+    r#if: Option<Condition>,
+    rate: Option<Rate>,
     threshold: Option<Threshold>,
+    require: Option<Require>,
+    depends_on: Vec<String>,
+    depends_on_within: Duration,
+    average: Option<Average>,
+    anomaly: Option<Anomaly>,
+    confirm_after: Option<Duration>,
+    confirm_reset: Option<Regex>,
+    stop_on: Option<Regex>,
+    pending: Option<Pending>,
 
     exec: Option<Exec>,
-    notify: Option<Notification>,
+    exec_parse: Option<Regex>,
+    exec_timeout: Duration,
+    after_exec: AfterExec,
+    /// `watch_dir` files whose events were suppressed, which the directory watcher fires again.
+    deferred_drops: Option<Arc<Mutex<HashSet<PathBuf>>>>,
+    exec_pty: bool,
+    env_newlines: EnvNewlines,
+    fifo: Option<Fifo>,
+    notify: Vec<Notification>,
+    notify_cap: Option<NotifyCap>,
+    fingerprints: Option<Fingerprints>,
+    /// When notifications were sent with each notify config, for `escalate`.
+    notify_history: Mutex<HashMap<String, VecDeque<Instant>>>,
+    /// The path of `log`, for `notify.tail`.
+    log_path: Option<PathBuf>,
+    /// How far the `log` watcher has read, which may be shared with other monitors.
+    log_progress: Option<Arc<LogProgress>>,
+    /// The `service` unit, and its properties from the last time they were looked up.
+    service: Option<String>,
+    unit_properties: Option<(Instant, HashMap<String, Value>)>,
+    daemon: Option<Arc<Daemon>>,
+}
+
+/// Counters that are logged when Ramon shuts down.
+#[derive(Default)]
+pub struct Stats {
+    pub events: AtomicU64,
+    pub actions: AtomicU64,
+    pub notifications: AtomicU64,
+    pub exec_failures: AtomicU64,
+    pub source_errors: AtomicU64,
+    pub notify_failures: AtomicU64,
+    pub breaker_trips: AtomicU64,
+    /// The most recent failure, which is included in `on_error` notifications.
+    pub last_error: Mutex<Option<String>>,
+}
+
+impl Stats {
+    pub fn exec_failed(&self, err: impl Display) {
+        self.exec_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(format!("exec: {err}"));
+    }
+
+    pub fn source_failed(&self, source: &str, err: impl Display) {
+        self.source_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(format!("{source}: {err}"));
+    }
+
+    pub fn notify_failed(&self, target: &str, err: impl Display) {
+        self.notify_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(format!("Failed to notify `{target}`: {err}"));
+    }
+
+    /// The number of failures of every kind.
+    pub fn failures(&self) -> u64 {
+        self.exec_failures.load(Ordering::Relaxed)
+            + self.source_errors.load(Ordering::Relaxed)
+            + self.notify_failures.load(Ordering::Relaxed)
+    }
 }
 
+#[derive(Clone)]
 pub enum Event {
     Tick,
-    NewLogLine(String),
+    NewLogLine {
+        /// Either `log`, `service`, or `ssh`.
+        source: &'static str,
+        line: String,
+        /// The severity reported by the source, if any. Falls back to the monitor's `severity`.
+        severity: Option<Severity>,
+        /// How many times the line was repeated in a row, if the log has `dedup_consecutive`.
+        repeat_count: Option<usize>,
+    },
+    /// The lines of a chunk of a log with `batch_lines` set.
+    LogLines(Vec<String>),
+    /// A chunk of a log with `binary` set.
+    NewLogBytes(Vec<u8>),
+    /// The log file was rotated and reopened.
+    LogRotated,
+    /// The state of the `service_state` unit, polled periodically.
+    ServiceState {
+        service: String,
+        state: String,
+    },
+    /// Sent by the control socket to run the actions, bypassing the conditions.
+    Fire,
+    /// A file matching `watch` was modified, or its mode or owner changed, or a new file in
+    /// `watch_dir` was completely written.
+    FileChange {
+        path: PathBuf,
+        /// When Ramon received the change.
+        time: SystemTime,
+        /// The file's modification time, if it still exists.
+        mtime: Option<SystemTime>,
+        /// Set with `watch_permissions`, if the file still exists.
+        permissions: Option<PermissionsChange>,
+        /// Whether the content changed, rather than the mode or owner.
+        content: bool,
+        /// Whether the file is new in `watch_dir`.
+        dropped: bool,
+    },
+}
+
+impl Event {
+    fn severity(&self) -> Option<Severity> {
+        match self {
+            Event::Tick
+            | Event::LogLines(_)
+            | Event::NewLogBytes(_)
+            | Event::LogRotated
+            | Event::ServiceState { .. }
+            | Event::Fire
+            | Event::FileChange { .. } => None,
+            Event::NewLogLine { severity, .. } => *severity,
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        match self {
+            Event::Tick => "every",
+            Event::NewLogLine { source, .. } => source,
+            Event::LogLines(_) | Event::NewLogBytes(_) | Event::LogRotated => "log",
+            Event::ServiceState { .. } => "service_state",
+            Event::FileChange { dropped: true, .. } => "watch_dir",
+            Event::FileChange { .. } => "watch",
+            Event::Fire => "control",
+        }
+    }
 }
 
 struct Unique {
     variable_name: String,
-    recorded_values: HashSet<String>,
+    /// Whether values are recorded separately for each file matching `watch`.
+    per_file: bool,
+    /// The values seen in each file, or under `None` if `per_file` isn't set. Each file's values
+    /// are loaded the first time it changes.
+    recorded_values: HashMap<Option<PathBuf>, HashSet<String>>,
+}
+
+struct Changed {
+    variable: String,
+    /// The last value seen, which is persisted across restarts.
+    previous: Option<String>,
+}
+
+struct Average {
+    variable: String,
+    window: RollingWindow,
+}
+
+struct Anomaly {
+    variable: String,
+    /// The values before the current one, which it's compared to.
+    window: RollingWindow,
+    k: f64,
+}
+
+/// The most recent values of a variable.
+struct RollingWindow {
+    size: usize,
+    values: Vec<f64>,
+    rotating_index: usize,
+}
+
+impl RollingWindow {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            values: Vec::with_capacity(size),
+            rotating_index: 0,
+        }
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.len() < self.size {
+            self.values.push(value);
+        } else {
+            self.values[self.rotating_index] = value;
+            self.rotating_index = (self.rotating_index + 1) % self.size;
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        self.values.iter().sum::<f64>() / self.values.len() as f64
+    }
+
+    /// The population standard deviation.
+    fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / self.values.len() as f64;
+        variance.sqrt()
+    }
+
+    fn is_full(&self) -> bool {
+        self.values.len() == self.size
+    }
+}
+
+/// Counts events within a sliding window.
+struct Rate {
+    window: Duration,
+    above: usize,
+    events: VecDeque<Instant>,
+    /// Whether the rate is above the bound, so that actions only run when it's crossed.
+    tripped: bool,
+}
+
+/// Deduplicates notifications by the rendered `fingerprint` template.
+struct Fingerprints {
+    template: String,
+    window: Duration,
+    /// When each fingerprint was first notified within its window, as Unix seconds, which is
+    /// persisted across restarts.
+    sent: HashMap<String, u64>,
+}
+
+/// A hard cap on the number of actions that send notifications within `NOTIFY_CAP_WINDOW`.
+struct NotifyCap {
+    max: usize,
+    summarize: bool,
+    sent: VecDeque<Instant>,
+    suppressed: usize,
+}
+
+impl NotifyCap {
+    fn prune(&mut self, now: Instant) {
+        while let Some(&time) = self.sent.front() {
+            if now.duration_since(time) < NOTIFY_CAP_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+        }
+    }
+
+    /// Records a send and returns true if the cap hasn't been reached.
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+        if self.sent.len() < self.max {
+            self.sent.push_back(now);
+            return true;
+        }
+        self.suppressed += 1;
+        false
+    }
+
+    /// When the oldest send leaves the window, if notifications are being suppressed.
+    fn clears_at(&self) -> Option<Instant> {
+        if self.suppressed == 0 {
+            return None;
+        }
+        self.sent.front().map(|&time| time + NOTIFY_CAP_WINDOW)
+    }
+}
+
+/// Actions waiting for `confirm_after` to pass.
+struct Pending {
+    variables: HashMap<String, Value>,
+    severity: Severity,
+    source: &'static str,
+    due: Instant,
+    /// Whether the latest tick would also have run the actions. Ticks are re-evaluated rather
+    /// than waiting for a reset.
+    held: bool,
 }
 
 struct Threshold {
@@ -59,15 +497,21 @@ struct Threshold {
 }
 
 impl Monitor {
-    pub async fn new(
-        config: MonitorConfig,
-        notify_config: Arc<HashMap<String, NotificationConfig>>,
-    ) -> Result<Self> {
+    pub async fn new(config: MonitorConfig, shared: Shared) -> Result<Self> {
         let name = config.name;
 
         let (event_tx, event_rx) = mpsc::channel(1);
+        let log_path = config.log.as_ref().map(|log| log.path.clone());
+        let service = config.service.clone();
+
+        if let Some(path) = config.fifo.as_ref().filter(|_| !shared.dry_run) {
+            fifo::create(path)?;
+        }
 
         if let Some(mut interval) = config.every {
+            // The default, `Burst`, fires every missed tick at once after the system resumes from
+            // suspend.
+            interval.set_missed_tick_behavior(config.missed_ticks);
             let tx = event_tx.clone();
             tokio::spawn(async move {
                 loop {
@@ -77,59 +521,226 @@ impl Monitor {
             });
         }
 
-        if let Some(log) = config.log {
-            let log_watcher = LogWatcher::new(name.clone(), log, event_tx.clone()).await?;
+        let stats = Arc::new(Stats::default());
+
+        let shared_progress = config.log.as_ref().and_then(|log| {
+            share_log_watcher(&name, log, config.circuit_breaker, &shared, &event_tx)
+        });
+        let mut log_progress = shared_progress.clone();
+        if let Some(mut log) = config.log.filter(|_| shared_progress.is_none()) {
+            let progress = Arc::new(LogProgress::new(log.path.clone()));
+            log_progress = Some(progress.clone());
+            // Replayed lines are only sent to this monitor.
+            let event_tx = match log.replay {
+                Some(_) => event_tx.clone(),
+                None => {
+                    let key = log_watcher_key(&log, config.circuit_breaker);
+                    let subscribers = Subscribers::new(Mutex::new(vec![event_tx.clone()]));
+                    let (log_tx, log_rx) = mpsc::channel(1);
+                    tokio::spawn(fan_out(log_rx, subscribers.clone()));
+                    shared
+                        .log_watchers
+                        .lock()
+                        .unwrap()
+                        .insert(key, (subscribers, progress.clone()));
+                    log_tx
+                }
+            };
+            let mut log_watcher = Some(
+                LogWatcher::new(
+                    name.clone(),
+                    log.clone(),
+                    progress.clone(),
+                    event_tx.clone(),
+                )
+                .await?,
+            );
+            // Restarted log watchers must not replay lines that were already processed.
+            log.replay = None;
             let name = name.clone();
+            let event_tx = event_tx.clone();
+            tokio::spawn(circuit_breaker::supervise(
+                name.clone(),
+                "log watcher",
+                config.circuit_breaker,
+                stats.clone(),
+                event_tx.clone(),
+                move || {
+                    let log_watcher = log_watcher.take();
+                    let (name, log, progress, event_tx) = (
+                        name.clone(),
+                        log.clone(),
+                        progress.clone(),
+                        event_tx.clone(),
+                    );
+                    async move {
+                        let log_watcher = match log_watcher {
+                            Some(log_watcher) => log_watcher,
+                            None => LogWatcher::new(name, log, progress, event_tx).await?,
+                        };
+                        log_watcher.start().await
+                    }
+                },
+            ));
+        }
+
+        if let Some(progress) = &log_progress {
+            tokio::spawn(log_lag(
+                name.clone(),
+                progress.clone(),
+                event_tx.downgrade(),
+            ));
+        }
+
+        if !config.watch.is_empty() {
+            let file_watcher = FileWatcher::new(
+                name.clone(),
+                &config.watch,
+                config.poll_interval,
+                config.watch_permissions,
+                config.watch_debounce,
+                event_tx.clone(),
+            )?;
+            let (name, stats) = (name.clone(), stats.clone());
             tokio::spawn(async move {
-                if let Err(err) = log_watcher.start().await {
-                    error!("[{name}] Log watcher: {err}");
+                if let Err(err) = file_watcher.start().await {
+                    error!("[{name}] File watcher: {err}");
+                    stats.source_failed("File watcher", err);
                 }
             });
         }
 
-        if let Some(service) = config.service {
-            let child = Command::new("journalctl")
-                .args(["-n0", "-fu", &service])
-                .stdin(Stdio::null())
-                .stdout(Stdio::piped())
-                .spawn()
-                .map_err(|err| anyhow!("Failed to spawn journalctl: {err}"))?;
-            let stdout = child.stdout.ok_or(anyhow!("Failed to capture stdout."))?;
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-            let name = name.clone();
-            let event_tx = event_tx.clone();
+        let mut deferred_drops = None;
+        if let Some(dir) = &config.watch_dir {
+            if let AfterExec::Move(move_to) = &config.after_exec {
+                if !move_to.is_dir() {
+                    bail!("Key `move_to`: {move_to:?} is not a directory.");
+                }
+            }
+            // Kept files would be processed again each time Ramon starts.
+            let process_existing = !matches!(config.after_exec, AfterExec::Keep);
+            let deferred = Arc::default();
+            let dir_watcher = DirWatcher::new(
+                name.clone(),
+                dir,
+                config.poll_interval,
+                config.settle_time,
+                process_existing,
+                Arc::clone(&deferred),
+                event_tx.clone(),
+            )?;
+            deferred_drops = Some(deferred);
+            let (name, stats) = (name.clone(), stats.clone());
             tokio::spawn(async move {
-                while let Some(line) = lines.next_line().await.unwrap() {
-                    event_tx.send(Event::NewLogLine(line)).await.unwrap();
+                if let Err(err) = dir_watcher.start().await {
+                    error!("[{name}] Directory watcher: {err}");
+                    stats.source_failed("Directory watcher", err);
                 }
-                error!("[{name}] Service watcher exited early.");
             });
         }
 
+        if let Some(service) = config.service_state {
+            let event_tx = event_tx.clone();
+            tokio::spawn(circuit_breaker::supervise(
+                name.clone(),
+                "service state poller",
+                config.circuit_breaker,
+                stats.clone(),
+                event_tx.clone(),
+                move || poll_service_state(service.clone(), event_tx.clone()),
+            ));
+        }
+
+        if let Some(service) = config.service {
+            let mut service_args = config.service_args;
+            if config.severity_from_priority {
+                service_args.extend(["-o".to_owned(), "json".to_owned()]);
+            }
+            let json = config.severity_from_priority;
+            let mut reader = Some(spawn_journalctl(&service, &service_args)?);
+            let (monitor_name, event_tx) = (name.clone(), event_tx.clone());
+            tokio::spawn(circuit_breaker::supervise(
+                name.clone(),
+                "service watcher",
+                config.circuit_breaker,
+                stats.clone(),
+                event_tx.clone(),
+                move || {
+                    let reader = reader.take();
+                    let (service, service_args, event_tx) =
+                        (service.clone(), service_args.clone(), event_tx.clone());
+                    let limiter = LineLimiter::new(monitor_name.clone(), config.line_limit);
+                    async move {
+                        let reader = match reader {
+                            Some(reader) => reader,
+                            None => spawn_journalctl(&service, &service_args)?,
+                        };
+                        match json {
+                            true => forward_journal_entries(reader, limiter, event_tx).await,
+                            false => forward_lines(reader, "service", limiter, event_tx).await,
+                        }
+                    }
+                },
+            ));
+        }
+
+        if let Some(ssh) = config.ssh {
+            tokio::spawn(ssh::tail(
+                name.clone(),
+                ssh,
+                config.line_limit,
+                stats.clone(),
+                event_tx.clone(),
+            ));
+        }
+
         let unique = match config.unique {
             None => None,
             Some(variable_name) => {
-                let file_path = format!("/var/cache/ramon/unique_{name}");
-                let recorded_values = match OpenOptions::new().read(true).open(file_path).await {
-                    Err(_) => HashSet::new(),
-                    Ok(file) => {
-                        let mut values = HashSet::new();
-                        let reader = BufReader::new(file);
-                        let mut lines = reader.lines();
-                        while let Some(line) = lines.next_line().await? {
-                            values.insert(line);
-                        }
-                        values
-                    }
-                };
+                let mut recorded_values = HashMap::new();
+                if !config.unique_per_file {
+                    let values = load_unique_values(&name, None, shared.dry_run).await?;
+                    recorded_values.insert(None, values);
+                }
                 Some(Unique {
                     variable_name,
+                    per_file: config.unique_per_file,
                     recorded_values,
                 })
             }
         };
 
+        let fingerprints = match config.fingerprint {
+            None => None,
+            Some(template) => Some(Fingerprints {
+                template,
+                window: config.fingerprint_window,
+                sent: load_fingerprints(&name, shared.dry_run).await?,
+            }),
+        };
+
+        let changed = match config.changed {
+            None => None,
+            Some(variable) => {
+                let file_path = format!("/var/cache/ramon/changed_{name}");
+                let previous = match shared.dry_run {
+                    true => None,
+                    false => fs::read_to_string(file_path)
+                        .await
+                        .ok()
+                        .map(|value| value.trim_end_matches('\n').to_owned()),
+                };
+                Some(Changed { variable, previous })
+            }
+        };
+
+        let rate = config.rate.map(|(window, above)| Rate {
+            window,
+            above,
+            events: VecDeque::new(),
+            tripped: false,
+        });
+
         let threshold = config.threshold.map(|(threshold, duration)| Threshold {
             threshold,
             duration,
@@ -137,59 +748,439 @@ impl Monitor {
             rotating_index: 0,
         });
 
-        let notify_type = match &config.notify {
-            None => "default",
-            Some(notify) => &notify.r#type,
+        let average = config.average.map(|(variable, size)| Average {
+            variable,
+            window: RollingWindow::new(size),
+        });
+
+        // The files' content when Ramon starts is the baseline that changes are compared against.
+        let content_matched = match (&config.match_content, config.match_content_changes) {
+            (Some(regex), true) => {
+                let mut matched = HashMap::new();
+                for pattern in &config.watch {
+                    for path in glob::glob(pattern)?.flatten() {
+                        if let Some(Content::Text(content)) =
+                            read_content(&name, &path, config.max_file_size).await
+                        {
+                            matched.insert(path, regex.is_match(&content));
+                        }
+                    }
+                }
+                Some(matched)
+            }
+            _ => None,
+        };
+
+        let anomaly = config.anomaly.map(|anomaly| Anomaly {
+            variable: anomaly.variable,
+            window: RollingWindow::new(anomaly.window),
+            k: anomaly.k,
+        });
+
+        for notification in &config.notify {
+            if !shared.notify_configs.contains_key(&notification.r#type) {
+                bail!(
+                    "Could not find notification config `{}`.",
+                    notification.r#type
+                );
+            }
+        }
+
+        if let Some(target) = config.on_error.filter(|_| !shared.dry_run) {
+            tokio::spawn(on_error::watch(
+                name.clone(),
+                target,
+                stats.clone(),
+                shared.clone(),
+            ));
+        }
+
+        let mut daemon = None;
+        let exec = match config.exec_mode {
+            ExecMode::Trigger => config.exec,
+            ExecMode::Daemon => {
+                if let Some(exec) = config.exec.filter(|_| !shared.dry_run) {
+                    let variables = config
+                        .labels
+                        .iter()
+                        .map(|(key, value)| (format!("label_{key}"), value.clone().into()))
+                        .collect();
+                    let (stop_tx, stop_rx) = watch::channel(false);
+                    let handle = tokio::spawn(run_daemon(
+                        name.clone(),
+                        exec,
+                        variables,
+                        config.env_newlines,
+                        shared.clone(),
+                        stats.clone(),
+                        stop_rx,
+                    ));
+                    let started = Arc::new(Daemon {
+                        stop_tx,
+                        handle: Mutex::new(Some(handle)),
+                        _event_tx: event_tx.clone(),
+                    });
+                    shared.daemons.0.lock().unwrap().push(started.clone());
+                    daemon = Some(started);
+                }
+                None
+            }
         };
-        let notify_config = notify_config
-            .get(notify_type)
-            .ok_or(anyhow!(
-                "Could not find notification config `{notify_type}`."
-            ))?
-            .to_owned();
 
         Ok(Self {
             name,
-            notify_config,
+            stats,
+            shared,
 
             event_rx,
+            event_tx: event_tx.downgrade(),
+            paused: Arc::default(),
+            dry_run_variables: None,
             last_action_time: None,
+            last_action_severity: Severity::default(),
+            first_match_fired: false,
+            down_since: None,
+            down_for: config.down_for,
+            exec_running: Arc::new(AtomicBool::new(false)),
+            skip_if_running: config.skip_if_running,
+
+            severity: config.severity,
+            labels: config.labels,
+            timezone: config.timezone,
+            first_match_only: config.first_match_only,
 
             cooldown: config.cooldown,
+            cooldown_mode: config.cooldown_mode,
+            last_trigger_time: None,
+            warmup_until: config.warmup.map(|warmup| Instant::now() + warmup),
             log_regex: config.match_log,
             ignore_regex: config.ignore_log,
+            log_bytes_regex: config.match_log_bytes,
+            ignore_bytes_regex: config.ignore_log_bytes,
+            transform: config.transform,
+            ignore_if: config.ignore_if,
+            severity_from_priority: config.severity_from_priority,
+            content_regex: config.match_content,
+            max_file_size: config.max_file_size,
+            fire_when_skipped: config.fire_when_skipped,
+            content_matched,
             unique,
+            changed,
+            r#if: config.r#if,
+            rate,
             threshold,
+            require: config.require,
+            depends_on: config.depends_on,
+            depends_on_within: config.depends_on_within,
+            average,
+            anomaly,
+            confirm_after: config.confirm_after,
+            confirm_reset: config.confirm_reset,
+            stop_on: config.stop_on,
+            pending: None,
 
-            exec: config.exec,
+            exec,
+            exec_parse: config.exec_parse,
+            exec_timeout: config.exec_timeout,
+            after_exec: config.after_exec,
+            deferred_drops,
+            exec_pty: config.exec_pty,
+            env_newlines: config.env_newlines,
+            fifo: config.fifo.map(Fifo::new),
             notify: config.notify,
+            log_path,
+            log_progress,
+            daemon,
+            service,
+            unit_properties: None,
+            notify_cap: config.max_notifications_per_hour.map(|max| NotifyCap {
+                max,
+                summarize: config.summarize_suppressed,
+                sent: VecDeque::with_capacity(max),
+                suppressed: 0,
+            }),
+            fingerprints,
+            notify_history: Mutex::default(),
         })
     }
 
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting monitor `{}`", self.name);
+        let res = self.run().await;
+        if let Some(daemon) = &self.daemon {
+            daemon.stop().await;
+        }
+        res
+    }
 
-        while let Some(event) = self.event_rx.recv().await {
-            self.evaluate(event).await?;
+    async fn run(&mut self) -> Result<()> {
+        loop {
+            let clears_at = self.notify_cap.as_ref().and_then(NotifyCap::clears_at);
+            let cap_cleared = async {
+                match clears_at {
+                    Some(clears_at) => time::sleep_until(clears_at.into()).await,
+                    None => pending().await,
+                }
+            };
+            let due = self.pending.as_ref().map(|pending| pending.due);
+            let confirmed = async {
+                match due {
+                    Some(due) => time::sleep_until(due.into()).await,
+                    None => pending().await,
+                }
+            };
+            tokio::select! {
+                event = self.event_rx.recv() => match event {
+                    Some(event) => {
+                        let stop = self.is_stop_line(&event);
+                        self.evaluate_and_recheck(event).await?;
+                        if stop {
+                            info!("[{}] Stopping because a line matched `stop_on`.", self.name);
+                            return Ok(());
+                        }
+                    }
+                    None => break,
+                },
+                _ = cap_cleared => self.notify_cap_cleared().await,
+                _ = confirmed => self.confirm().await?,
+            }
         }
 
         bail!("No more events?");
     }
 
+    pub fn control_handle(&self) -> MonitorHandle {
+        MonitorHandle {
+            name: self.name.clone(),
+            stats: self.stats.clone(),
+            paused: self.paused.clone(),
+            event_tx: self.event_tx.clone(),
+            log_progress: self.log_progress.clone(),
+        }
+    }
+
+    /// Evaluates the line as if the monitor's log had logged it. Returns the variables the actions
+    /// would have run with, if any. Only meaningful in a dry run.
+    pub async fn test_line(
+        &mut self,
+        source: &'static str,
+        line: String,
+    ) -> Result<Option<HashMap<String, Value>>> {
+        self.evaluate(Event::NewLogLine {
+            source,
+            line,
+            severity: None,
+            repeat_count: None,
+        })
+        .await?;
+        Ok(self.dry_run_variables.take())
+    }
+
+    /// Evaluates the event, and cancels the pending actions if a tick no longer runs them.
+    async fn evaluate_and_recheck(&mut self, event: Event) -> Result<()> {
+        let tick = matches!(event, Event::Tick);
+        if let Some(pending) = &mut self.pending {
+            pending.held = !tick;
+        }
+        self.evaluate(event).await?;
+        if self.pending.as_ref().is_some_and(|pending| !pending.held) {
+            info!(
+                "[{}] No longer true. Canceled the pending actions.",
+                self.name
+            );
+            self.pending = None;
+        }
+        Ok(())
+    }
+
+    /// Runs the pending actions once `confirm_after` has passed.
+    async fn confirm(&mut self) -> Result<()> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        if self.paused.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if self.warming_up() || self.dependency_down() {
+            return Ok(());
+        }
+        info!("[{}] Confirmed. Running the pending actions.", self.name);
+        self.run_actions(pending.variables, pending.severity, pending.source)
+            .await
+    }
+
+    /// Returns whether actions are suppressed because Ramon started recently, and logs it if so.
+    fn warming_up(&self) -> bool {
+        let Some(remaining) = self
+            .warmup_until
+            .filter(|_| !self.shared.dry_run)
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+        else {
+            return false;
+        };
+        info!(
+            "[{}] Not running actions because the monitor is warming up for another {}s.",
+            self.name,
+            remaining.as_secs()
+        );
+        true
+    }
+
+    /// Returns whether the event is suppressed because `cooldown` hasn't passed since `since`,
+    /// and logs it if so. Higher severity events are let through, which resets the cooldown.
+    fn cooling_down(&self, since: Option<Instant>, severity: Severity) -> bool {
+        let (Some(cooldown), Some(since)) = (self.cooldown, since) else {
+            return false;
+        };
+        if since.elapsed() >= cooldown {
+            return false;
+        }
+        if severity <= self.last_action_severity {
+            info!("[{}] Still cooling down.", self.name);
+            return true;
+        }
+        info!("[{}] Bypassing cooldown for {severity:?} event.", self.name);
+        false
+    }
+
+    /// Returns whether actions are suppressed because a monitor in `depends_on` is down, and logs
+    /// it if so.
+    fn dependency_down(&self) -> bool {
+        if self.shared.dry_run {
+            return false;
+        }
+        let down = self.shared.down.lock().unwrap();
+        let Some(dependency) = self.depends_on.iter().find(|name| {
+            down.get(*name).is_some_and(|down| {
+                down.until_recovered || down.since.elapsed() <= self.depends_on_within
+            })
+        }) else {
+            return false;
+        };
+        info!(
+            "[{}] Not running actions because `{dependency}`, which it depends on, is down.",
+            self.name
+        );
+        true
+    }
+
+    /// Returns whether the event has a line that matches `stop_on`.
+    fn is_stop_line(&self, event: &Event) -> bool {
+        let Some(regex) = &self.stop_on else {
+            return false;
+        };
+        match event {
+            Event::NewLogLine { line, .. } => regex.is_match(line),
+            Event::LogLines(lines) => lines.iter().any(|line| regex.is_match(line)),
+            _ => false,
+        }
+    }
+
+    /// Cancels the pending actions if a line matches `confirm_reset`. Returns whether they were
+    /// canceled.
+    fn reset_pending(&mut self, lines: &[&str]) -> bool {
+        let Some(regex) = &self.confirm_reset else {
+            return false;
+        };
+        if self.pending.is_none() || !lines.iter().any(|line| regex.is_match(line)) {
+            return false;
+        }
+        info!("[{}] Reset. Canceled the pending actions.", self.name);
+        self.pending = None;
+        true
+    }
+
     /// Evaluate all conditions to determine if actions should be run.
     async fn evaluate(&mut self, event: Event) -> Result<()> {
-        if let Some(cooldown) = self.cooldown {
-            if let Some(last_action_time) = self.last_action_time {
-                if Instant::now().duration_since(last_action_time) < cooldown {
-                    info!("[{}] Still cooling down.", self.name);
-                    return Ok(());
-                }
+        if let Event::LogRotated = event {
+            // `first_match_only` applies to each log file.
+            self.first_match_fired = false;
+            return Ok(());
+        }
+
+        if let Event::Fire = event {
+            info!(
+                "[{}] Running actions because of a control command.",
+                self.name
+            );
+            let mut temp_variables = HashMap::from([(
+                "timestamp".to_owned(),
+                Utc::now()
+                    .with_timezone(&self.timezone)
+                    .to_rfc3339_opts(SecondsFormat::Secs, false)
+                    .into(),
+            )]);
+            for (key, value) in &self.labels {
+                temp_variables.insert(format!("label_{key}"), value.clone().into());
             }
+            return self
+                .run_actions(temp_variables, self.severity, event.source())
+                .await;
         }
 
-        let temp_variables = match event {
-            Event::NewLogLine(line) => {
+        let dropped_file = match &event {
+            Event::FileChange {
+                path,
+                dropped: true,
+                ..
+            } => Some(path.clone()),
+            _ => None,
+        };
+        if self.paused.load(Ordering::Relaxed) {
+            self.defer_drop(dropped_file);
+            return Ok(());
+        }
+
+        self.stats.events.fetch_add(1, Ordering::Relaxed);
+
+        let reset = match &event {
+            Event::NewLogLine { line, .. } => self.reset_pending(&[line]),
+            Event::LogLines(lines) => {
+                self.reset_pending(&lines.iter().map(String::as_str).collect::<Vec<_>>())
+            }
+            _ => false,
+        };
+        // `unique_per_file` records values separately for each watched file.
+        let event_file = match &event {
+            Event::FileChange { path, .. } => Some(path.clone()),
+            _ => None,
+        };
+        if reset {
+            return Ok(());
+        }
+
+        if self.first_match_only && self.first_match_fired {
+            return Ok(());
+        }
+
+        if let Event::Tick = event {
+            if self.skip_if_running && self.exec_running.load(Ordering::Relaxed) {
+                debug!(
+                    "[{}] Skipping tick because the previous exec is still running.",
+                    self.name
+                );
+                return Ok(());
+            }
+        }
+
+        let severity = event.severity().unwrap_or(self.severity);
+        let source = event.source();
+
+        if self.cooldown_mode == CooldownMode::Trailing
+            && self.cooling_down(self.last_action_time, severity)
+        {
+            self.defer_drop(dropped_file);
+            return Ok(());
+        }
+
+        let mut temp_variables = match event {
+            Event::NewLogLine {
+                line, repeat_count, ..
+            } => {
                 let mut temp_variables = HashMap::new();
+                if let Some(repeat_count) = repeat_count {
+                    temp_variables.insert("repeat_count".to_owned(), (repeat_count as i64).into());
+                }
                 if let Some(regex) = &self.log_regex {
                     let captures = match regex.captures(&line) {
                         Some(captures) => captures,
@@ -197,20 +1188,7 @@ impl Monitor {
                         None => return Ok(()),
                     };
                     debug!("[{}] Match found.", self.name);
-                    for capture_name in regex
-                        .capture_names()
-                        .filter(Option::is_some)
-                        .map(|n| n.unwrap())
-                    {
-                        if let Some(capture) = captures.name(capture_name) {
-                            temp_variables.insert(capture_name.to_owned(), capture.as_str().into());
-                        } else {
-                            warn!(
-                                "[{}] Capture group `{capture_name}` was not found.",
-                                self.name
-                            );
-                        }
-                    }
+                    insert_captures(regex, &captures, &mut temp_variables);
                 }
 
                 if let Some(regex) = &self.ignore_regex {
@@ -220,59 +1198,802 @@ impl Monitor {
                 }
                 temp_variables
             }
-            Event::Tick => HashMap::new(),
-        };
-
-        if let Some(unique) = &mut self.unique {
-            if let Some(var) = temp_variables
-                .get(&unique.variable_name)
-                .and_then(|v: &Value| v.as_str())
-            {
-                if unique.recorded_values.contains(var) {
+            Event::LogLines(lines) => {
+                // Only the lines that pass `match_log` and `ignore_log` are kept.
+                let lines = lines
+                    .into_iter()
+                    .filter(|line| self.log_regex.as_ref().is_none_or(|r| r.is_match(line)))
+                    .filter(|line| !self.ignore_regex.as_ref().is_some_and(|r| r.is_match(line)))
+                    .collect::<Vec<_>>();
+                let Some(first) = lines.first() else {
                     return Ok(());
-                } else {
-                    unique.recorded_values.insert(var.to_owned());
-                    if let Err(err) = self.store_unique_values().await {
-                        warn!("[{}] Failed to store unique values: {err}", self.name);
+                };
+                let mut temp_variables = HashMap::new();
+                if let Some(regex) = &self.log_regex {
+                    if let Some(captures) = regex.captures(first) {
+                        insert_captures(regex, &captures, &mut temp_variables);
                     }
                 }
+                temp_variables.insert("count".to_owned(), (lines.len() as i64).into());
+                temp_variables.insert("lines".to_owned(), lines.join("\n").into());
+                temp_variables
             }
-        }
-
-        // TODO: get
-
-        // TODO: if
-
-        if let Some(threshold) = &mut self.threshold {
-            let now = Instant::now();
-            if threshold.event_history.len() < threshold.threshold {
-                threshold.event_history.push(now);
-                if threshold.event_history.len() < threshold.threshold {
-                    return Ok(());
+            Event::NewLogBytes(bytes) => {
+                let mut temp_variables = HashMap::new();
+                if let Some(regex) = &self.log_bytes_regex {
+                    let captures = match regex.captures(&bytes) {
+                        Some(captures) => captures,
+                        None => return Ok(()),
+                    };
+                    debug!("[{}] Match found.", self.name);
+                    // Captures aren't necessarily valid UTF-8, so they're hex encoded.
+                    // Optional groups that didn't take part in the match are left unset.
+                    for (i, variable) in capture_variables(regex.capture_names()) {
+                        if let Some(capture) = captures.get(i) {
+                            temp_variables.insert(variable, to_hex(capture.as_bytes()).into());
+                        }
+                    }
                 }
-            } else {
-                let _ = replace(&mut threshold.event_history[threshold.rotating_index], now);
-                threshold.rotating_index = (threshold.rotating_index + 1) % threshold.threshold;
-            }
 
-            let oldest_event = &threshold.event_history[threshold.rotating_index];
-            if now.duration_since(oldest_event.to_owned()) > threshold.duration {
-                info!("Didn't hit it yet");
-                return Ok(());
+                if let Some(regex) = &self.ignore_bytes_regex {
+                    if regex.is_match(&bytes) {
+                        return Ok(());
+                    }
+                }
+                temp_variables
+            }
+            Event::FileChange {
+                path,
+                time,
+                mtime,
+                permissions,
+                content,
+                dropped: _,
+            } => {
+                // Prefer the mtime so that coalesced changes report when the file was last
+                // written rather than when Ramon processed them.
+                let change_time = DateTime::<Utc>::from(mtime.unwrap_or(time))
+                    .with_timezone(&self.timezone)
+                    .to_rfc3339_opts(SecondsFormat::Secs, false);
+                let mut temp_variables = HashMap::new();
+                // `match_content` only applies to changes to the content.
+                if let Some(regex) = self.content_regex.as_ref().filter(|_| content) {
+                    match read_content(&self.name, &path, self.max_file_size).await {
+                        None => return Ok(()),
+                        Some(Content::TooBig) if !self.fire_when_skipped => return Ok(()),
+                        Some(Content::TooBig) => {
+                            temp_variables.insert("skipped".to_owned(), true.into());
+                        }
+                        Some(Content::Text(content)) => {
+                            let captures = regex.captures(&content);
+                            if let Some(content_matched) = &mut self.content_matched {
+                                let matched = captures.is_some();
+                                // Files that couldn't be read at first are assumed not to match.
+                                let was_matched = content_matched.insert(path.clone(), matched);
+                                if was_matched.unwrap_or(false) == matched {
+                                    return Ok(());
+                                }
+                                temp_variables.insert("matched".to_owned(), matched.into());
+                            }
+                            match captures {
+                                Some(captures) => {
+                                    debug!("[{}] Content of {path:?} matched.", self.name);
+                                    insert_captures(regex, &captures, &mut temp_variables);
+                                }
+                                None if self.content_matched.is_some() => {
+                                    debug!("[{}] Content of {path:?} stopped matching.", self.name);
+                                }
+                                None => return Ok(()),
+                            }
+                            temp_variables.insert("skipped".to_owned(), false.into());
+                        }
+                    }
+                }
+                temp_variables.insert("file".to_owned(), path.display().to_string().into());
+                temp_variables.insert("change_time".to_owned(), change_time.into());
+                if let Some(PermissionsChange { old, new }) = permissions {
+                    let change = if content { "content" } else { "permissions" };
+                    temp_variables.insert("change".to_owned(), change.into());
+                    temp_variables.insert("old_mode".to_owned(), old.mode_string().into());
+                    temp_variables.insert("new_mode".to_owned(), new.mode_string().into());
+                    temp_variables.insert("old_owner".to_owned(), old.owner_string().await.into());
+                    temp_variables.insert("new_owner".to_owned(), new.owner_string().await.into());
+                }
+                temp_variables
+            }
+            Event::ServiceState { service, state } => {
+                if state == "active" {
+                    if self.down_since.take().is_some() {
+                        info!("[{}] {service} is active again.", self.name);
+                        self.shared.down.lock().unwrap().remove(&self.name);
+                    }
+                    if self.pending.take().is_some() {
+                        info!("[{}] Canceled the pending actions.", self.name);
+                    }
+                    return Ok(());
+                }
+                let now = Instant::now();
+                let (since, alerted) = self.down_since.get_or_insert((now, false));
+                if *alerted || now.duration_since(*since) < self.down_for {
+                    return Ok(());
+                }
+                *alerted = true;
+                HashMap::from([
+                    ("service".to_owned(), service.into()),
+                    ("state".to_owned(), state.into()),
+                ])
+            }
+            // `Fire` is handled above.
+            Event::Tick | Event::LogRotated | Event::Fire => HashMap::new(),
+        };
+        if self.severity_from_priority {
+            temp_variables.insert("severity".to_owned(), severity.as_str().into());
+        }
+
+        for transform in &self.transform {
+            let Some(value) = temp_variables.get_mut(&transform.variable) else {
+                continue;
+            };
+            let text = value_to_string(value.clone());
+            if let Cow::Owned(replaced) = transform.regex.replace_all(&text, &transform.replace) {
+                *value = replaced.into();
+            }
+        }
+
+        for (variable, regex) in &self.ignore_if {
+            if let Some(value) = temp_variables.get(variable) {
+                if regex.is_match(&value_to_string(value.clone())) {
+                    debug!("[{}] Ignoring event because of `{variable}`.", self.name);
+                    return Ok(());
+                }
+            }
+        }
+
+        temp_variables
+            .entry("timestamp".to_owned())
+            .or_insert_with(|| {
+                Utc::now()
+                    .with_timezone(&self.timezone)
+                    .to_rfc3339_opts(SecondsFormat::Secs, false)
+                    .into()
+            });
+
+        // Labels are prefixed so that they can't collide with captures.
+        for (key, value) in &self.labels {
+            temp_variables.insert(format!("label_{key}"), value.clone().into());
+        }
+
+        if let Some(unique) = &mut self.unique {
+            if let Some(var) = temp_variables
+                .get(&unique.variable_name)
+                .and_then(|v: &Value| v.as_str())
+            {
+                let file = match unique.per_file {
+                    true => event_file,
+                    false => None,
+                };
+                let values = match unique.recorded_values.entry(file.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        load_unique_values(&self.name, file.as_deref(), self.shared.dry_run)
+                            .await?,
+                    ),
+                };
+                if values.contains(var) {
+                    return Ok(());
+                } else {
+                    values.insert(var.to_owned());
+                    if !self.shared.dry_run {
+                        if let Err(err) = self.store_unique_values(file.as_deref()).await {
+                            warn!("[{}] Failed to store unique values: {err}", self.name);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(changed) = &mut self.changed {
+            let Some(value) = temp_variables.get(&changed.variable) else {
+                return Ok(());
+            };
+            let value = value_to_string(value.clone());
+            if changed.previous.as_ref() == Some(&value) {
+                return Ok(());
+            }
+            let previous = changed.previous.replace(value.clone());
+            if !self.shared.dry_run {
+                if let Err(err) = store_changed_value(&self.name, &value).await {
+                    warn!("[{}] Failed to store changed value: {err}", self.name);
+                }
+            }
+            // The first value is only recorded.
+            let Some(previous) = previous else {
+                return Ok(());
+            };
+            if let (Ok(new), Ok(old)) =
+                (value.trim().parse::<f64>(), previous.trim().parse::<f64>())
+            {
+                temp_variables.insert("delta".to_owned(), (new - old).into());
+            }
+            temp_variables.insert("previous_value".to_owned(), previous.into());
+        }
+
+        if let Some(average) = &mut self.average {
+            match temp_variables.get(&average.variable).and_then(value_to_f64) {
+                Some(value) => {
+                    average.window.push(value);
+                    temp_variables.insert(
+                        format!("{}_avg", average.variable),
+                        average.window.mean().into(),
+                    );
+                }
+                None => warn!(
+                    "[{}] Variable `{}` is not a number.",
+                    self.name, average.variable
+                ),
+            }
+        }
+
+        if let Some(anomaly) = &mut self.anomaly {
+            let Some(value) = temp_variables.get(&anomaly.variable).and_then(value_to_f64) else {
+                warn!(
+                    "[{}] Variable `{}` is not a number.",
+                    self.name, anomaly.variable
+                );
+                return Ok(());
+            };
+            // The value is compared to the ones before it, so that an outlier doesn't widen its
+            // own bounds. Recording it afterwards lets a lasting shift become the new normal.
+            let history = anomaly
+                .window
+                .is_full()
+                .then(|| (anomaly.window.mean(), anomaly.window.stddev()));
+            anomaly.window.push(value);
+            let Some((mean, stddev)) = history else {
+                return Ok(());
+            };
+            let deviation = value - mean;
+            if deviation.abs() <= anomaly.k * stddev {
+                return Ok(());
+            }
+            // A window of identical values makes any change infinitely unusual.
+            let zscore = match stddev {
+                0.0 => deviation.signum() * f64::INFINITY,
+                _ => deviation / stddev,
+            };
+            let variable = &anomaly.variable;
+            temp_variables.insert(format!("{variable}_zscore"), zscore.into());
+            temp_variables.insert(format!("{variable}_mean"), mean.into());
+            temp_variables.insert(format!("{variable}_stddev"), stddev.into());
+        }
+
+        // TODO: get
+
+        if let Some(condition) = &self.r#if {
+            if !condition.evaluate(&temp_variables) {
+                self.defer_drop(dropped_file);
+                return Ok(());
+            }
+        }
+
+        if let Some(rate) = &mut self.rate {
+            let now = Instant::now();
+            rate.events.push_back(now);
+            while let Some(&time) = rate.events.front() {
+                if now.duration_since(time) <= rate.window {
+                    break;
+                }
+                rate.events.pop_front();
+            }
+            let count = rate.events.len();
+            if count <= rate.above {
+                rate.tripped = false;
+                return Ok(());
+            }
+            if rate.tripped {
+                return Ok(());
+            }
+            rate.tripped = true;
+            temp_variables.insert("rate".to_owned(), (count as i64).into());
+        }
+
+        if let Some(threshold) = &mut self.threshold {
+            let now = Instant::now();
+            if threshold.event_history.len() < threshold.threshold {
+                threshold.event_history.push(now);
+                if threshold.event_history.len() < threshold.threshold {
+                    return Ok(());
+                }
+            } else {
+                let _ = replace(&mut threshold.event_history[threshold.rotating_index], now);
+                threshold.rotating_index = (threshold.rotating_index + 1) % threshold.threshold;
             }
-        }
 
-        self.run_actions(temp_variables).await
+            let oldest_event = &threshold.event_history[threshold.rotating_index];
+            if now.duration_since(oldest_event.to_owned()) > threshold.duration {
+                info!("Didn't hit it yet");
+                return Ok(());
+            }
+        }
+
+        if !self.record_true() {
+            debug!("[{}] Required monitors were not true.", self.name);
+            return Ok(());
+        }
+
+        // A leading window is anchored at the event that opened it, so events that are
+        // suppressed, or that bypass it with a higher severity, don't extend it. It's only opened
+        // once the actions are run or queued, so that events suppressed by e.g. `warmup` don't
+        // silence the first event after it.
+        let mut opens_window = false;
+        if self.cooldown_mode == CooldownMode::Leading {
+            opens_window = self
+                .last_trigger_time
+                .zip(self.cooldown)
+                .is_none_or(|(since, cooldown)| since.elapsed() >= cooldown);
+            if self.cooling_down(self.last_trigger_time, severity) {
+                self.defer_drop(dropped_file);
+                return Ok(());
+            }
+        }
+        let trigger_time = Instant::now();
+
+        if source == "service" && !self.shared.dry_run {
+            temp_variables.extend(self.unit_properties().await);
+        }
+
+        // Dry runs report the actions immediately.
+        if let Some(confirm_after) = self.confirm_after.filter(|_| !self.shared.dry_run) {
+            match &mut self.pending {
+                Some(pending) => pending.held = true,
+                None => {
+                    info!(
+                        "[{}] Waiting {}s to confirm before running the actions.",
+                        self.name,
+                        confirm_after.as_secs()
+                    );
+                    self.pending = Some(Pending {
+                        variables: temp_variables,
+                        severity,
+                        source,
+                        due: Instant::now() + confirm_after,
+                        held: true,
+                    });
+                    if opens_window {
+                        self.last_trigger_time = Some(trigger_time);
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        if self.warming_up() || self.dependency_down() {
+            self.defer_drop(dropped_file);
+            return Ok(());
+        }
+        if opens_window {
+            self.last_trigger_time = Some(trigger_time);
+        }
+
+        self.run_actions(temp_variables, severity, source).await
+    }
+
+    /// Hands a `watch_dir` file whose event was suppressed back to the directory watcher, so that
+    /// it's processed once the suppression ends rather than after the next restart.
+    fn defer_drop(&self, dropped_file: Option<PathBuf>) {
+        if let (Some(deferred), Some(path)) = (&self.deferred_drops, dropped_file) {
+            debug!("[{}] Retrying {path:?} later.", self.name);
+            deferred.lock().unwrap().insert(path);
+        }
+    }
+
+    /// Returns the name, state, main PID, and restart count of the `service` unit. The
+    /// properties are cached for `UNIT_PROPERTIES_TTL`.
+    async fn unit_properties(&mut self) -> HashMap<String, Value> {
+        let Some(service) = &self.service else {
+            return HashMap::new();
+        };
+        if let Some((fetched, properties)) = &self.unit_properties {
+            if fetched.elapsed() < UNIT_PROPERTIES_TTL {
+                return properties.clone();
+            }
+        }
+        let mut properties = HashMap::from([("service".to_owned(), service.clone().into())]);
+        match show_unit(service).await {
+            Ok(unit) => properties.extend(unit),
+            Err(err) => warn!("[{}] Failed to look up {service:?}: {err}", self.name),
+        }
+        self.unit_properties = Some((Instant::now(), properties.clone()));
+        properties
     }
 
-    async fn store_unique_values(&mut self) -> Result<()> {
+    /// Records that every other condition is true, so that monitors that require this one can
+    /// fire. Returns whether `require` is true.
+    fn record_true(&self) -> bool {
+        let now = Instant::now();
+        let mut last_true = self.shared.last_true.lock().unwrap();
+        last_true.insert(self.name.clone(), now);
+        let Some(require) = &self.require else {
+            return true;
+        };
+        let is_true = |name: &String| {
+            last_true
+                .get(name)
+                .is_some_and(|&time| now.duration_since(time) <= require.within)
+        };
+        require.all.iter().all(is_true)
+            && (require.any.is_empty() || require.any.iter().any(is_true))
+    }
+
+    /// Spawns the `exec` command. If `exec_parse` is set, this waits for the command to exit and
+    /// adds the captures from its stdout to the variables. `dropped_file` is deleted or moved
+    /// according to `after_exec` once the command exits successfully.
+    async fn exec(
+        &self,
+        exec: &Exec,
+        temp_variables: &mut HashMap<String, Value>,
+        dropped_file: Option<PathBuf>,
+    ) -> Result<()> {
+        let mut command = exec_command(exec, temp_variables);
+        for (var, val) in temp_variables.iter() {
+            let val = value_to_string(val.clone());
+            match sanitize_env_value(self.env_newlines, val) {
+                Some(val) => {
+                    for name in self.shared.env_names.of(var) {
+                        command.env(name, &val);
+                    }
+                }
+                None => {
+                    self.stats
+                        .exec_failed(format!("`{var}` contains a new line"));
+                    warn!(
+                        "[{}] Not running exec because `{var}` contains a new line.",
+                        self.name
+                    );
+                    return Ok(());
+                }
+            };
+        }
+        let pty = match self.exec_pty {
+            true => Some(attach_pty(&mut command)?),
+            false => None,
+        };
+        if pty.is_some() {
+            // The output is read from the pseudo-terminal.
+        } else if self.exec_parse.is_some() {
+            command.stdout(Stdio::piped());
+            // Its own process group, so that it's killed with its children if it times out. A
+            // terminal already starts a new session.
+            command.process_group(0);
+        } else if self.shared.emit_events {
+            // Keep stdout clean for `--emit-events`.
+            command.stdout(io::stderr().as_fd().try_clone_to_owned()?);
+        }
+        let spawned = command.spawn();
+        // Closes Ramon's copies of the terminal, so that reading it ends when the child exits.
+        drop(command);
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(err) => {
+                self.stats.exec_failed(&err);
+                return Err(err.into());
+            }
+        };
+
+        if let Some(regex) = &self.exec_parse {
+            self.exec_running.store(true, Ordering::Relaxed);
+            let output = async {
+                let stdout = match pty {
+                    Some(master) => read_pty(master).await?,
+                    None => {
+                        let mut stdout = Vec::new();
+                        if let Some(mut pipe) = child.stdout.take() {
+                            pipe.read_to_end(&mut stdout).await?;
+                        }
+                        stdout
+                    }
+                };
+                io::Result::Ok((child.wait().await?, stdout))
+            };
+            // A command that hangs would otherwise block the monitor's events forever.
+            let Ok(res) = time::timeout(self.exec_timeout, output).await else {
+                kill_process_group(&mut child).await;
+                self.exec_running.store(false, Ordering::Relaxed);
+                let err = format!(
+                    "Command timed out after {}s, so it was killed.",
+                    self.exec_timeout.as_secs()
+                );
+                warn!("[{}] {err}", self.name);
+                self.stats.exec_failed(err);
+                return Ok(());
+            };
+            self.exec_running.store(false, Ordering::Relaxed);
+            let (status, stdout) = match res {
+                Ok(output) => output,
+                Err(err) => {
+                    self.stats.exec_failed(&err);
+                    return Err(err.into());
+                }
+            };
+            if !status.success() {
+                self.stats
+                    .exec_failed(format!("Command exited with {status}"));
+                warn!("[{}] Command exited with {}", self.name, status);
+            } else if let Some(path) = dropped_file {
+                finish_dropped_file(&self.name, &self.after_exec, &path, &self.stats).await;
+            }
+            // Terminals end lines with `\r\n`.
+            let stdout = String::from_utf8_lossy(&stdout).replace("\r\n", "\n");
+            match regex.captures(&stdout) {
+                Some(captures) => insert_captures(regex, &captures, temp_variables),
+                None => warn!("[{}] `exec_parse` did not match the output.", self.name),
+            }
+            return Ok(());
+        }
+
+        if let Some(master) = pty {
+            tokio::spawn(forward_pty(
+                self.name.clone(),
+                master,
+                self.shared.emit_events,
+            ));
+        }
+        self.exec_running.store(true, Ordering::Relaxed);
+        let exec_running = self.exec_running.clone();
+        let name = self.name.clone();
+        let stats = self.stats.clone();
+        let after_exec = self.after_exec.clone();
+        tokio::spawn(async move {
+            let res = child.wait().await;
+            exec_running.store(false, Ordering::Relaxed);
+            match res {
+                Ok(status) if !status.success() => {
+                    stats.exec_failed(format!("Command exited with {status}"));
+                    warn!("[{name}] Command exited with {status}");
+                }
+                Ok(_) => {
+                    if let Some(path) = dropped_file {
+                        finish_dropped_file(&name, &after_exec, &path, &stats).await;
+                    }
+                }
+                Err(err) => {
+                    stats.exec_failed(&err);
+                    error!("[{name}] {err}");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn notify(
+        &self,
+        notification: &Notification,
+        temp_variables: &HashMap<String, Value>,
+        severity: Severity,
+        source: &'static str,
+    ) -> Result<bool> {
+        if let Some(remaining) = self.shared.maintenance.suppress(&self.name) {
+            info!(
+                "[{}] Not sending notification because alerts are muted for maintenance for another {}s.",
+                self.name,
+                remaining.as_secs()
+            );
+            return Ok(false);
+        }
+        // The tail is read once, and isn't escaped, since it isn't a variable.
+        let tail = match (notification.tail, &self.log_path) {
+            (Some(lines), Some(path)) => match log_watcher::read_tail(path, lines).await {
+                Ok(tail) => Some(format!(
+                    "\n\nLast {lines} lines of {}:\n{tail}",
+                    path.display()
+                )),
+                Err(err) => {
+                    warn!("[{}] Failed to read the tail of {path:?}: {err}", self.name);
+                    None
+                }
+            },
+            _ => None,
+        };
+        let mut sent = false;
+        for (target, config) in self.notify_targets(&notification.r#type) {
+            if !config.enabled {
+                debug!(
+                    "[{}] Not sending notification because `{target}` is disabled.",
+                    self.name
+                );
+                continue;
+            }
+            // Each target may escape variables differently.
+            let mut body =
+                template::render_escaped(&notification.body, temp_variables, config.escape);
+            if let Some(tail) = &tail {
+                body += tail;
+            }
+            let rendered = notifier::Notification {
+                monitor: self.name.clone(),
+                severity,
+                source,
+                title: template::render_escaped(&notification.title, temp_variables, config.escape),
+                body,
+                variables: temp_variables.clone(),
+                test: false,
+            };
+            debug!("[{}] Sending notification to `{target}`.", self.name);
+            let limit = self.shared.notify_limit.as_ref();
+            match notifier::send(
+                &self.name,
+                target,
+                config,
+                &rendered,
+                limit,
+                &self.shared.http,
+            )
+            .await
+            {
+                Ok(true) => {
+                    info!("[{}] Sent notification to `{target}`.", self.name);
+                    self.stats.notifications.fetch_add(1, Ordering::Relaxed);
+                    sent = true;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    error!("[{}] Failed to notify `{target}`: {err}", self.name);
+                    self.stats.notify_failed(target, &err);
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Returns the targets of the notify config, including the configs it escalates to now that
+    /// another notification is being sent with it.
+    fn notify_targets<'a>(&'a self, name: &'a str) -> Vec<(&'a str, &'a NotificationConfig)> {
+        let configs = &self.shared.notify_configs;
+        let mut targets = scheduled_targets(configs, name);
+        let tiers = &configs[name].escalate;
+        if tiers.is_empty() {
+            return targets;
+        }
+
+        let now = Instant::now();
+        let mut history = self.notify_history.lock().unwrap();
+        let sent = history.entry(name.to_owned()).or_default();
+        sent.push_back(now);
+        let longest = tiers
+            .iter()
+            .map(|tier| tier.within)
+            .max()
+            .unwrap_or_default();
+        while sent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > longest)
+        {
+            sent.pop_front();
+        }
+        for tier in tiers {
+            let count = sent
+                .iter()
+                .filter(|time| now.duration_since(**time) <= tier.within)
+                .count();
+            // A config that inherits `escalate` from `notify.default` may be its own target.
+            if count <= tier.after || tier.target == name {
+                continue;
+            }
+            info!(
+                "[{}] Escalating to `{}` after {count} notifications to `{name}` within {}s.",
+                self.name,
+                tier.target,
+                tier.within.as_secs(),
+            );
+            for (target, config) in scheduled_targets(configs, &tier.target) {
+                if !targets.iter().any(|(other, _)| *other == target) {
+                    targets.push((target, config));
+                }
+            }
+        }
+        targets
+    }
+
+    /// Sends one notice when `max_notifications_per_hour` starts suppressing notifications.
+    async fn notify_capped(
+        &self,
+        temp_variables: &HashMap<String, Value>,
+        severity: Severity,
+        source: &'static str,
+    ) {
+        let Some(cap) = &self.notify_cap else {
+            return;
+        };
+        if cap.suppressed != 1 {
+            debug!("[{}] Suppressed notification.", self.name);
+            return;
+        }
+        warn!(
+            "[{}] Sent {} notifications within an hour. Suppressing notifications.",
+            self.name, cap.max,
+        );
+        let title = format!("{}: notifications are rate-limited", self.name);
+        let body = format!(
+            "Monitor `{}` sent {} notifications within an hour, so further notifications are suppressed until fewer were sent within the last hour.",
+            self.name, cap.max,
+        );
+        self.notify_each_type(title, body, temp_variables, severity, source)
+            .await;
+    }
+
+    /// Resets the suppressed count, and sends a summary if `summarize_suppressed` is set.
+    async fn notify_cap_cleared(&mut self) {
+        let Some(cap) = &mut self.notify_cap else {
+            return;
+        };
+        cap.prune(Instant::now());
+        let suppressed = replace(&mut cap.suppressed, 0);
+        info!(
+            "[{}] No longer suppressing notifications. {suppressed} were suppressed.",
+            self.name
+        );
+        if !cap.summarize {
+            return;
+        }
+        let title = format!("{}: suppressed {suppressed} notifications", self.name);
+        let body = format!(
+            "Monitor `{}` suppressed {suppressed} notifications because of `max_notifications_per_hour`. Notifications are no longer suppressed.",
+            self.name,
+        );
+        let variables = HashMap::from([("suppressed".to_owned(), (suppressed as i64).into())]);
+        self.notify_each_type(
+            title,
+            body,
+            &variables,
+            self.severity,
+            "max_notifications_per_hour",
+        )
+        .await;
+    }
+
+    /// Sends a notification with each of the monitor's notify configs.
+    async fn notify_each_type(
+        &self,
+        title: String,
+        body: String,
+        temp_variables: &HashMap<String, Value>,
+        severity: Severity,
+        source: &'static str,
+    ) {
+        let mut types = self
+            .notify
+            .iter()
+            .map(|notification| &notification.r#type)
+            .collect::<Vec<_>>();
+        types.sort();
+        types.dedup();
+        for notify_type in types {
+            let notification = Notification {
+                r#type: notify_type.clone(),
+                title: title.clone(),
+                body: body.clone(),
+                tail: None,
+            };
+            if let Err(err) = self
+                .notify(&notification, temp_variables, severity, source)
+                .await
+            {
+                error!("[{}] Notification `{notify_type}`: {err}", self.name);
+            }
+        }
+    }
+
+    async fn store_unique_values(&mut self, watched_file: Option<&Path>) -> Result<()> {
         let _ = create_dir("/var/cache/ramon").await;
 
-        let file_path = format!("/var/cache/ramon/unique_{}", self.name);
+        let file_path = unique_file_path(&self.name, watched_file);
         let tmp_file_path = format!("{file_path}.new");
         let file = OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .open(&tmp_file_path)
             .await
             .map_err(|err| anyhow!("Failed to create {tmp_file_path}: {err}"))?;
@@ -280,10 +2001,10 @@ impl Monitor {
 
         let variables = match &self.unique {
             None => panic!(),
-            Some(values) => &values.recorded_values,
+            Some(unique) => &unique.recorded_values[&watched_file.map(Path::to_owned)],
         };
         for variable in variables {
-            writer.write(variable.as_bytes()).await?;
+            writer.write_all(variable.as_bytes()).await?;
             writer.write_u8(b'\n').await?;
         }
         writer.flush().await?;
@@ -293,65 +2014,1004 @@ impl Monitor {
         Ok(())
     }
 
-    async fn run_actions(&mut self, temp_variables: HashMap<String, Value>) -> Result<()> {
+    async fn run_actions(
+        &mut self,
+        mut temp_variables: HashMap<String, Value>,
+        severity: Severity,
+        source: &'static str,
+    ) -> Result<()> {
         self.last_action_time = Some(Instant::now());
+        self.last_action_severity = severity;
+        self.first_match_fired = true;
+        self.stats.actions.fetch_add(1, Ordering::Relaxed);
+        self.shared.down.lock().unwrap().insert(
+            self.name.clone(),
+            Down {
+                since: Instant::now(),
+                until_recovered: self.down_since.is_some(),
+            },
+        );
 
-        if let Some(exec) = &self.exec {
-            let mut command = match exec {
-                Exec::Shell(sh_command) => {
-                    let mut command = Command::new("sh");
-                    command.args(["-c", sh_command]);
-                    command
-                }
-                Exec::Spawn(args) => {
-                    let mut command = Command::new(&args[0]);
-                    command.args(&args[1..]);
-                    command
+        if self.shared.dry_run {
+            self.dry_run_variables = Some(temp_variables);
+            return Ok(());
+        }
+
+        let mut duplicate = false;
+        // A new fingerprint is only recorded once a notification was sent with it, so that it
+        // isn't swallowed when `exec` fails or notifications are capped or muted.
+        let mut new_fingerprint = None;
+        if let Some(fingerprints) = &mut self.fingerprints {
+            let fingerprint = template::render(&fingerprints.template, &temp_variables);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            let window = fingerprints.window.as_secs();
+            fingerprints
+                .sent
+                .retain(|_, sent| now.saturating_sub(*sent) < window);
+            match fingerprints.sent.get(&fingerprint) {
+                Some(sent) => {
+                    info!(
+                        "[{}] Not sending notifications because fingerprint `{fingerprint}` was notified {}s ago.",
+                        self.name,
+                        now.saturating_sub(*sent)
+                    );
+                    duplicate = true;
                 }
+                None => new_fingerprint = Some((fingerprint.clone(), now)),
+            }
+            temp_variables.insert("fingerprint".to_owned(), fingerprint.into());
+        }
+
+        if let Some(exec) = &self.exec {
+            // `after_exec` only applies to files dropped into `watch_dir`.
+            let dropped_file = match (&self.after_exec, source) {
+                (AfterExec::Keep, _) => None,
+                (_, "watch_dir") => temp_variables
+                    .get("file")
+                    .map(|file| PathBuf::from(value_to_string(file.clone()))),
+                _ => None,
             };
-            for (var, val) in &temp_variables {
-                command.env(var, value_to_string((*val).clone()));
+            self.exec(exec, &mut temp_variables, dropped_file).await?;
+        }
+
+        // Duplicates don't count towards the cap.
+        let mut allowed = !duplicate;
+        if allowed {
+            allowed = match &mut self.notify_cap {
+                None => true,
+                Some(cap) => cap.allow(),
+            };
+            if !allowed {
+                self.notify_capped(&temp_variables, severity, source).await;
+            } else if self
+                .notify_cap
+                .as_ref()
+                .is_some_and(|cap| cap.suppressed > 0)
+            {
+                // The window cleared before the timer fired.
+                self.notify_cap_cleared().await;
             }
-            let mut child = command.spawn()?;
-            tokio::spawn(async move {
-                if let Err(err) = child.wait().await {
-                    error!("{err}");
+        }
+
+        // A failure to send one notification doesn't prevent the others from being sent.
+        let mut sent = false;
+        for notification in self.notify.iter().filter(|_| allowed) {
+            match self
+                .notify(notification, &temp_variables, severity, source)
+                .await
+            {
+                Ok(any_sent) => sent |= any_sent,
+                Err(err) => error!(
+                    "[{}] Notification `{}`: {err}",
+                    self.name, notification.r#type
+                ),
+            }
+        }
+        if let (Some(fingerprints), Some((fingerprint, now)), true) =
+            (&mut self.fingerprints, new_fingerprint, sent)
+        {
+            fingerprints.sent.insert(fingerprint, now);
+            if let Err(err) = store_fingerprints(&self.name, &fingerprints.sent).await {
+                warn!("[{}] Failed to store fingerprints: {err}", self.name);
+            }
+        }
+
+        if self.shared.audit_tx.is_none()
+            && self.shared.syslog_tx.is_none()
+            && !self.shared.emit_events
+            && self.fifo.is_none()
+        {
+            return Ok(());
+        }
+        let mut actions = Vec::new();
+        if self.exec.is_some() {
+            actions.push("exec");
+        }
+        if self.fifo.is_some() {
+            actions.push("fifo");
+        }
+        if !self.notify.is_empty() {
+            actions.push("notify");
+        }
+        let entry = AuditEntry::new(self.name.clone(), severity, temp_variables, actions);
+        if let Some(fifo) = &mut self.fifo {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            if let Err(err) = fifo.write(&line).await {
+                warn!("[{}] {err}", self.name);
+            }
+        }
+        if self.shared.emit_events {
+            if let Err(err) = emit_event(&entry) {
+                error!("[{}] Failed to emit event: {err}", self.name);
+            }
+        }
+        // A collector that's slow or unreachable mustn't hold up the monitor.
+        if let Some(syslog_tx) = &self.shared.syslog_tx {
+            match syslog_tx.try_send(entry.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "[{}] Syslog forwarder is behind. Dropped the message.",
+                        self.name
+                    );
                 }
-            });
+                Err(TrySendError::Closed(_)) => {
+                    warn!("[{}] Syslog forwarder is closed.", self.name);
+                }
+            }
+        }
+        if let Some(audit_tx) = &self.shared.audit_tx {
+            if audit_tx.send(entry).await.is_err() {
+                warn!("[{}] Audit log is closed.", self.name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn log_watcher_key(
+    log: &LogConfig,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+) -> LogWatcherKey {
+    LogWatcherKey {
+        // Different spellings of the same path share a watcher.
+        path: std::fs::canonicalize(&log.path).unwrap_or_else(|_| log.path.clone()),
+        binary: log.binary,
+        batch_lines: log.batch_lines,
+        dedup_consecutive: log.dedup_consecutive,
+        reopen_timeout: log.reopen_timeout,
+        poll_interval: log.poll_interval,
+        line_limit: log.line_limit,
+        line_ending: log.line_ending,
+        circuit_breaker,
+    }
+}
+
+/// Subscribes the monitor to an existing watcher for the same log, if there is one. Returns the
+/// watcher's progress.
+fn share_log_watcher(
+    name: &str,
+    log: &LogConfig,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    shared: &Shared,
+    event_tx: &Sender<Event>,
+) -> Option<Arc<LogProgress>> {
+    if log.replay.is_some() {
+        return None;
+    }
+    let key = log_watcher_key(log, circuit_breaker);
+    let log_watchers = shared.log_watchers.lock().unwrap();
+    let (subscribers, progress) = log_watchers.get(&key)?;
+    debug!("[{name}] Sharing the watcher for {:?}.", log.path);
+    subscribers.lock().unwrap().push(event_tx.clone());
+    Some(progress.clone())
+}
+
+/// Deletes or moves a `watch_dir` file that `exec` processed successfully.
+async fn finish_dropped_file(name: &str, after_exec: &AfterExec, path: &Path, stats: &Stats) {
+    let res = match after_exec {
+        AfterExec::Keep => return,
+        AfterExec::Delete => fs::remove_file(path)
+            .await
+            .map(|()| info!("[{name}] Deleted {path:?}.")),
+        AfterExec::Move(dir) => move_file(path, dir)
+            .await
+            .map(|dest| info!("[{name}] Moved {path:?} to {dest:?}.")),
+    };
+    if let Err(err) = res {
+        error!("[{name}] Failed to clean up {path:?} after exec: {err}");
+        stats.exec_failed(format!("Failed to clean up {path:?}: {err}"));
+    }
+}
+
+/// Moves the file into the directory, copying it if the directory is on another filesystem.
+/// A file with the same name isn't replaced; a `.1`, `.2`, etc. suffix is added instead. Returns
+/// the new path.
+async fn move_file(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    let Some(file_name) = path.file_name() else {
+        return Err(io::Error::other("path has no file name"));
+    };
+    for suffix in 0..MAX_MOVE_SUFFIX {
+        let mut dest_name = file_name.to_owned();
+        if suffix > 0 {
+            dest_name.push(format!(".{suffix}"));
+        }
+        let dest = dir.join(dest_name);
+        // Unlike renaming, linking fails if the destination exists.
+        match fs::hard_link(path, &dest).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+                let mut dest_file = match OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&dest)
+                    .await
+                {
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                    res => res?,
+                };
+                let mut file = File::open(path).await?;
+                tokio::io::copy(&mut file, &mut dest_file).await?;
+                fs::set_permissions(&dest, file.metadata().await?.permissions()).await?;
+            }
+            Err(err) => return Err(err),
+        }
+        fs::remove_file(path).await?;
+        return Ok(dest);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{MAX_MOVE_SUFFIX} files with the same name already exist"),
+    ))
+}
+
+/// Renders the command that `exec` runs.
+fn exec_command(exec: &Exec, variables: &HashMap<String, Value>) -> Command {
+    // Templates are rendered before the shell runs, and values are quoted so that they can't
+    // inject commands.
+    match exec {
+        Exec::Shell(sh_command) => {
+            let mut command = Command::new("sh");
+            command.args(["-c", &template::render_shell(sh_command, variables)]);
+            command
+        }
+        Exec::Spawn(args) => {
+            let mut command = Command::new(&args[0]);
+            command.args(args[1..].iter().map(|arg| template::render(arg, variables)));
+            command
+        }
+    }
+}
+
+/// Attaches the command's stdin, stdout, and stderr to a new pseudo-terminal, which becomes its
+/// controlling terminal. Returns the master side, which the output is read from.
+fn attach_pty(command: &mut Command) -> Result<std::fs::File> {
+    let mut master = -1;
+    let mut slave = -1;
+    // SAFETY: The pointers are valid, and the name, termios, and window size are optional.
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if res != 0 {
+        return Err(anyhow!(
+            "Failed to open a pseudo-terminal: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    // SAFETY: `openpty` succeeded, so both are open file descriptors that nothing else owns.
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    // `openpty` can't open them with `O_CLOEXEC`, so other children spawned in the meantime
+    // would inherit them, and the terminal wouldn't close until they exit. The child gets the
+    // slave as its stdio, which is duplicated without the flag.
+    for fd in [&master, &slave] {
+        // SAFETY: The file descriptor is open, and `fcntl` has no other requirements.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(anyhow!(
+                "Failed to configure the pseudo-terminal: {}",
+                io::Error::last_os_error()
+            ));
+        }
+    }
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    // SAFETY: Only async-signal-safe functions are called between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            // A terminal can only control a session leader's session.
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(master.into())
+}
+
+/// Kills the child and every process in its process group, and waits for the child to exit.
+async fn kill_process_group(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: `kill` has no memory safety requirements. The child hasn't been waited for, so
+        // its PID, which is also its process group ID, hasn't been reused.
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
         }
+    }
+    let _ = child.wait().await;
+}
+
+/// Reads the pseudo-terminal until every process attached to it has exited.
+async fn read_pty(master: std::fs::File) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match fs::File::from_std(master).read_to_end(&mut output).await {
+        Ok(_) => Ok(output),
+        // Linux reports a terminal whose other side was closed as EIO rather than EOF.
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(output),
+        Err(err) => Err(err),
+    }
+}
+
+/// Copies the pseudo-terminal's output to Ramon's stdout, or stderr for `--emit-events`, until
+/// every process attached to it has exited.
+async fn forward_pty(name: String, master: std::fs::File, emit_events: bool) {
+    let mut master = fs::File::from_std(master);
+    let res = match emit_events {
+        true => tokio::io::copy(&mut master, &mut tokio::io::stderr()).await,
+        false => tokio::io::copy(&mut master, &mut tokio::io::stdout()).await,
+    };
+    match res {
+        Ok(_) => {}
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => {}
+        Err(err) => warn!("[{name}] Failed to read the pseudo-terminal: {err}"),
+    }
+}
 
-        if let Some(notification) = &self.notify {
-            if let Some(smtp) = &self.notify_config.smtp {
-                let email = Message::builder()
-                    .from(smtp.from.clone())
-                    .to(smtp.to.clone())
-                    .subject(&notification.title)
-                    .header(ContentType::TEXT_PLAIN)
-                    .body(notification.body.clone())
-                    .map_err(|err| anyhow!("Failed to build email: {err}"))?;
-                let mailer = match &smtp.login {
-                    None => SmtpTransport::unencrypted_localhost(),
-                    Some(login) => {
-                        let creds =
-                            Credentials::new(login.username.clone(), login.password.clone());
-                        SmtpTransport::starttls_relay(&login.host)
-                            .map_err(|err| anyhow!("Failed to parse {:?}: {err}", login.host))?
-                            .credentials(creds)
-                            .build()
+/// Keeps `exec` running for `exec_mode = "daemon"`, restarting it with an exponential backoff
+/// whenever it exits, until `stop_rx` is set.
+async fn run_daemon(
+    name: String,
+    exec: Exec,
+    variables: HashMap<String, Value>,
+    env_newlines: EnvNewlines,
+    shared: Shared,
+    stats: Arc<Stats>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    let mut backoff = DAEMON_INITIAL_BACKOFF;
+    loop {
+        let start = Instant::now();
+        let mut command = exec_command(&exec, &variables);
+        for (var, val) in &variables {
+            if let Some(val) = sanitize_env_value(env_newlines, value_to_string(val.clone())) {
+                for name in shared.env_names.of(var) {
+                    command.env(name, &val);
+                }
+            }
+        }
+        if shared.emit_events {
+            // Keep stdout clean for `--emit-events`.
+            match io::stderr().as_fd().try_clone_to_owned() {
+                Ok(stderr) => {
+                    command.stdout(stderr);
+                }
+                Err(err) => warn!("[{name}] Failed to redirect stdout: {err}"),
+            }
+        }
+        // The child is also killed if Ramon exits without stopping it, e.g. after an error.
+        match command.stdin(Stdio::null()).kill_on_drop(true).spawn() {
+            Ok(mut child) => {
+                info!(
+                    "[{name}] Started exec with PID {}.",
+                    child.id().unwrap_or_default()
+                );
+                let res = tokio::select! {
+                    res = child.wait() => res,
+                    _ = stop_rx.changed() => {
+                        if let Err(err) = child.kill().await {
+                            error!("[{name}] Failed to kill exec: {err}");
+                        }
+                        info!("[{name}] Stopped exec.");
+                        return;
                     }
                 };
-                if let Err(err) = mailer.send(&email) {
-                    error!("[{}] Failed to send email: {err}", self.name);
-                    if smtp.login.is_none() {
-                        info!(
-                            "[{}] Consider setting smtp_host, login, and password.",
-                            self.name
-                        );
+                match res {
+                    Ok(status) if status.success() => info!("[{name}] exec exited."),
+                    Ok(status) => {
+                        warn!("[{name}] exec exited with {status}.");
+                        stats.exec_failed(format!("Daemon exited with {status}"));
+                    }
+                    Err(err) => {
+                        error!("[{name}] Failed to wait for exec: {err}");
+                        stats.exec_failed(format!("Failed to wait for daemon: {err}"));
                     }
                 }
             }
+            Err(err) => {
+                error!("[{name}] Failed to start exec: {err}");
+                stats.exec_failed(format!("Failed to start daemon: {err}"));
+            }
         }
 
-        Ok(())
+        // A process that ran for a while was healthy, so the next failure starts over.
+        if start.elapsed() > DAEMON_MAX_BACKOFF {
+            backoff = DAEMON_INITIAL_BACKOFF;
+        }
+        warn!("[{name}] Restarting exec in {}s.", backoff.as_secs());
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = stop_rx.changed() => return,
+        }
+        backoff = (backoff * 2).min(DAEMON_MAX_BACKOFF);
+    }
+}
+
+/// Returns how many events are waiting for the monitor to evaluate them.
+pub fn queued_events(event_tx: &Sender<Event>) -> usize {
+    event_tx.max_capacity() - event_tx.capacity()
+}
+
+/// Logs how far behind the log watcher is every `LAG_LOG_INTERVAL` until the monitor exits.
+async fn log_lag(name: String, progress: Arc<LogProgress>, event_tx: WeakSender<Event>) {
+    let mut interval = time::interval(LAG_LOG_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        let Some(event_tx) = event_tx.upgrade() else {
+            return;
+        };
+        if let Some(bytes_behind) = progress.bytes_behind() {
+            debug!(
+                "[{name}] Log watcher is {bytes_behind} bytes behind, with {} events queued.",
+                queued_events(&event_tx),
+            );
+        }
+    }
+}
+
+/// Sends each event to every subscriber. Unlike a broadcast channel, this never drops events when
+/// a monitor falls behind; it waits for the monitor instead.
+async fn fan_out(mut event_rx: Receiver<Event>, subscribers: Subscribers) {
+    while let Some(event) = event_rx.recv().await {
+        let senders = subscribers.lock().unwrap().clone();
+        for event_tx in senders {
+            // The monitor may have exited.
+            let _ = event_tx.send(event.clone()).await;
+        }
+    }
+}
+
+/// Prints the entry to stdout as a line of JSON for `--emit-events`.
+fn emit_event(entry: &AuditEntry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    // Each line is flushed so that a consumer sees it immediately, even when stdout is a pipe.
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&line)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Sends the state of the unit (e.g. `active` or `failed`) every `SERVICE_STATE_INTERVAL`.
+async fn poll_service_state(service: String, event_tx: Sender<Event>) -> Result<()> {
+    let mut interval = time::interval(SERVICE_STATE_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        // `systemctl is-active` exits with a non-zero code if the unit isn't active, so only the
+        // output is checked.
+        let output = Command::new("systemctl")
+            .args(["is-active", "--", &service])
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|err| anyhow!("Failed to spawn systemctl: {err}"))?;
+        let state = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        if state.is_empty() {
+            bail!("systemctl did not report a state for {service:?}.");
+        }
+        event_tx
+            .send(Event::ServiceState {
+                service: service.clone(),
+                state,
+            })
+            .await?;
+    }
+}
+
+/// Looks up the unit's `ActiveState`, `MainPID`, and `NRestarts` with `systemctl show`.
+async fn show_unit(service: &str) -> Result<HashMap<String, Value>> {
+    let output = Command::new("systemctl")
+        .args([
+            "show",
+            "--property=ActiveState,MainPID,NRestarts",
+            "--",
+            service,
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|err| anyhow!("Failed to spawn systemctl: {err}"))?;
+    if !output.status.success() {
+        bail!("systemctl exited with {}.", output.status);
+    }
+    let mut properties = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (name, value) = match key {
+            "ActiveState" => ("unit_active_state", value.into()),
+            "MainPID" => (
+                "unit_main_pid",
+                value.parse::<i64>().unwrap_or_default().into(),
+            ),
+            "NRestarts" => (
+                "unit_restarts",
+                value.parse::<i64>().unwrap_or_default().into(),
+            ),
+            _ => continue,
+        };
+        properties.insert(name.to_owned(), value);
+    }
+    Ok(properties)
+}
+
+async fn store_changed_value(name: &str, value: &str) -> Result<()> {
+    let _ = create_dir("/var/cache/ramon").await;
+
+    let file_path = format!("/var/cache/ramon/changed_{name}");
+    let tmp_file_path = format!("{file_path}.new");
+    fs::write(&tmp_file_path, format!("{value}\n"))
+        .await
+        .map_err(|err| anyhow!("Failed to create {tmp_file_path}: {err}"))?;
+    rename(tmp_file_path, file_path).await?;
+
+    Ok(())
+}
+
+/// Reads the fingerprints that were notified, and when. Expired fingerprints are dropped when the
+/// next one is checked.
+async fn load_fingerprints(name: &str, dry_run: bool) -> Result<HashMap<String, u64>> {
+    let mut sent = HashMap::new();
+    // Dry runs start from a clean state.
+    if dry_run {
+        return Ok(sent);
+    }
+    let file_path = format!("/var/cache/ramon/fingerprints_{name}");
+    let Ok(file) = OpenOptions::new().read(true).open(file_path).await else {
+        return Ok(sent);
+    };
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        let Some((time, fingerprint)) = line.split_once(' ') else {
+            continue;
+        };
+        if let Ok(time) = time.parse() {
+            sent.insert(unescape_fingerprint(fingerprint), time);
+        }
+    }
+    Ok(sent)
+}
+
+/// Writes each fingerprint and when it was notified as `<unix seconds> <fingerprint>`, with the
+/// fingerprint escaped to fit on the line.
+async fn store_fingerprints(name: &str, sent: &HashMap<String, u64>) -> Result<()> {
+    let _ = create_dir("/var/cache/ramon").await;
+
+    let file_path = format!("/var/cache/ramon/fingerprints_{name}");
+    let tmp_file_path = format!("{file_path}.new");
+    let contents = sent
+        .iter()
+        .map(|(fingerprint, time)| format!("{time} {}\n", escape_fingerprint(fingerprint)))
+        .collect::<String>();
+    fs::write(&tmp_file_path, contents)
+        .await
+        .map_err(|err| anyhow!("Failed to create {tmp_file_path}: {err}"))?;
+    rename(tmp_file_path, file_path).await?;
+
+    Ok(())
+}
+
+/// Escapes backslashes and line breaks, so that distinct fingerprints stay distinct.
+fn escape_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Reverses `escape_fingerprint`.
+fn unescape_fingerprint(escaped: &str) -> String {
+    let mut fingerprint = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => fingerprint.push('\n'),
+            ('\\', Some('r')) => fingerprint.push('\r'),
+            ('\\', Some('\\')) => fingerprint.push('\\'),
+            _ => {
+                fingerprint.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    fingerprint
+}
+
+/// Follows the service's journal.
+fn spawn_journalctl(service: &str, args: &[String]) -> Result<BufReader<ChildStdout>> {
+    let child = Command::new("journalctl")
+        .args(["-n0", "-fu", service])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| anyhow!("Failed to spawn journalctl: {err}"))?;
+    let stdout = child.stdout.ok_or(anyhow!("Failed to capture stdout."))?;
+    Ok(BufReader::new(stdout))
+}
+
+/// Sends each line as an event until the reader is closed.
+pub async fn forward_lines(
+    mut reader: BufReader<ChildStdout>,
+    source: &'static str,
+    mut limiter: LineLimiter,
+    event_tx: Sender<Event>,
+) -> Result<()> {
+    while let Some(line) = limiter.read_line(&mut reader).await? {
+        event_tx
+            .send(Event::NewLogLine {
+                source,
+                line,
+                severity: None,
+                repeat_count: None,
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Like `forward_lines`, but each line is a journal entry from `journalctl -o json`. The entry's
+/// `MESSAGE` is sent with the severity of its `PRIORITY`.
+async fn forward_journal_entries(
+    mut reader: BufReader<ChildStdout>,
+    mut limiter: LineLimiter,
+    event_tx: Sender<Event>,
+) -> Result<()> {
+    while let Some(line) = limiter.read_line(&mut reader).await? {
+        let (line, severity) = match parse_journal_entry(&line) {
+            Some((message, severity)) => (message, severity),
+            None => {
+                debug!("Failed to parse journal entry: {line}");
+                (line, None)
+            }
+        };
+        event_tx
+            .send(Event::NewLogLine {
+                source: "service",
+                line,
+                severity,
+                repeat_count: None,
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Returns the message and the severity of a journal entry. Messages that aren't valid UTF-8 are
+/// exported as arrays of bytes.
+fn parse_journal_entry(line: &str) -> Option<(String, Option<Severity>)> {
+    let entry = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let message = match &entry["MESSAGE"] {
+        serde_json::Value::String(message) => message.clone(),
+        serde_json::Value::Array(bytes) => {
+            let bytes = bytes
+                .iter()
+                .map(|byte| byte.as_u64().map(|byte| byte as u8))
+                .collect::<Option<Vec<_>>>()?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => return None,
+    };
+    let severity = entry["PRIORITY"]
+        .as_str()
+        .and_then(|priority| priority.parse().ok())
+        .map(Severity::from_priority);
+    Some((message, severity))
+}
+
+/// Returns the cache file of the monitor's `unique` values, or of the values seen in the file if
+/// `unique_per_file` is set.
+fn unique_file_path(monitor_name: &str, file: Option<&Path>) -> String {
+    match file {
+        None => format!("/var/cache/ramon/unique_{monitor_name}"),
+        // Escaped so that each file maps to a distinct cache file without subdirectories.
+        Some(file) => format!(
+            "/var/cache/ramon/unique_{monitor_name}_{}",
+            file.display()
+                .to_string()
+                .replace('%', "%25")
+                .replace('/', "%2F")
+        ),
+    }
+}
+
+/// Reads the `unique` values that were recorded, or the values seen in the file if
+/// `unique_per_file` is set.
+async fn load_unique_values(
+    monitor_name: &str,
+    file: Option<&Path>,
+    dry_run: bool,
+) -> Result<HashSet<String>> {
+    let mut values = HashSet::new();
+    // Dry runs start from a clean state.
+    if dry_run {
+        return Ok(values);
+    }
+    let file_path = unique_file_path(monitor_name, file);
+    let Ok(file) = OpenOptions::new().read(true).open(file_path).await else {
+        return Ok(values);
+    };
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        values.insert(line);
+    }
+    Ok(values)
+}
+
+/// A watched file's content for `match_content`.
+enum Content {
+    Text(String),
+    /// The file is bigger than `max_file_size`, so it wasn't read.
+    TooBig,
+}
+
+/// Reads a watched file for `match_content`. Returns `None` if it can't be read or isn't valid
+/// UTF-8.
+async fn read_content(monitor_name: &str, path: &Path, max_size: u64) -> Option<Content> {
+    let too_big = || {
+        warn!("[{monitor_name}] {path:?} is bigger than `max_file_size`. Skipping the match.");
+        Some(Content::TooBig)
+    };
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(err) => {
+            debug!("[{monitor_name}] Failed to open {path:?}: {err}");
+            return None;
+        }
+    };
+    match file.metadata().await {
+        Ok(metadata) if metadata.len() > max_size => return too_big(),
+        Ok(_) => {}
+        Err(err) => {
+            warn!("[{monitor_name}] Failed to stat {path:?}: {err}");
+            return None;
+        }
+    }
+    // The file may grow after its size was checked, so no more than the limit is read.
+    let mut content = Vec::new();
+    if let Err(err) = file.take(max_size + 1).read_to_end(&mut content).await {
+        warn!("[{monitor_name}] Failed to read {path:?}: {err}");
+        return None;
+    }
+    if content.len() as u64 > max_size {
+        return too_big();
+    }
+    match String::from_utf8(content) {
+        Ok(content) => Some(Content::Text(content)),
+        Err(err) => {
+            warn!("[{monitor_name}] {path:?} isn't valid UTF-8: {err}");
+            None
+        }
+    }
+}
+
+/// Adds the named and numbered capture groups to the variables.
+fn insert_captures(
+    regex: &Regex,
+    captures: &Captures,
+    temp_variables: &mut HashMap<String, Value>,
+) {
+    // Optional groups that didn't take part in the match are left unset.
+    for (i, variable) in capture_variables(regex.capture_names()) {
+        if let Some(capture) = captures.get(i) {
+            temp_variables.insert(variable, capture.as_str().into());
+        }
+    }
+}
+
+/// Resolves a notification config's `schedule` to the configs that should be sent to now.
+/// If no entry matches the current time, every target in the schedule is used.
+fn scheduled_targets<'a>(
+    configs: &'a HashMap<String, NotificationConfig>,
+    name: &'a str,
+) -> Vec<(&'a str, &'a NotificationConfig)> {
+    let config = &configs[name];
+    if config.schedule.is_empty() || !config.enabled {
+        return vec![(name, config)];
+    }
+
+    let now = match config.timezone {
+        Some(timezone) => Utc::now().with_timezone(&timezone).time(),
+        None => Local::now().time(),
+    };
+    let in_range = |entry: &&ScheduleEntry| match entry.from <= entry.to {
+        true => entry.from <= now && now < entry.to,
+        // The range wraps around midnight.
+        false => now >= entry.from || now < entry.to,
+    };
+    let mut entries = config.schedule.iter().filter(in_range).collect::<Vec<_>>();
+    if entries.is_empty() {
+        entries = config.schedule.iter().collect();
+    }
+    let mut targets = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if !targets.contains(&entry.target.as_str()) {
+            targets.push(entry.target.as_str());
+        }
+    }
+
+    targets
+        .into_iter()
+        .map(|target| (target, &configs[target]))
+        .collect()
+}
+
+/// Applies `env_newlines` to a variable before it's passed to `exec` through the environment.
+/// Returns `None` if the value should be rejected.
+fn sanitize_env_value(mode: EnvNewlines, value: String) -> Option<String> {
+    // Environment variables can never contain NUL.
+    let value = match value.contains('\0') {
+        true => value.replace('\0', ""),
+        false => value,
+    };
+    if !value.contains(['\n', '\r']) {
+        return Some(value);
+    }
+    match mode {
+        EnvNewlines::Escape => Some(
+            value
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r"),
+        ),
+        EnvNewlines::Flatten => Some(
+            value
+                .lines()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .replace('\r', " "),
+        ),
+        EnvNewlines::Reject => None,
+        EnvNewlines::Keep => Some(value),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, ExecPolicy};
+
+    #[test]
+    fn fingerprints_round_trip_through_the_cache() {
+        for fingerprint in ["a b", "line\nbreak", "back\\slash\\n", "crlf\r\n", "\\"] {
+            let escaped = escape_fingerprint(fingerprint);
+            assert!(!escaped.contains('\n'));
+            assert_eq!(unescape_fingerprint(&escaped), fingerprint);
+        }
+        assert_ne!(escape_fingerprint("a\nb"), escape_fingerprint("a b"));
+    }
+
+    /// Creates the only monitor in the config without its sources.
+    async fn new_monitor(doc: &str) -> Monitor {
+        let config = config::parse(doc, &ExecPolicy::default()).unwrap();
+        let shared = Shared {
+            notify_configs: Arc::new(config.notifications),
+            audit_tx: None,
+            syslog_tx: None,
+            notify_limit: None,
+            last_true: Arc::default(),
+            down: Arc::default(),
+            log_watchers: Arc::default(),
+            emit_events: false,
+            dry_run: false,
+            http: reqwest::Client::new(),
+            maintenance: Arc::default(),
+            env_names: Arc::new(config.env_names),
+            daemons: Arc::default(),
+        };
+        let mut monitor_config = config.monitors.into_iter().next().unwrap();
+        monitor_config.log = None;
+        Monitor::new(monitor_config, shared).await.unwrap()
+    }
+
+    fn cooldown_monitor(mode: &str, extra: &str) -> String {
+        format!(
+            r#"
+            [monitor.errors]
+            log = "/dev/null"
+            match_log = "error"
+            cooldown = "500ms"
+            cooldown_mode = "{mode}"
+            exec = "true"
+            {extra}
+            "#
+        )
+    }
+
+    async fn log(monitor: &mut Monitor, line: &str) {
+        monitor
+            .evaluate_and_recheck(Event::NewLogLine {
+                source: "log",
+                line: line.to_owned(),
+                severity: None,
+                repeat_count: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    fn actions(monitor: &Monitor) -> u64 {
+        monitor.stats.actions.load(Ordering::Relaxed)
+    }
+
+    // The actions run 300ms after the first event, so the second event is within the cooldown
+    // when it's counted from the actions, but not when it's counted from the first event.
+    async fn confirm_and_log_again(mode: &str) -> Monitor {
+        let mut monitor = new_monitor(&cooldown_monitor(mode, r#"confirm_after = "300ms""#)).await;
+        log(&mut monitor, "error").await;
+        assert!(monitor.pending.is_some());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        monitor.confirm().await.unwrap();
+        assert_eq!(actions(&monitor), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        log(&mut monitor, "error").await;
+        monitor
+    }
+
+    #[tokio::test]
+    async fn trailing_cooldown_counts_from_when_the_actions_ran() {
+        let monitor = confirm_and_log_again("trailing").await;
+        assert!(monitor.pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn leading_cooldown_counts_from_the_event_that_queued_the_actions() {
+        let monitor = confirm_and_log_again("leading").await;
+        assert!(monitor.pending.is_some());
+    }
+
+    #[tokio::test]
+    async fn events_suppressed_by_warmup_dont_open_a_leading_window() {
+        let mut monitor = new_monitor(&cooldown_monitor("leading", r#"warmup = "300ms""#)).await;
+        log(&mut monitor, "error").await;
+        assert_eq!(actions(&monitor), 0);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        log(&mut monitor, "error").await;
+        assert_eq!(actions(&monitor), 1);
+        // Later events are suppressed without extending the window.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        log(&mut monitor, "error").await;
+        assert_eq!(actions(&monitor), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        log(&mut monitor, "error").await;
+        assert_eq!(actions(&monitor), 2);
     }
 }
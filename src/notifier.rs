@@ -0,0 +1,278 @@
+use crate::{
+    config::{HttpConfig, NotificationConfig, Severity, SmtpConfig},
+    spool,
+};
+use anyhow::{anyhow, bail, Result};
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, Message,
+    SmtpTransport, Transport,
+};
+use log::{info, warn};
+use reqwest::{Client, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    mem::take,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task,
+};
+use toml::Value;
+
+/// Incremented whenever a field of `WebhookPayload` is removed or changes meaning.
+const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+/// How long a webhook may take to respond, so that a hung endpoint doesn't hold a notification
+/// slot forever.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the HTTP client shared by every webhook, so that connections to the same endpoint are
+/// reused rather than renegotiating TLS for each notification.
+pub fn http_client(config: &HttpConfig) -> Result<Client> {
+    let mut builder = Client::builder().timeout(WEBHOOK_TIMEOUT);
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(
+            Proxy::all(proxy.clone()).map_err(|err| anyhow!("Invalid `http.proxy`: {err}"))?,
+        );
+    }
+    for certificate in &config.ca_certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if config.insecure {
+        warn!("TLS certificates are NOT verified because `http.insecure` is set. Anyone between Ramon and a webhook can read and forge notifications.");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder
+        .build()
+        .map_err(|err| anyhow!("Failed to build the HTTP client: {err}"))
+}
+
+/// A rendered notification.
+pub struct Notification {
+    pub monitor: String,
+    pub severity: Severity,
+    /// The kind of event that triggered the notification, e.g. `log` or `every`.
+    pub source: &'static str,
+    pub title: String,
+    pub body: String,
+    pub variables: HashMap<String, Value>,
+    /// Test notifications are marked so that they aren't mistaken for real alerts.
+    pub test: bool,
+}
+
+/// A notification as it's delivered, which is also what's written to `spool_dir` when it fails.
+#[derive(Serialize, Deserialize)]
+pub struct Outgoing {
+    /// The notify config it's sent with.
+    pub target: String,
+    /// The backends that haven't sent it yet.
+    pub backends: Vec<Backend>,
+    pub monitor: String,
+    pub severity: Severity,
+    /// When the notification was first sent, in Unix seconds.
+    pub timestamp: u64,
+    pub source: String,
+    pub title: String,
+    pub body: String,
+    pub variables: HashMap<String, Value>,
+    pub test: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Smtp,
+    Webhook,
+}
+
+impl Backend {
+    fn as_str(self) -> &'static str {
+        match self {
+            Backend::Smtp => "email",
+            Backend::Webhook => "webhook",
+        }
+    }
+}
+
+impl Outgoing {
+    fn new(target: &str, backends: Vec<Backend>, notification: &Notification) -> Self {
+        Self {
+            target: target.to_owned(),
+            backends,
+            monitor: notification.monitor.clone(),
+            severity: notification.severity,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            source: notification.source.to_owned(),
+            title: notification.title.clone(),
+            body: notification.body.clone(),
+            variables: notification.variables.clone(),
+            test: notification.test,
+        }
+    }
+
+    fn subject(&self) -> String {
+        if self.test {
+            format!("[TEST] {}", self.title)
+        } else {
+            self.title.clone()
+        }
+    }
+}
+
+/// The JSON body POSTed to webhooks.
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    schema_version: u32,
+    monitor: &'a str,
+    severity: Severity,
+    /// Unix seconds.
+    timestamp: u64,
+    source: &'a str,
+    title: &'a str,
+    body: &'a str,
+    variables: &'a HashMap<String, Value>,
+    test: bool,
+}
+
+/// Sends the notification with every backend in the config. Returns false if the config has no
+/// backends. If a backend fails, the remaining backends are still tried, and the notification is
+/// spooled for the failed ones if the config has a `spool_dir`.
+pub async fn send(
+    name: &str,
+    target: &str,
+    config: &NotificationConfig,
+    notification: &Notification,
+    limit: Option<&Arc<Semaphore>>,
+    http: &Client,
+) -> Result<bool> {
+    let mut backends = Vec::new();
+    if config.smtp.is_some() {
+        backends.push(Backend::Smtp);
+    }
+    if config.webhook.is_some() {
+        backends.push(Backend::Webhook);
+    }
+    if backends.is_empty() {
+        return Ok(false);
+    }
+
+    let _permit = acquire(name, limit).await?;
+    let mut outgoing = Outgoing::new(target, backends, notification);
+    let errors = deliver(config, &mut outgoing, http).await;
+    if errors.is_empty() {
+        return Ok(true);
+    }
+    let mut message = errors.join("\n");
+    // Test notifications are only useful while someone is watching.
+    if let Some(dir) = config.spool_dir.as_ref().filter(|_| !notification.test) {
+        match spool::write(dir, &outgoing).await {
+            Ok(path) => message += &format!("\nSpooled to {path:?} to retry later."),
+            Err(err) => message += &format!("\nFailed to spool the notification: {err}"),
+        }
+    }
+    bail!(message);
+}
+
+/// Sends the notification with each of its remaining backends, leaving the ones that failed in
+/// `backends`. Returns their errors.
+pub async fn deliver(
+    config: &NotificationConfig,
+    outgoing: &mut Outgoing,
+    http: &Client,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for backend in take(&mut outgoing.backends) {
+        let res = match (backend, &config.smtp, &config.webhook) {
+            (Backend::Smtp, Some(smtp), _) => send_email(smtp, outgoing).await,
+            (Backend::Webhook, _, Some(url)) => send_webhook(http, url, outgoing).await,
+            // Only happens to spooled notifications after the config changed.
+            _ => {
+                warn!(
+                    "[{}] Dropping {} notification, since `{}` no longer sends them.",
+                    outgoing.monitor,
+                    backend.as_str(),
+                    outgoing.target
+                );
+                continue;
+            }
+        };
+        if let Err(err) = res {
+            errors.push(err.to_string());
+            outgoing.backends.push(backend);
+        }
+    }
+    errors
+}
+
+/// Waits for a permit to send a notification, if `max_concurrent_notifications` is set.
+pub async fn acquire(
+    name: &str,
+    limit: Option<&Arc<Semaphore>>,
+) -> Result<Option<OwnedSemaphorePermit>> {
+    let Some(limit) = limit else {
+        return Ok(None);
+    };
+    match limit.clone().try_acquire_owned() {
+        Ok(permit) => Ok(Some(permit)),
+        Err(_) => {
+            info!("[{name}] Notification queued; too many notifications are being sent.");
+            Ok(Some(limit.clone().acquire_owned().await?))
+        }
+    }
+}
+
+async fn send_email(smtp: &SmtpConfig, notification: &Outgoing) -> Result<()> {
+    let email = Message::builder()
+        .from(smtp.from.clone())
+        .to(smtp.to.clone())
+        .subject(notification.subject())
+        .date(UNIX_EPOCH + Duration::from_secs(notification.timestamp))
+        .header(ContentType::TEXT_PLAIN)
+        .body(notification.body.clone())
+        .map_err(|err| anyhow!("Failed to build email: {err}"))?;
+    let mailer = match &smtp.login {
+        None => SmtpTransport::unencrypted_localhost(),
+        Some(login) => {
+            let creds = Credentials::new(login.username.clone(), login.password.clone());
+            SmtpTransport::starttls_relay(&login.host)
+                .map_err(|err| anyhow!("Failed to parse {:?}: {err}", login.host))?
+                .credentials(creds)
+                .build()
+        }
+    };
+
+    if let Err(err) = task::spawn_blocking(move || mailer.send(&email)).await? {
+        let mut message = format!("Failed to send email: {err}");
+        if smtp.login.is_none() {
+            message += " (consider setting smtp_host, username, and password)";
+        }
+        bail!(message);
+    }
+    Ok(())
+}
+
+async fn send_webhook(http: &Client, url: &Url, notification: &Outgoing) -> Result<()> {
+    let payload = WebhookPayload {
+        schema_version: WEBHOOK_SCHEMA_VERSION,
+        monitor: &notification.monitor,
+        severity: notification.severity,
+        timestamp: notification.timestamp,
+        source: &notification.source,
+        title: &notification.title,
+        body: &notification.body,
+        variables: &notification.variables,
+        test: notification.test,
+    };
+    http.post(url.clone())
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|err| anyhow!("Failed to send webhook: {err}"))?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::{
+    config::Severity,
+    monitor::{Shared, Stats},
+    notifier::{self, Notification},
+};
+use log::{error, info};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::{self, MissedTickBehavior};
+use toml::Value;
+
+/// How often a monitor's counters are checked for new failures.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The least time between two `on_error` notifications for the same monitor, so that a source
+/// that keeps failing doesn't page continuously. Failures in between are included in the next one.
+const MIN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Notifies `target` whenever the monitor's source, `exec`, or notifications fail, until Ramon
+/// exits. A failure to send this notification is only logged, so that a broken `on_error` target
+/// can't report its own failures.
+pub async fn watch(monitor: String, target: String, stats: Arc<Stats>, shared: Shared) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut reported = 0;
+    loop {
+        interval.tick().await;
+        let failures = stats.failures();
+        if failures == reported {
+            continue;
+        }
+        let new_failures = failures - reported;
+        reported = failures;
+        send(&monitor, &target, &stats, &shared, new_failures).await;
+        time::sleep(MIN_INTERVAL).await;
+    }
+}
+
+async fn send(monitor: &str, target: &str, stats: &Stats, shared: &Shared, new_failures: u64) {
+    if let Some(remaining) = shared.maintenance.suppress(monitor) {
+        info!(
+            "[{monitor}] Not sending `on_error` notification because alerts are muted for maintenance for another {}s.",
+            remaining.as_secs()
+        );
+        return;
+    }
+    let config = &shared.notify_configs[target];
+    if !config.enabled {
+        return;
+    }
+    let last_error = stats.last_error.lock().unwrap().clone().unwrap_or_default();
+    let counter = |counter: &AtomicU64| Value::Integer(counter.load(Ordering::Relaxed) as i64);
+    let notification = Notification {
+        monitor: monitor.to_owned(),
+        severity: Severity::Critical,
+        source: "on_error",
+        title: format!("{monitor}: monitoring is failing"),
+        body: format!(
+            "Monitor `{monitor}` failed {new_failures} times, so it may be missing events. The last failure was:\n{last_error}"
+        ),
+        variables: HashMap::from([
+            ("failures".into(), Value::Integer(new_failures as i64)),
+            ("error".into(), Value::String(last_error)),
+            ("source_errors".into(), counter(&stats.source_errors)),
+            ("exec_failures".into(), counter(&stats.exec_failures)),
+            ("notify_failures".into(), counter(&stats.notify_failures)),
+        ]),
+        test: false,
+    };
+    let limit = shared.notify_limit.as_ref();
+    match notifier::send(monitor, target, config, &notification, limit, &shared.http).await {
+        Ok(true) => info!("[{monitor}] Sent `on_error` notification to `{target}`."),
+        Ok(false) => {}
+        Err(err) => {
+            error!("[{monitor}] Failed to send `on_error` notification to `{target}`: {err}")
+        }
+    }
+}
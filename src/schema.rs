@@ -0,0 +1,721 @@
+use serde_json::{json, Map, Value};
+
+/// A key accepted by the config parser.
+pub struct Key {
+    pub name: &'static str,
+    /// The JSON Schema types the key accepts.
+    pub types: &'static [&'static str],
+    /// The accepted values, if the key is an enum.
+    pub values: &'static [&'static str],
+    /// The default value as JSON, if any.
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+const fn key(name: &'static str, types: &'static [&'static str], description: &'static str) -> Key {
+    Key {
+        name,
+        types,
+        values: &[],
+        default: None,
+        description,
+    }
+}
+
+const fn key_with_default(
+    name: &'static str,
+    types: &'static [&'static str],
+    default: &'static str,
+    description: &'static str,
+) -> Key {
+    Key {
+        name,
+        types,
+        values: &[],
+        default: Some(default),
+        description,
+    }
+}
+
+const fn enum_key(
+    name: &'static str,
+    values: &'static [&'static str],
+    default: &'static str,
+    description: &'static str,
+) -> Key {
+    Key {
+        name,
+        types: &["string"],
+        values,
+        default: Some(default),
+        description,
+    }
+}
+
+pub const TOP_LEVEL_KEYS: &[Key] = &[
+    key(
+        "audit_log",
+        &["string"],
+        "Path to a file that a line of JSON is appended to each time actions are run.",
+    ),
+    key(
+        "max_concurrent_notifications",
+        &["integer"],
+        "The maximum number of notifications sent at once across all monitors.",
+    ),
+    key(
+        "http",
+        &["object"],
+        "Settings for webhooks: a `proxy` URL, a `ca_bundle` of extra trusted certificates, and `insecure` to skip TLS verification.",
+    ),
+    key(
+        "control_socket",
+        &["string"],
+        "A Unix socket that accepts commands such as `status`, `pause`, and `fire`.",
+    ),
+    key(
+        "secrets_file",
+        &["string"],
+        "A TOML file whose `[notify.*]` tables are merged over the notify configs.",
+    ),
+    key(
+        "notify_lifecycle",
+        &["string"],
+        "The notify config that's notified when Ramon starts and stops.",
+    ),
+    key(
+        "warmup",
+        &["string"],
+        "Suppresses actions for the duration after Ramon starts, for monitors that don't set their own.",
+    ),
+    key(
+        "maintenance_summary",
+        &["string"],
+        "The notify config that's sent a summary of suppressed notifications when a maintenance window ends.",
+    ),
+    key_with_default(
+        "env_prefix",
+        &["string"],
+        "\"RAMON_\"",
+        "The prefix of the environment variables `exec` receives variables as.",
+    ),
+    key_with_default(
+        "env_unprefixed",
+        &["boolean"],
+        "false",
+        "Also passes variables to `exec` under their bare names, as older versions did.",
+    ),
+    key(
+        "on_error",
+        &["string"],
+        "The notify config that's notified when a monitor's source, `exec`, or notifications fail, for monitors that don't set their own.",
+    ),
+    key(
+        "heartbeat_url",
+        &["string"],
+        "A URL that Ramon sends a GET request to every `heartbeat_interval`, for a dead man's switch such as healthchecks.io.",
+    ),
+    key_with_default(
+        "heartbeat_interval",
+        &["string"],
+        "\"1m\"",
+        "How often `heartbeat_url` is pinged.",
+    ),
+    key_with_default(
+        "heartbeat_timeout",
+        &["string"],
+        "\"10s\"",
+        "How long each ping of `heartbeat_url` may take.",
+    ),
+    key(
+        "syslog",
+        &["object"],
+        "Sends a syslog message of JSON each time actions are run, to an `address` with a `facility`.",
+    ),
+    key(
+        "commands",
+        &["object"],
+        "Named commands that a monitor's `exec` can refer to as `\"@name\"`.",
+    ),
+    key("notify", &["object"], "Notification configs by name."),
+    key("monitor", &["object"], "Monitors by name."),
+];
+
+pub const NOTIFY_KEYS: &[Key] = &[
+    key_with_default(
+        "enabled",
+        &["boolean"],
+        "true",
+        "Set to false to stop sending notifications with this config.",
+    ),
+    key(
+        "from",
+        &["string"],
+        "The mailbox emails are sent from, e.g. `Ramon <ramon@example.com>`.",
+    ),
+    key(
+        "to",
+        &["string"],
+        "The mailbox emails are sent to. Required if `from` is set.",
+    ),
+    key(
+        "smtp_host",
+        &["string"],
+        "The SMTP relay to use. Defaults to an unencrypted connection to localhost.",
+    ),
+    key(
+        "username",
+        &["string"],
+        "The SMTP username. Required if `smtp_host` is set.",
+    ),
+    key(
+        "password",
+        &["string"],
+        "The SMTP password. Required if `smtp_host` is set.",
+    ),
+    key(
+        "webhook",
+        &["string"],
+        "A URL notifications are POSTed to as versioned JSON.",
+    ),
+    enum_key(
+        "escape",
+        &["none", "html", "slack"],
+        "\"none\"",
+        "How variables are escaped when they're rendered into the title and body.",
+    ),
+    key(
+        "aggregate",
+        &["string"],
+        "How long to wait to aggregate notifications.",
+    ),
+    key(
+        "schedule",
+        &["array"],
+        "Routes notifications to `target` configs between `from` and `to` (`\"HH:MM\"`).",
+    ),
+    key(
+        "escalate",
+        &["array"],
+        "Also notifies `target` once a monitor sends more than `after` notifications within `within`.",
+    ),
+    key(
+        "timezone",
+        &["string"],
+        "The IANA timezone `schedule` is evaluated in. Defaults to the local timezone.",
+    ),
+    key(
+        "spool_dir",
+        &["string"],
+        "Where notifications that failed to send are kept and retried every minute until they're sent.",
+    ),
+];
+
+pub const MONITOR_KEYS: &[Key] = &[
+    key(
+        "every",
+        &["string"],
+        "Fires an event immediately and then at the specified interval.",
+    ),
+    enum_key(
+        "missed_ticks",
+        &["skip", "delay", "burst"],
+        "\"skip\"",
+        "What `every` does with ticks missed while the system was suspended or busy.",
+    ),
+    key_with_default(
+        "skip_if_running",
+        &["boolean"],
+        "false",
+        "Skips ticks while the previous exec is still running.",
+    ),
+    key(
+        "log",
+        &["string"],
+        "Fires an event for every line appended to the file.",
+    ),
+    key(
+        "watch",
+        &["string", "array"],
+        "Fires an event each time the contents of a file matching the glob change.",
+    ),
+    key(
+        "match_content",
+        &["string"],
+        "Only fires `watch` and `watch_dir` events if the file's content matches. Named captures become variables.",
+    ),
+    key_with_default(
+        "match_content_changes",
+        &["boolean"],
+        "false",
+        "Fires `watch` events when a file starts or stops matching `match_content`, with `matched` set.",
+    ),
+    key_with_default(
+        "max_file_size",
+        &["integer"],
+        "1048576",
+        "Files bigger than this many bytes aren't read for `match_content`.",
+    ),
+    key_with_default(
+        "fire_when_skipped",
+        &["boolean"],
+        "false",
+        "Fires for files bigger than `max_file_size` with `skipped` set, rather than ignoring them.",
+    ),
+    key_with_default(
+        "watch_permissions",
+        &["boolean"],
+        "false",
+        "Also fires `watch` events when a file's mode or owner changes.",
+    ),
+    key(
+        "expected_mode",
+        &["string"],
+        "Only reports permission changes that leave the file without this octal mode.",
+    ),
+    key(
+        "expected_owner",
+        &["string"],
+        "Only reports permission changes that leave the file without this `user` or `user:group`.",
+    ),
+    key(
+        "watch_debounce",
+        &["string"],
+        "Holds back `watch` events until the file's modification time is this old, merging the changes in between.",
+    ),
+    key(
+        "watch_dir",
+        &["string"],
+        "Fires an event for each new file in the directory once it's completely written.",
+    ),
+    key_with_default(
+        "settle_time",
+        &["string"],
+        "\"1s\"",
+        "How long a `watch_dir` file's size must stay the same before it's considered completely written.",
+    ),
+    key(
+        "replay_since",
+        &["string"],
+        "Replays the `log` lines written within this duration when Ramon starts.",
+    ),
+    key(
+        "timestamp_regex",
+        &["string"],
+        "Matches the timestamp of a `log` line for `replay_since`.",
+    ),
+    key(
+        "timestamp_format",
+        &["string"],
+        "The strftime format of `timestamp_regex`. Defaults to RFC 3339.",
+    ),
+    key(
+        "poll_interval",
+        &["string"],
+        "Polls `log`, `watch`, and `watch_dir` at this interval instead of using inotify.",
+    ),
+    key_with_default(
+        "binary",
+        &["boolean"],
+        "false",
+        "Matches the log as bytes rather than lines of text.",
+    ),
+    key_with_default(
+        "batch_lines",
+        &["boolean"],
+        "false",
+        "Fires one event for each chunk of lines appended to the `log`.",
+    ),
+    key(
+        "max_line_length",
+        &["integer"],
+        "Truncates or skips `log`, `service`, and `ssh` lines longer than this many bytes.",
+    ),
+    enum_key(
+        "long_lines",
+        &["truncate", "skip"],
+        "\"truncate\"",
+        "What to do with lines longer than `max_line_length`.",
+    ),
+    key_with_default(
+        "dedup_consecutive",
+        &["boolean"],
+        "false",
+        "Collapses runs of identical `log` lines into one event with `repeat_count`.",
+    ),
+    enum_key(
+        "line_ending",
+        &["auto", "lf", "crlf"],
+        "\"auto\"",
+        "How `log` lines end. `auto` splits on `\\n` and removes a `\\r` before it.",
+    ),
+    key_with_default(
+        "reopen_timeout",
+        &["string"],
+        "\"1s\"",
+        "How long to wait for a rotated log to be recreated, or `\"forever\"`.",
+    ),
+    key(
+        "service",
+        &["string"],
+        "Fires an event for every line the systemd service logs.",
+    ),
+    key(
+        "service_args",
+        &["array"],
+        "Extra arguments passed to `journalctl`, such as `--user` or `-o cat`.",
+    ),
+    key_with_default(
+        "severity_from_priority",
+        &["boolean"],
+        "false",
+        "Derives the severity of each `service` line from its journal `PRIORITY`.",
+    ),
+    key(
+        "ssh",
+        &["object"],
+        "Fires an event for every line appended to `path` on `host`, via `ssh` and `tail -F`.",
+    ),
+    key(
+        "service_state",
+        &["string"],
+        "Runs actions when the systemd unit stops being active.",
+    ),
+    key(
+        "down_for",
+        &["string"],
+        "How long the `service_state` unit must be down before actions are run.",
+    ),
+    key(
+        "circuit_breaker",
+        &["object"],
+        "Restarts failed sources, pausing them after `errors` failures within `within` for `pause`.",
+    ),
+    enum_key(
+        "severity",
+        &["info", "warn", "warning", "error", "critical"],
+        "\"info\"",
+        "The default severity of events fired by the monitor.",
+    ),
+    key(
+        "labels",
+        &["object"],
+        "String labels that are available to actions as `label_<key>` variables.",
+    ),
+    key(
+        "group",
+        &["string"],
+        "Runs the monitor only in instances started with a matching `--shard`.",
+    ),
+    key(
+        "for_each",
+        &["array"],
+        "Defines a monitor named `<name>.<item>` for each item, with `{{each}}` replaced by the item.",
+    ),
+    key_with_default(
+        "timezone",
+        &["string"],
+        "\"UTC\"",
+        "The IANA timezone the `timestamp` variable is rendered in.",
+    ),
+    key_with_default(
+        "first_match_only",
+        &["boolean"],
+        "false",
+        "Only runs actions once per process lifetime or log file.",
+    ),
+    key(
+        "cooldown",
+        &["string"],
+        "True if actions have not been run within the duration.",
+    ),
+    enum_key(
+        "cooldown_mode",
+        &["trailing", "leading"],
+        "\"trailing\"",
+        "Whether `cooldown` counts from the last time the actions ran, or from the event that opened the window.",
+    ),
+    key(
+        "warmup",
+        &["string"],
+        "Suppresses actions for the duration after Ramon starts. Overrides the top-level `warmup`.",
+    ),
+    key(
+        "match_log",
+        &["string"],
+        "True if the line matches the regular expression. Named captures become variables.",
+    ),
+    key(
+        "ignore_log",
+        &["string"],
+        "True if the line does not match the regular expression.",
+    ),
+    key(
+        "transform",
+        &["object"],
+        "Rewrites variables with a `regex` and `replace` per variable before the conditions see them.",
+    ),
+    key(
+        "ignore_if",
+        &["object"],
+        "True if none of the variables match their regular expression.",
+    ),
+    key(
+        "unique",
+        &["string"],
+        "True if the variable's value has not been seen before.",
+    ),
+    key_with_default(
+        "unique_per_file",
+        &["boolean"],
+        "false",
+        "Records `unique` values separately for each file matching `watch`.",
+    ),
+    key(
+        "changed",
+        &["string"],
+        "True if the variable's value differs from the last value seen.",
+    ),
+    key(
+        "if",
+        &["string"],
+        "True if the comparison is true. Numbers are compared numerically.",
+    ),
+    key(
+        "rate",
+        &["object"],
+        "True when more than `above` events occur within `window`. Sets `rate` to the count.",
+    ),
+    key(
+        "threshold",
+        &["string"],
+        "True if the preceding conditions were true `n` times within `d` (`\"n/d\"`).",
+    ),
+    key(
+        "require",
+        &["object"],
+        "True if `all` or `any` of the named monitors' conditions were true `within` a duration.",
+    ),
+    key(
+        "confirm_after",
+        &["string"],
+        "Waits this long before running the actions, and only runs them if the trigger wasn't reset.",
+    ),
+    key(
+        "confirm_reset",
+        &["string"],
+        "A regex for lines that cancel actions waiting for `confirm_after`.",
+    ),
+    key(
+        "depends_on",
+        &["string", "array"],
+        "Monitors that suppress this monitor's actions while they're down.",
+    ),
+    key_with_default(
+        "depends_on_within",
+        &["string"],
+        "\"5m\"",
+        "How long a dependency is considered down after it runs its actions.",
+    ),
+    key(
+        "stop_on",
+        &["string"],
+        "A regex for lines that stop the monitor after they're evaluated. Ramon exits once every monitor has stopped.",
+    ),
+    key(
+        "average",
+        &["object"],
+        "Exposes the mean of the last `window` values of `variable` as `<variable>_avg`.",
+    ),
+    key(
+        "anomaly",
+        &["object"],
+        "Only runs actions when `variable` is more than `k` standard deviations from the mean of its last `window` values.",
+    ),
+    key(
+        "exec",
+        &["string", "array"],
+        "Spawns a shell command (string) or a binary with arguments (array).",
+    ),
+    enum_key(
+        "exec_mode",
+        &["trigger", "daemon"],
+        "\"trigger\"",
+        "Whether `exec` runs each time the actions run, or is kept running from startup.",
+    ),
+    key(
+        "exec_parse",
+        &["string"],
+        "Waits for `exec` to exit and matches its output. Named captures become variables.",
+    ),
+    key_with_default(
+        "exec_timeout",
+        &["string"],
+        "\"1m\"",
+        "How long `exec` may run when `exec_parse` is set before it's killed.",
+    ),
+    enum_key(
+        "after_exec",
+        &["keep", "delete", "move"],
+        "\"keep\"",
+        "What happens to a `watch_dir` file once `exec` exits successfully for it.",
+    ),
+    key(
+        "move_to",
+        &["string"],
+        "The directory `watch_dir` files are moved to with `after_exec = \"move\"`.",
+    ),
+    key_with_default(
+        "exec_pty",
+        &["boolean"],
+        "false",
+        "Runs `exec` attached to a pseudo-terminal, for tools that behave differently without one.",
+    ),
+    key(
+        "fifo",
+        &["string"],
+        "A named pipe that each event is written to as a line of JSON. It's created if it doesn't exist.",
+    ),
+    enum_key(
+        "env_newlines",
+        &["escape", "flatten", "reject", "keep"],
+        "\"escape\"",
+        "How variables containing new lines are passed to `exec`.",
+    ),
+    key(
+        "notify",
+        &["string", "object", "array"],
+        "Sends notifications. A string is the title; a table has `type`, `title`, `body`, and `tail`.",
+    ),
+    key(
+        "max_notifications_per_hour",
+        &["integer"],
+        "Suppresses notifications once this many were sent within the last hour.",
+    ),
+    key_with_default(
+        "summarize_suppressed",
+        &["boolean"],
+        "false",
+        "Sends a summary of suppressed notifications once `max_notifications_per_hour` clears.",
+    ),
+    key(
+        "fingerprint",
+        &["string"],
+        "A template that identifies an incident. Notifications with the same fingerprint aren't sent again within `fingerprint_window`.",
+    ),
+    key_with_default(
+        "fingerprint_window",
+        &["string"],
+        "\"1h\"",
+        "How long notifications with the same fingerprint are deduplicated for.",
+    ),
+    key(
+        "on_error",
+        &["string"],
+        "The notify config that's notified when the monitor's source, `exec`, or notifications fail.",
+    ),
+];
+
+/// Returns the names of the keys.
+pub fn names(keys: &[Key]) -> Vec<&'static str> {
+    keys.iter().map(|key| key.name).collect()
+}
+
+/// Returns a JSON Schema describing `ramon.toml`.
+pub fn json_schema() -> Value {
+    let mut schema = object_schema(TOP_LEVEL_KEYS);
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema["title"] = json!("ramon.toml");
+    schema["properties"]["notify"]["additionalProperties"] = object_schema(NOTIFY_KEYS);
+    schema["properties"]["monitor"]["additionalProperties"] = object_schema(MONITOR_KEYS);
+    schema
+}
+
+fn object_schema(keys: &[Key]) -> Value {
+    let properties = keys
+        .iter()
+        .map(|key| (key.name.to_owned(), key_schema(key)))
+        .collect::<Map<_, _>>();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+fn key_schema(key: &Key) -> Value {
+    let mut schema = json!({
+        "type": key.types,
+        "description": key.description,
+    });
+    if !key.values.is_empty() {
+        schema["enum"] = json!(key.values);
+    }
+    if let Some(default) = key.default {
+        schema["default"] = serde_json::from_str(default).expect("invalid default");
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, ExecPolicy};
+
+    /// A value of the key's first type, which may well be invalid for the key.
+    fn sample(key: &Key) -> String {
+        if let Some(value) = key.values.first() {
+            return format!("{value:?}");
+        }
+        match key.types[0] {
+            "string" => "\"x\"",
+            "integer" => "1",
+            "number" => "1.0",
+            "boolean" => "true",
+            "array" => "[]",
+            _ => "{}",
+        }
+        .to_owned()
+    }
+
+    /// Fails unless the parser reads every key, i.e. doesn't reject it as unknown when it's set
+    /// in the document built by `doc`.
+    fn assert_parsed<'a>(keys: impl Iterator<Item = &'a Key>, doc: impl Fn(&str) -> String) {
+        let unparsed = keys
+            .filter(|key| {
+                let line = format!("{} = {}", key.name, sample(key));
+                config::parse(&doc(&line), &ExecPolicy::default()).is_err_and(|err| {
+                    err.to_string()
+                        .contains(&format!("Unknown key `{}`", key.name))
+                })
+            })
+            .map(|key| key.name)
+            .collect::<Vec<_>>();
+        assert!(unparsed.is_empty(), "Not parsed: {unparsed:?}");
+    }
+
+    // Parsing `every` creates an interval, which needs a runtime.
+    #[tokio::test]
+    async fn top_level_keys_are_parsed() {
+        let keys = TOP_LEVEL_KEYS
+            .iter()
+            .filter(|key| !["notify", "monitor"].contains(&key.name));
+        assert_parsed(keys, |line| {
+            format!("{line}\n[monitor.m]\nevery = \"1s\"\n")
+        });
+    }
+
+    #[tokio::test]
+    async fn notify_keys_are_parsed() {
+        assert_parsed(NOTIFY_KEYS.iter(), |line| {
+            format!("[notify.n]\n{line}\n[monitor.m]\nevery = \"1s\"\n")
+        });
+    }
+
+    #[tokio::test]
+    async fn monitor_keys_are_parsed() {
+        assert_parsed(MONITOR_KEYS.iter(), |line| {
+            format!("[monitor.m]\nevery = \"1s\"\n{line}\n")
+        });
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    config::NotificationConfig,
+    notifier::{self, Outgoing},
+};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use reqwest::Client;
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Semaphore,
+    time::{self, MissedTickBehavior},
+};
+
+/// How often spooled notifications are retried.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Distinguishes notifications spooled within the same nanosecond.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Writes the notification to a new file in the directory, and returns its path. File names sort
+/// in the order the notifications were spooled.
+pub async fn write(dir: &Path, outgoing: &Outgoing) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .await
+        .map_err(|err| anyhow!("Failed to create {dir:?}: {err}"))?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("{nanos:020}-{id}.json"));
+    replace(&path, outgoing).await?;
+    Ok(path)
+}
+
+/// Atomically writes the notification to the path. The file is only readable by Ramon, since
+/// notifications may contain secrets from logs.
+async fn replace(path: &Path, outgoing: &Outgoing) -> Result<()> {
+    let json = serde_json::to_vec(outgoing)?;
+    let mut tmp_path = path.to_owned().into_os_string();
+    tmp_path.push(".tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .await
+        .map_err(|err| anyhow!("Failed to create {tmp_path:?}: {err}"))?;
+    file.write_all(&json).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Retries the notifications spooled in the directory every minute until Ramon exits, starting
+/// with the ones left over from before Ramon started. Retries count towards
+/// `max_concurrent_notifications` like any other notification.
+pub async fn run(
+    dir: PathBuf,
+    notify_configs: Arc<HashMap<String, NotificationConfig>>,
+    limit: Option<Arc<Semaphore>>,
+    http: Client,
+) {
+    let mut interval = time::interval(RETRY_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(err) = retry(&dir, &notify_configs, limit.as_ref(), &http).await {
+            warn!("Failed to retry the notifications spooled in {dir:?}: {err}");
+        }
+    }
+}
+
+/// Sends each spooled notification again, oldest first. Once one fails, the rest for the same
+/// notify config wait for the next retry, so that they still arrive in order.
+async fn retry(
+    dir: &Path,
+    notify_configs: &HashMap<String, NotificationConfig>,
+    limit: Option<&Arc<Semaphore>>,
+    http: &Client,
+) -> Result<()> {
+    let mut entries = match fs::read_dir(dir).await {
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        res => res?,
+    };
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut failing = HashSet::new();
+    for path in paths {
+        let mut outgoing = match read(&path).await {
+            Ok(outgoing) => outgoing,
+            Err(err) => {
+                set_aside(&path, &err.to_string()).await;
+                continue;
+            }
+        };
+        let Some(config) = notify_configs.get(&outgoing.target) else {
+            let reason = format!("notify config `{}` no longer exists", outgoing.target);
+            set_aside(&path, &reason).await;
+            continue;
+        };
+        if !config.enabled || failing.contains(&outgoing.target) {
+            continue;
+        }
+
+        let permit = notifier::acquire(&outgoing.monitor, limit).await?;
+        let errors = notifier::deliver(config, &mut outgoing, http).await;
+        drop(permit);
+        if errors.is_empty() {
+            info!(
+                "[{}] Sent spooled notification `{}` to `{}`.",
+                outgoing.monitor, outgoing.title, outgoing.target
+            );
+            fs::remove_file(&path).await?;
+            continue;
+        }
+        warn!(
+            "[{}] Failed to send spooled notification `{}` to `{}` again: {}",
+            outgoing.monitor,
+            outgoing.title,
+            outgoing.target,
+            errors.join("\n")
+        );
+        // Backends that succeeded don't send it again.
+        replace(&path, &outgoing).await?;
+        failing.insert(outgoing.target);
+    }
+    Ok(())
+}
+
+async fn read(path: &Path) -> Result<Outgoing> {
+    let json = fs::read(path).await?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Renames a spooled notification that can't be sent, so that it's kept for inspection but not
+/// retried.
+async fn set_aside(path: &Path, reason: &str) {
+    let mut invalid_path = path.to_owned().into_os_string();
+    invalid_path.push(".invalid");
+    warn!("Not retrying spooled notification {path:?}, because {reason}. Renaming it to {invalid_path:?}.");
+    if let Err(err) = fs::rename(path, &invalid_path).await {
+        warn!("Failed to rename {path:?}: {err}");
+    }
+}
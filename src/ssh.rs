@@ -0,0 +1,108 @@
+use crate::{
+    config::{LineLimit, SshConfig},
+    line_limit::LineLimiter,
+    monitor::{self, Event, Stats},
+    template,
+};
+use anyhow::{anyhow, bail, Result};
+use log::{error, info, warn};
+use std::{
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::mpsc::Sender,
+    time::sleep,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Follows the remote log, reconnecting with an exponential backoff whenever the connection is
+/// lost. Only returns once the monitor has exited.
+pub async fn tail(
+    name: String,
+    config: SshConfig,
+    line_limit: Option<LineLimit>,
+    stats: Arc<Stats>,
+    event_tx: Sender<Event>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let start = Instant::now();
+        let limiter = LineLimiter::new(name.clone(), line_limit);
+        match connect(&name, &config, limiter, event_tx.clone()).await {
+            Ok(()) => stats.source_failed("ssh", "Connection closed"),
+            Err(err) => {
+                error!("[{name}] ssh: {err}");
+                stats.source_failed("ssh", err);
+            }
+        }
+        if event_tx.is_closed() {
+            return;
+        }
+        // A connection that lasted a while was healthy, so the next failure starts over.
+        if start.elapsed() > MAX_BACKOFF {
+            backoff = INITIAL_BACKOFF;
+        }
+        warn!(
+            "[{name}] Reconnecting to {} in {}s.",
+            config.host,
+            backoff.as_secs()
+        );
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(
+    name: &str,
+    config: &SshConfig,
+    limiter: LineLimiter,
+    event_tx: Sender<Event>,
+) -> Result<()> {
+    let mut command = Command::new("ssh");
+    // Never prompt for a password or host key, since there's no one to answer.
+    command.args(["-o", "BatchMode=yes", "-o", "ServerAliveInterval=30"]);
+    if let Some(key) = &config.key {
+        command.arg("-i").arg(key);
+    }
+    // The remote command is interpreted by the remote shell.
+    let remote = format!("tail -n0 -F -- {}", template::shell_quote(&config.path));
+    let mut child = command
+        .args(["--", &config.host, &remote])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| anyhow!("Failed to spawn ssh: {err}"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or(anyhow!("Failed to capture stdout."))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or(anyhow!("Failed to capture stderr."))?;
+    info!(
+        "[{name}] Connecting to {} to follow {}.",
+        config.host, config.path
+    );
+
+    // ssh and tail report errors such as refused connections and missing files on stderr.
+    let stderr_name = name.to_owned();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            warn!("[{stderr_name}] ssh: {line}");
+        }
+    });
+
+    monitor::forward_lines(BufReader::new(stdout), "ssh", limiter, event_tx).await?;
+    let status = child.wait().await?;
+    bail!("Connection to {} closed with {status}.", config.host);
+}
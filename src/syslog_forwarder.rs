@@ -0,0 +1,126 @@
+use crate::{
+    audit_log::AuditEntry,
+    config::{Severity, SyslogAddress, SyslogConfig},
+};
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use std::net::{SocketAddr, ToSocketAddrs};
+use syslog::{Formatter3164, Logger, LoggerBackend};
+use tokio::sync::mpsc::Receiver;
+
+/// Sends an `AuditEntry` as a syslog message of JSON for every action Ramon runs, so that alerts
+/// reach the same collectors as other syslog sources.
+pub struct SyslogForwarder {
+    config: SyslogConfig,
+    formatter: Formatter3164,
+    logger: Option<Logger<LoggerBackend, Formatter3164>>,
+    entry_rx: Receiver<AuditEntry>,
+}
+
+impl SyslogForwarder {
+    pub fn new(config: SyslogConfig, entry_rx: Receiver<AuditEntry>) -> Self {
+        let formatter = Formatter3164 {
+            facility: config.facility,
+            // The local daemon adds its own hostname, but a remote collector needs to know
+            // where the message came from.
+            hostname: match config.address {
+                SyslogAddress::Local | SyslogAddress::Unix(_) => None,
+                SyslogAddress::Udp(_) | SyslogAddress::Tcp(_) => Some(crate::hostname()),
+            },
+            process: "ramon".to_owned(),
+            pid: std::process::id(),
+        };
+        Self {
+            config,
+            formatter,
+            logger: None,
+            entry_rx,
+        }
+    }
+
+    /// Forwards entries until every sender is dropped. This blocks, since the `syslog` crate's
+    /// sockets do, so it should be run with `spawn_blocking`.
+    pub fn start(mut self) {
+        match self.connect() {
+            Ok(logger) => self.logger = Some(logger),
+            Err(err) => warn!("{err}. Retrying with the next action."),
+        }
+        while let Some(entry) = self.entry_rx.blocking_recv() {
+            let message = match serde_json::to_string(&entry) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!(
+                        "[{}] Failed to serialize syslog message: {err}",
+                        entry.monitor
+                    );
+                    continue;
+                }
+            };
+            // A stream socket that was closed, e.g. because the collector restarted, only fails
+            // once it's written to, so the message is sent again over a new connection.
+            let res = self.send(entry.severity, &message).or_else(|err| {
+                debug!("Reconnecting to syslog: {err}");
+                self.logger = None;
+                self.send(entry.severity, &message)
+            });
+            if let Err(err) = res {
+                self.logger = None;
+                warn!(
+                    "[{}] Failed to forward action to syslog: {err}",
+                    entry.monitor
+                );
+            }
+        }
+    }
+
+    fn send(&mut self, severity: Severity, message: &str) -> Result<()> {
+        let logger = match self.logger.take() {
+            Some(logger) => logger,
+            None => self.connect()?,
+        };
+        let logger = self.logger.insert(logger);
+        match severity {
+            Severity::Info => logger.info(message),
+            Severity::Warning => logger.warning(message),
+            Severity::Error => logger.err(message),
+            Severity::Critical => logger.crit(message),
+        }
+        .map_err(|err| anyhow!(describe(&err)))
+    }
+
+    fn connect(&self) -> Result<Logger<LoggerBackend, Formatter3164>> {
+        let formatter = self.formatter.clone();
+        match &self.config.address {
+            SyslogAddress::Local => syslog::unix(formatter),
+            SyslogAddress::Unix(path) => syslog::unix_custom(formatter, path),
+            SyslogAddress::Udp(server) => {
+                let server = server
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| anyhow!("Failed to resolve {server}"))?;
+                let local: SocketAddr = match server {
+                    SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+                    SocketAddr::V6(_) => "[::]:0".parse()?,
+                };
+                syslog::udp(formatter, local, server)
+            }
+            SyslogAddress::Tcp(server) => syslog::tcp(formatter, server.as_str()),
+        }
+        .map_err(|err| anyhow!("Failed to connect to syslog: {}", describe(&err)))
+    }
+}
+
+/// Includes the causes of an initialization error, since the `syslog` crate's error doesn't report
+/// its cause as its source.
+fn describe(err: &syslog::Error) -> String {
+    let mut message = err.to_string();
+    let mut cause = match err {
+        syslog::Error::Initialization(err) => err.source(),
+        syslog::Error::Write(_) | syslog::Error::Io(_) => None,
+    };
+    while let Some(err) = cause {
+        message += &format!(": {err}");
+        cause = err.source();
+    }
+    message
+}
@@ -0,0 +1,94 @@
+use crate::cli::Args;
+use anyhow::{anyhow, bail, Result};
+use std::{env, fs};
+
+/// Returns a systemd unit that runs this binary with the config and flags in the arguments.
+pub fn generate(args: &Args) -> Result<String> {
+    if args.daemonize {
+        bail!("Argument `--generate-systemd-unit` can't be used with `--daemonize`, since systemd keeps Ramon in the foreground.");
+    }
+    let binary = env::current_exe()
+        .map_err(|err| anyhow!("Failed to find the path of the Ramon binary: {err}"))?;
+
+    let mut exec_start = vec![binary.to_string_lossy().into_owned()];
+    if let Some(config) = &args.config {
+        let config = fs::canonicalize(config)
+            .map_err(|err| anyhow!("Failed to resolve config `{}`: {err}", config.display()))?;
+        exec_start.push("--config".to_owned());
+        exec_start.push(config.to_string_lossy().into_owned());
+    }
+    if args.watch_config {
+        exec_start.push("--watch-config".to_owned());
+    }
+    if let Some(shard) = &args.shard {
+        exec_start.push("--shard".to_owned());
+        exec_start.push(shard.clone());
+    }
+    for binary in &args.exec_policy.allowed {
+        exec_start.push("--allow-exec".to_owned());
+        exec_start.push(binary.clone());
+    }
+    if args.exec_policy.forbid_shell {
+        exec_start.push("--forbid-shell-exec".to_owned());
+    }
+    let exec_start = exec_start
+        .iter()
+        .map(|arg| quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let description = match &args.shard {
+        Some(shard) => format!("Ramon system monitor ({shard})"),
+        None => "Ramon system monitor".to_owned(),
+    };
+    // Ramon stays root so that it can read the journal and watch any file, but the rest of the
+    // system is kept out of reach. `unique`, `changed`, and `fingerprint` state lives in the cache
+    // directory.
+    let unit = format!(
+        "[Unit]
+Description={description}
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart={exec_start}
+Restart=on-failure
+RestartSec=5
+CacheDirectory=ramon
+# Hardening. exec actions run inside this sandbox too, so loosen it if they need to write to
+# /usr, /etc, or home directories, or to gain privileges, e.g. with sudo.
+NoNewPrivileges=true
+ProtectSystem=full
+ProtectHome=read-only
+PrivateTmp=true
+ProtectKernelTunables=true
+ProtectKernelModules=true
+ProtectControlGroups=true
+RestrictSUIDSGID=true
+RestrictRealtime=true
+LockPersonality=true
+
+[Install]
+WantedBy=multi-user.target
+"
+    );
+    Ok(unit)
+}
+
+/// Quotes an argument for `ExecStart=`, escaping the characters systemd would otherwise expand.
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    let needs_quotes = escaped.is_empty()
+        || escaped
+            .chars()
+            .any(|char| char.is_whitespace() || matches!(char, '"' | '\'' | ';'));
+    if !needs_quotes {
+        return escaped;
+    }
+    format!("\"{escaped}\"")
+}
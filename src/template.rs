@@ -0,0 +1,82 @@
+use crate::config::{value_to_string, Escape};
+use std::collections::HashMap;
+use toml::Value;
+
+/// Replaces each `{{name}}` in the template with the value of the variable. Placeholders for
+/// undefined variables are left as is.
+pub fn render(template: &str, variables: &HashMap<String, Value>) -> String {
+    render_with(template, variables, |value| value)
+}
+
+/// Like `render`, but each value is escaped so that it can't inject markup. The template itself
+/// isn't escaped.
+pub fn render_escaped(
+    template: &str,
+    variables: &HashMap<String, Value>,
+    escape: Escape,
+) -> String {
+    match escape {
+        Escape::None => render(template, variables),
+        Escape::Html => render_with(template, variables, |value| {
+            value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;")
+        }),
+        Escape::Slack => render_with(template, variables, |value| {
+            value
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        }),
+    }
+}
+
+/// Like `render`, but each value is quoted so that the shell treats it as a single word.
+pub fn render_shell(template: &str, variables: &HashMap<String, Value>) -> String {
+    render_with(template, variables, |value| shell_quote(&value))
+}
+
+/// Single-quotes the value so that the shell treats it as a single word.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Returns the name of each `{{name}}` in the template.
+pub fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        names.push(rest[start + 2..start + len].trim());
+        rest = &rest[start + len + 2..];
+    }
+    names
+}
+
+fn render_with(
+    template: &str,
+    variables: &HashMap<String, Value>,
+    format: impl Fn(String) -> String,
+) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered += &rest[..start];
+        let placeholder = &rest[start..start + len + 2];
+        let name = placeholder[2..placeholder.len() - 2].trim();
+        match variables.get(name) {
+            Some(value) => rendered += &format(value_to_string(value.clone())),
+            None => rendered += placeholder,
+        }
+        rest = &rest[start + len + 2..];
+    }
+    rendered + rest
+}
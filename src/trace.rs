@@ -0,0 +1,31 @@
+use crate::config::Provenance;
+
+/// Returns a line for each setting in the config, with the file and line it was set on, and
+/// whether it was inherited from `notify.default`, read from the secrets file, or expanded from
+/// `for_each`. Values from the secrets file are hidden.
+pub fn trace(config_name: &str, provenance: &[Provenance]) -> Vec<String> {
+    provenance
+        .iter()
+        .map(|setting| {
+            let value = match setting.secrets_file {
+                Some(_) => "<hidden>".to_owned(),
+                None => setting.value.to_string(),
+            };
+            let mut origin = setting
+                .secrets_file
+                .as_deref()
+                .unwrap_or(config_name)
+                .to_owned();
+            if let Some(line) = setting.line {
+                origin += &format!(":{line}");
+            }
+            if setting.inherited {
+                origin += ", from notify.default";
+            }
+            if let Some(item) = &setting.item {
+                origin += &format!(", for_each item {item:?}");
+            }
+            format!("{} = {value}  # {origin}", setting.path)
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use std::{
+    ffi::{CStr, CString},
+    mem::MaybeUninit,
+    ptr,
+};
+
+/// Looks up a user's ID by name.
+pub fn user_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    with_buffer(|buf| {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: The name is NUL-terminated, the buffer's length is passed with it, and the
+        // pointers outlive the call. `getpwnam_r` is thread-safe, unlike `getpwnam`.
+        let err = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        // SAFETY: A non-null result points to `passwd`, which was filled in.
+        (
+            err,
+            (!result.is_null()).then(|| unsafe { (*result).pw_uid }),
+        )
+    })
+}
+
+/// Looks up a group's ID by name.
+pub fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    with_buffer(|buf| {
+        let mut group = MaybeUninit::<libc::group>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: The name is NUL-terminated, the buffer's length is passed with it, and the
+        // pointers outlive the call. `getgrnam_r` is thread-safe, unlike `getgrnam`.
+        let err = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                group.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        // SAFETY: A non-null result points to `group`, which was filled in.
+        (
+            err,
+            (!result.is_null()).then(|| unsafe { (*result).gr_gid }),
+        )
+    })
+}
+
+/// Looks up a user's name by ID.
+pub fn user_name(uid: u32) -> Option<String> {
+    with_buffer(|buf| {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: The buffer's length is passed with it, and the pointers outlive the call.
+        let err = unsafe {
+            libc::getpwuid_r(
+                uid,
+                passwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        // SAFETY: A non-null result points to `passwd`, whose name is a NUL-terminated string in
+        // the buffer, which is still alive.
+        let name = (!result.is_null()).then(|| {
+            unsafe { CStr::from_ptr((*result).pw_name) }
+                .to_string_lossy()
+                .into_owned()
+        });
+        (err, name)
+    })
+}
+
+/// Looks up a group's name by ID.
+pub fn group_name(gid: u32) -> Option<String> {
+    with_buffer(|buf| {
+        let mut group = MaybeUninit::<libc::group>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: The buffer's length is passed with it, and the pointers outlive the call.
+        let err = unsafe {
+            libc::getgrgid_r(
+                gid,
+                group.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        // SAFETY: A non-null result points to `group`, whose name is a NUL-terminated string in
+        // the buffer, which is still alive.
+        let name = (!result.is_null()).then(|| {
+            unsafe { CStr::from_ptr((*result).gr_name) }
+                .to_string_lossy()
+                .into_owned()
+        });
+        (err, name)
+    })
+}
+
+/// Calls a `get*_r` function with a buffer for its strings, growing the buffer while it's too
+/// small.
+fn with_buffer<T>(
+    mut lookup: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<T>),
+) -> Option<T> {
+    let mut buf = vec![0; 1024];
+    loop {
+        match lookup(&mut buf) {
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (_, result) => return result,
+        }
+    }
+}
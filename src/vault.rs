@@ -0,0 +1,122 @@
+use crate::{config::HttpConfig, notifier};
+use anyhow::{anyhow, bail, Result};
+use reqwest::Client;
+use serde_json::Map;
+use std::{collections::HashMap, env, thread, time::Duration};
+use toml::{Table, Value};
+
+/// Prefix of notify config values that are read from Vault.
+const PREFIX: &str = "vault://";
+
+type Secret = Map<String, serde_json::Value>;
+
+/// Replaces each `vault://<path>#<key>` string in the notify configs with the secret it refers
+/// to. Each path is only fetched once, however many configs refer to it, so values in
+/// `notify.default` should be resolved before they're inherited.
+pub fn resolve<'a>(
+    configs: impl IntoIterator<Item = (&'a str, &'a mut Table)>,
+    http: &HttpConfig,
+) -> Result<()> {
+    let mut refs = Vec::new();
+    for (name, table) in configs {
+        for (config_key, value) in table {
+            let Value::String(string) = &*value else {
+                continue;
+            };
+            let Some(reference) = string.strip_prefix(PREFIX) else {
+                continue;
+            };
+            let setting = format!("notify.{name}.{config_key}");
+            let Some((path, key)) = reference.split_once('#') else {
+                bail!("Key `{setting}` must be of the form `vault://<path>#<key>`.");
+            };
+            refs.push((setting, path.to_owned(), key.to_owned(), value));
+        }
+    }
+    if refs.is_empty() {
+        return Ok(());
+    }
+
+    let mut paths = Vec::new();
+    for (setting, path, key, _) in &refs {
+        if !paths.iter().any(|(other, _)| other == path) {
+            paths.push((
+                path.clone(),
+                format!("`vault://{path}#{key}` for key `{setting}`"),
+            ));
+        }
+    }
+    let secrets = fetch_all(&paths, http)?;
+    for (setting, path, key, value) in refs {
+        let Some(secret) = secrets[&path].get(&key) else {
+            bail!("Vault secret `{path}` has no key `{key}`, which is used by key `{setting}`.");
+        };
+        *value = Value::String(match secret {
+            serde_json::Value::String(secret) => secret.clone(),
+            secret => secret.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Reads each path, which is paired with the reference to name in errors, from the Vault server
+/// at `VAULT_ADDR` with `VAULT_TOKEN`.
+fn fetch_all(paths: &[(String, String)], http: &HttpConfig) -> Result<HashMap<String, Secret>> {
+    let addr = env::var("VAULT_ADDR")
+        .map_err(|_| anyhow!("Environment variable `VAULT_ADDR` must be set."))?;
+    let token = env::var("VAULT_TOKEN")
+        .map_err(|_| anyhow!("Environment variable `VAULT_TOKEN` must be set."))?;
+    // Vault is reached like any webhook, e.g. through `http.proxy`.
+    let client = notifier::http_client(http)?;
+
+    // The config is parsed synchronously, sometimes from within a runtime, so the requests are
+    // made on their own thread and runtime.
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(async {
+                        let mut secrets = HashMap::with_capacity(paths.len());
+                        for (path, reference) in paths {
+                            let secret = fetch(&client, &addr, &token, path)
+                                .await
+                                .map_err(|err| anyhow!("Failed to read {reference}: {err}"))?;
+                            secrets.insert(path.clone(), secret);
+                        }
+                        Ok(secrets)
+                    })
+            })
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("Vault request panicked.")))
+    })
+}
+
+/// Reads the secret at the path. Both KV version 1 and 2 engines are supported.
+async fn fetch(client: &Client, addr: &str, token: &str, path: &str) -> Result<Secret> {
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    let res = client
+        .get(&url)
+        .header("X-Vault-Token", token)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<serde_json::Value>()
+        .await?;
+
+    // KV version 2 nests the secret in another `data` object next to its `metadata`.
+    let Some(serde_json::Value::Object(data)) = res.get("data") else {
+        bail!("Vault's response has no `data` object.");
+    };
+    let data = match (data.get("data"), data.get("metadata")) {
+        (Some(serde_json::Value::Object(secret)), Some(_)) => secret.clone(),
+        _ => data.clone(),
+    };
+    Ok(data)
+}